@@ -0,0 +1,78 @@
+//! Times building the graph of a single project's task, reading the project
+//! files lazily with `build_task_graph_for`, against reading every project
+//! with `build_task_graph`, which also adds the target of every project.
+//!
+//! Run with `cargo bench --bench lazy_loading`.
+#![allow(clippy::needless_return)]
+use std::time::{Duration, Instant};
+
+use nx_rs_lib::project::types::Workspace;
+
+const PROJECTS: usize = 2000;
+const RUNS: u32 = 10;
+
+fn main() {
+    let root = std::env::temp_dir().join(format!("nx-rs-bench-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+
+    let mut entries = vec![];
+    for i in 0..PROJECTS {
+        let dir = root.join(format!("p{i}"));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("project.json"),
+            format!(
+                r#"{{
+                    "name": "p{i}",
+                    "description": "",
+                    "owners": [],
+                    "affects_tags": [],
+                    "affected_by_tags": [],
+                    "targets": {{
+                        "test": {{ "command": ["true"], "depends_on": ["build"] }},
+                        "build": {{ "command": ["true"] }}
+                    }}
+                }}"#
+            ),
+        )
+        .unwrap();
+        entries.push(format!(r#""p{i}": "p{i}/project.json""#));
+    }
+    let json = format!(
+        r#"{{
+            "name": "bench",
+            "app_version": "0.1.0",
+            "projects": {{ {} }},
+            "tags": [],
+            "maintainers": [],
+            "repository": "",
+            "required_targets": []
+        }}"#,
+        entries.join(", ")
+    );
+
+    // The project paths are relative to the workspace root
+    std::env::set_current_dir(&root).unwrap();
+    let ws = Workspace::read_reader(json.as_bytes()).unwrap();
+    let task = vec!["p0:test".to_string()];
+
+    let time = |build: &dyn Fn()| -> Duration {
+        let start = Instant::now();
+        for _ in 0..RUNS {
+            build();
+        }
+        return start.elapsed() / RUNS;
+    };
+    let eager = time(&|| {
+        ws.build_task_graph("test", None).unwrap();
+    });
+    let lazy = time(&|| {
+        ws.build_task_graph_for(&task, None).unwrap();
+    });
+    println!("{PROJECTS} projects, one task:");
+    println!("  build_task_graph:     {eager:?}");
+    println!("  build_task_graph_for: {lazy:?}");
+
+    std::env::set_current_dir(std::env::temp_dir()).unwrap();
+    std::fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,320 @@
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+use super::types::TaskID;
+
+/// Where the results of successful tasks are remembered, by cache key.
+/// See `Task::cache_key`.
+///
+/// Keys are stored in the namespace of the task's cache scope, if it has one,
+/// see `Task::with_cache_scope`. Tasks of a scope still have their own keys,
+/// a task is only a hit if its own key is cached, but invalidating the scope
+/// discards the keys of all its tasks at once.
+pub trait CacheBackend: Send + Sync {
+    /// Whether a successful run is cached under the key.
+    /// # Arguments
+    /// * `scope` - The namespace of the key, `None` for the unscoped keys
+    /// * `key` - The key of the task
+    fn contains(&self, scope: Option<&str>, key: &str) -> Result<bool>;
+
+    /// Remembers a successful run under the key.
+    /// # Arguments
+    /// * `scope` - The namespace of the key, `None` for the unscoped keys
+    /// * `key` - The key of the task
+    fn store(&self, scope: Option<&str>, key: &str) -> Result<()>;
+
+    /// Discards every key of a scope, so all its tasks run again.
+    fn invalidate_scope(&self, scope: &str) -> Result<()>;
+
+    /// Saves the outputs of a successful run under the key, so a hit can
+    /// restore them. Called before `store`. Does nothing by default, for
+    /// backends only remembering keys.
+    /// # Arguments
+    /// * `scope` - The namespace of the key, `None` for the unscoped keys
+    /// * `key` - The key of the task
+    /// * `outputs` - The outputs of the task, relative to `root`
+    /// * `root` - The directory the outputs are relative to
+    fn store_outputs(
+        &self,
+        _scope: Option<&str>,
+        _key: &str,
+        _outputs: &[PathBuf],
+        _root: &Path,
+    ) -> Result<()> {
+        return Ok(());
+    }
+
+    /// Puts the outputs saved under the key back in the working tree, on a
+    /// hit, so the dependents see them. By default the outputs are left as
+    /// they are.
+    /// # Arguments
+    /// * `scope` - The namespace of the key, `None` for the unscoped keys
+    /// * `key` - The key of the task
+    /// * `outputs` - The outputs of the task, relative to `root`
+    /// * `root` - The directory the outputs are restored relative to
+    ///
+    /// # Returns
+    /// * `bool` - Whether the outputs were restored. `false` if the outputs
+    ///   saved are not the ones given, e.g. because the task's outputs changed
+    ///   since, in which case the task is run again
+    fn restore_outputs(
+        &self,
+        _scope: Option<&str>,
+        _key: &str,
+        _outputs: &[PathBuf],
+        _root: &Path,
+    ) -> Result<bool> {
+        return Ok(true);
+    }
+}
+
+/// A cache in a local directory, with a file per cached key.
+/// Scoped keys are in a subdirectory named after their scope. The outputs of
+/// a key are copied to a `<key>.outputs` directory next to it.
+pub struct LocalCache {
+    dir: PathBuf,
+}
+
+impl LocalCache {
+    /// # Arguments
+    /// * `dir` - The cache directory, created on the first store
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        LocalCache { dir: dir.into() }
+    }
+
+    /// The directory of the keys of a scope.
+    /// Fails if the scope isn't a single directory name, which could point
+    /// outside of the cache, e.g. `..`.
+    fn scope_dir(&self, scope: Option<&str>) -> Result<PathBuf> {
+        let Some(scope) = scope else {
+            return Ok(self.dir.clone());
+        };
+        if scope.is_empty() || scope == "." || scope == ".." || scope.contains(['/', '\\']) {
+            bail!("invalid cache scope `{scope}`");
+        }
+        return Ok(self.dir.join("scopes").join(scope));
+    }
+
+    /// The directory of the outputs of a key.
+    fn outputs_dir(&self, scope: Option<&str>, key: &str) -> Result<PathBuf> {
+        return Ok(self.scope_dir(scope)?.join(format!("{key}.outputs")));
+    }
+}
+
+/// The outputs saved with a key, so outputs defined differently since don't
+/// restore.
+const OUTPUTS_MANIFEST: &str = "manifest.json";
+
+impl CacheBackend for LocalCache {
+    fn contains(&self, scope: Option<&str>, key: &str) -> Result<bool> {
+        return Ok(self.scope_dir(scope)?.join(key).is_file());
+    }
+
+    fn store(&self, scope: Option<&str>, key: &str) -> Result<()> {
+        let dir = self.scope_dir(scope)?;
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join(key), b"")?;
+        return Ok(());
+    }
+
+    fn invalidate_scope(&self, scope: &str) -> Result<()> {
+        match std::fs::remove_dir_all(self.scope_dir(Some(scope))?) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => return Ok(()),
+        }
+    }
+
+    fn store_outputs(
+        &self,
+        scope: Option<&str>,
+        key: &str,
+        outputs: &[PathBuf],
+        root: &Path,
+    ) -> Result<()> {
+        for output in outputs {
+            // Copied under the outputs directory, which they must not escape
+            let relative = output
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+            if !relative {
+                bail!("output `{}` is not inside the root", output.display());
+            }
+        }
+
+        let dir = self.outputs_dir(scope, key)?;
+        remove_all(&dir)?;
+        std::fs::create_dir_all(&dir)?;
+        for output in outputs {
+            // Outputs the run did not produce are not restored either
+            let path = root.join(output);
+            if path.exists() {
+                copy_all(&path, &dir.join("files").join(output))?;
+            }
+        }
+        std::fs::write(dir.join(OUTPUTS_MANIFEST), serde_json::to_vec(outputs)?)?;
+        return Ok(());
+    }
+
+    fn restore_outputs(
+        &self,
+        scope: Option<&str>,
+        key: &str,
+        outputs: &[PathBuf],
+        root: &Path,
+    ) -> Result<bool> {
+        let dir = self.outputs_dir(scope, key)?;
+        let saved: Vec<PathBuf> = match std::fs::read(dir.join(OUTPUTS_MANIFEST)) {
+            Ok(manifest) => serde_json::from_slice(&manifest)?,
+            // Stored without outputs
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e.into()),
+        };
+        if saved != outputs {
+            return Ok(false);
+        }
+
+        for output in outputs {
+            let stored = dir.join("files").join(output);
+            if stored.exists() {
+                let path = root.join(output);
+                remove_all(&path)?;
+                copy_all(&stored, &path)?;
+            }
+        }
+        return Ok(true);
+    }
+}
+
+/// Copies a file, or a directory and everything in it.
+fn copy_all(from: &Path, to: &Path) -> Result<()> {
+    if from.is_dir() {
+        std::fs::create_dir_all(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            copy_all(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        return Ok(());
+    }
+
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::copy(from, to)?;
+    return Ok(());
+}
+
+/// Removes a file, or a directory and everything in it, if it exists.
+fn remove_all(path: &Path) -> Result<()> {
+    let removed = match path.is_dir() {
+        true => std::fs::remove_dir_all(path),
+        false => std::fs::remove_file(path),
+    };
+    match removed {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => return Ok(()),
+    }
+}
+
+/// Whether a task was found in the cache, with the key it was looked up by.
+/// Comparing the keys of two runs tells whether an input changed in between.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheEvent {
+    /// The task was cached, it is not run
+    Hit { id: TaskID, key: String },
+    /// The task was not cached, it is run
+    Miss { id: TaskID, key: String },
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_local_cache() {
+        let dir = std::env::temp_dir().join(format!("nx-rs-cache-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let cache = LocalCache::new(&dir);
+        assert!(!cache.contains(None, "abc").unwrap());
+        cache.store(None, "abc").unwrap();
+        assert!(cache.contains(None, "abc").unwrap());
+        assert!(!cache.contains(None, "def").unwrap());
+
+        // Scopes are separate namespaces
+        cache.store(Some("app-test"), "def").unwrap();
+        cache.store(Some("app-test"), "ghi").unwrap();
+        cache.store(Some("lib-test"), "def").unwrap();
+        assert!(!cache.contains(None, "def").unwrap());
+        assert!(cache.contains(Some("app-test"), "ghi").unwrap());
+
+        cache.invalidate_scope("app-test").unwrap();
+        assert!(!cache.contains(Some("app-test"), "def").unwrap());
+        assert!(!cache.contains(Some("app-test"), "ghi").unwrap());
+        assert!(cache.contains(Some("lib-test"), "def").unwrap());
+        assert!(cache.contains(None, "abc").unwrap());
+        cache.invalidate_scope("unknown").unwrap();
+
+        // Scopes can't point outside of their directory
+        std::fs::create_dir_all(dir.join("scopes/lib-test/nested")).unwrap();
+        for scope in [
+            "",
+            ".",
+            "..",
+            "../lib-test",
+            "lib-test/nested",
+            "/tmp",
+            "a\\b",
+        ] {
+            assert_eq!(
+                cache.invalidate_scope(scope).unwrap_err().to_string(),
+                format!("invalid cache scope `{scope}`")
+            );
+            assert!(cache.store(Some(scope), "def").is_err());
+            assert!(cache.contains(Some(scope), "def").is_err());
+        }
+        assert!(cache.contains(Some("lib-test"), "def").unwrap());
+        assert!(dir.join("scopes/lib-test/nested").is_dir());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_local_cache_outputs() {
+        let dir = std::env::temp_dir().join(format!("nx-rs-cache-outputs-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let root = dir.join("root");
+        std::fs::create_dir_all(root.join("dist/assets")).unwrap();
+        std::fs::write(root.join("dist/app.js"), "app").unwrap();
+        std::fs::write(root.join("dist/assets/logo.svg"), "logo").unwrap();
+
+        let cache = LocalCache::new(dir.join("cache"));
+        let outputs = vec![PathBuf::from("dist"), PathBuf::from("missing.txt")];
+        cache.store_outputs(None, "abc", &outputs, &root).unwrap();
+
+        std::fs::remove_dir_all(root.join("dist")).unwrap();
+        assert!(cache.restore_outputs(None, "abc", &outputs, &root).unwrap());
+        assert_eq!(
+            std::fs::read_to_string(root.join("dist/app.js")).unwrap(),
+            "app"
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.join("dist/assets/logo.svg")).unwrap(),
+            "logo"
+        );
+        assert!(!root.join("missing.txt").exists());
+
+        // The outputs changed since the key was stored
+        let outputs = vec![PathBuf::from("dist")];
+        assert!(!cache.restore_outputs(None, "abc", &outputs, &root).unwrap());
+        assert!(!cache.restore_outputs(None, "def", &outputs, &root).unwrap());
+        assert!(cache.restore_outputs(None, "def", &[], &root).unwrap());
+
+        assert!(cache
+            .store_outputs(None, "abc", &[PathBuf::from("../escape")], &root)
+            .is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
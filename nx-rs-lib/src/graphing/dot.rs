@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use super::types::{DependencyKind, TaskGraph, TaskStatus};
+
+/// How `TaskGraph::to_dot` renders a graph.
+#[derive(Debug, Clone)]
+pub struct DotStyle {
+    /// The fill color of the tasks in each status, as a DOT color, e.g.
+    /// `palegreen` or `#90ee90`. Tasks in a status without a color are not
+    /// filled
+    pub status_colors: HashMap<TaskStatus, String>,
+    /// Whether edges are labeled with their kind, `hard` or `soft`
+    pub edge_labels: bool,
+    /// The DOT style of soft edges, e.g. `dashed`
+    pub soft_edge_style: String,
+}
+
+impl Default for DotStyle {
+    fn default() -> Self {
+        let status_colors = [
+            (TaskStatus::Pending, "white"),
+            (TaskStatus::Running, "lightblue"),
+            (TaskStatus::Succeeded, "palegreen"),
+            (TaskStatus::Failed, "salmon"),
+            (TaskStatus::Skipped, "lightgrey"),
+        ]
+        .into_iter()
+        .map(|(status, color)| (status, color.to_string()))
+        .collect();
+
+        DotStyle {
+            status_colors,
+            edge_labels: true,
+            soft_edge_style: "dashed".to_string(),
+        }
+    }
+}
+
+impl TaskGraph {
+    /// Renders the graph in the DOT format of Graphviz, e.g. to see how far a
+    /// run got. Edges go from a task to its dependencies, and the tasks are
+    /// filled with the color of their status.
+    /// The output is sorted, so the same graph always renders the same.
+    /// # Arguments
+    /// * `style` - How the graph is rendered, see `DotStyle::default`
+    pub fn to_dot(&self, style: &DotStyle) -> String {
+        let statuses = self.statuses();
+        let mut task_ids = statuses.keys().collect::<Vec<_>>();
+        task_ids.sort();
+
+        let mut dot = String::from("digraph tasks {\n");
+        for task_id in &task_ids {
+            let status = statuses[*task_id];
+            match style.status_colors.get(&status) {
+                Some(color) => dot.push_str(&format!(
+                    "    {} [style=filled, fillcolor={}];\n",
+                    quote(task_id.as_str()),
+                    quote(color)
+                )),
+                None => dot.push_str(&format!("    {};\n", quote(task_id.as_str()))),
+            }
+        }
+
+        for task_id in &task_ids {
+            let mut deps = self.dependencies(task_id);
+            deps.sort();
+
+            for dep in deps {
+                let mut attrs: Vec<String> = vec![];
+                let kind = self.dependency_kind(task_id, &dep);
+                if style.edge_labels {
+                    let label = match kind {
+                        Some(DependencyKind::Soft) => "soft",
+                        _ => "hard",
+                    };
+                    attrs.push(format!("label={}", quote(label)));
+                }
+                if kind == Some(DependencyKind::Soft) {
+                    attrs.push(format!("style={}", quote(&style.soft_edge_style)));
+                }
+
+                dot.push_str(&format!("    {} -> {}", quote(task_id), quote(&dep)));
+                if !attrs.is_empty() {
+                    dot.push_str(&format!(" [{}]", attrs.join(", ")));
+                }
+                dot.push_str(";\n");
+            }
+        }
+
+        dot.push_str("}\n");
+        return dot;
+    }
+}
+
+/// Quotes a DOT ID, escaping the characters that would end it.
+fn quote(id: &str) -> String {
+    return format!("\"{}\"", id.replace('\\', "\\\\").replace('"', "\\\""));
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::graphing::types::{Action, Task, TaskGraphBuilder};
+
+    fn task(id: &str) -> Task {
+        Task::new(id, id, Action::shell(vec!["true".to_string()]))
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let mut builder = TaskGraphBuilder::new();
+        for id in ["app", "lib", "docs \"v2\""] {
+            builder.add_task(task(id));
+        }
+        builder.add_dependency("app".to_string(), "lib".to_string());
+        builder.add_soft_dependency("docs \"v2\"".to_string(), "lib".to_string());
+        let mut graph = builder.build().unwrap();
+        graph.fail(&"lib".to_string());
+
+        assert_eq!(
+            graph.to_dot(&DotStyle::default()),
+            r#"digraph tasks {
+    "app" [style=filled, fillcolor="lightgrey"];
+    "docs \"v2\"" [style=filled, fillcolor="white"];
+    "lib" [style=filled, fillcolor="salmon"];
+    "app" -> "lib" [label="hard"];
+    "docs \"v2\"" -> "lib" [label="soft", style="dashed"];
+}
+"#
+        );
+
+        let style = DotStyle {
+            status_colors: HashMap::new(),
+            edge_labels: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            graph.to_dot(&style),
+            r#"digraph tasks {
+    "app";
+    "docs \"v2\"";
+    "lib";
+    "app" -> "lib";
+    "docs \"v2\"" -> "lib" [style="dashed"];
+}
+"#
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use std::{fs::read_to_string, path::Path};
+
+use anyhow::{bail, Result};
+
+/// Reads and parses a dotenv file.
+/// See `parse` for the supported syntax.
+pub fn read(path: &Path) -> Result<Vec<(String, String)>> {
+    return parse(&read_to_string(path)?);
+}
+
+/// Parses the content of a dotenv file into its variables, in file order.
+/// Supports:
+/// * `KEY=VALUE` lines, optionally starting with `export `
+/// * Blank lines and `#` comments, including after unquoted values
+/// * Single quoted values, taken literally
+/// * Double quoted values, with `\n`, `\t`, `\"` and `\\` escapes
+pub fn parse(src: &str) -> Result<Vec<(String, String)>> {
+    let mut vars: Vec<(String, String)> = vec![];
+
+    for (i, line) in src.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            bail!("line {}: expected `KEY=VALUE`", i + 1);
+        };
+
+        let key = key.trim();
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            bail!("line {}: invalid variable name `{}`", i + 1, key);
+        }
+
+        vars.push((key.to_string(), parse_value(value.trim(), i + 1)?));
+    }
+
+    return Ok(vars);
+}
+
+fn parse_value(value: &str, line: usize) -> Result<String> {
+    if let Some(rest) = value.strip_prefix('\'') {
+        let Some(end) = rest.find('\'') else {
+            bail!("line {}: unterminated single quote", line);
+        };
+        return Ok(rest[..end].to_string());
+    }
+
+    if let Some(rest) = value.strip_prefix('"') {
+        let mut parsed = String::new();
+        let mut chars = rest.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => return Ok(parsed),
+                '\\' => match chars.next() {
+                    Some('n') => parsed.push('\n'),
+                    Some('t') => parsed.push('\t'),
+                    Some(c) => parsed.push(c),
+                    None => break,
+                },
+                c => parsed.push(c),
+            }
+        }
+        bail!("line {}: unterminated double quote", line);
+    }
+
+    // Unquoted, a comment starts at a `#` preceded by whitespace
+    let end = value.find(" #").unwrap_or(value.len());
+    return Ok(value[..end].trim_end().to_string());
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let src = r#"
+# A comment
+PLAIN=value
+export EXPORTED=yes
+SPACED = padded  # trailing comment
+SINGLE='no \n escapes # here'
+DOUBLE="line\nbreak \"quoted\""
+EMPTY=
+"#;
+
+        assert_eq!(
+            parse(src).unwrap(),
+            vec![
+                ("PLAIN".to_string(), "value".to_string()),
+                ("EXPORTED".to_string(), "yes".to_string()),
+                ("SPACED".to_string(), "padded".to_string()),
+                ("SINGLE".to_string(), "no \\n escapes # here".to_string()),
+                ("DOUBLE".to_string(), "line\nbreak \"quoted\"".to_string()),
+                ("EMPTY".to_string(), "".to_string()),
+            ]
+        );
+
+        assert!(parse("NO_EQUALS").is_err());
+        assert!(parse("A='open").is_err());
+    }
+}
@@ -0,0 +1,2321 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet, VecDeque},
+    ffi::OsString,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use shared_child::SharedChild;
+
+use super::cache::{CacheBackend, CacheEvent};
+use super::report::{RunEvent, TaskReport, TerminationReason, TIMED_OUT};
+use super::runner::{exit_status, ProcessRunner};
+use super::types::{Action, Task, TaskGraph, TaskID, TaskStatus};
+
+/// Runs tasks in-process instead of spawning their command, e.g. a Rust
+/// native file copier. Registered by name with `Executor::register_executor`
+/// and picked by a task's `executor`.
+pub trait TaskExecutor: Send + Sync {
+    /// Runs a task.
+    /// # Returns
+    /// * `ExitStatus` - The outcome of the task, checked against the task's
+    ///   success codes like the exit status of a process
+    fn execute(&self, ctx: &TaskContext) -> Result<ExitStatus>;
+}
+
+/// What a `TaskExecutor` gets to run a task.
+#[derive(Debug, Clone)]
+pub struct TaskContext {
+    pub task_id: TaskID,
+    /// The command of the task's action, taken as the executor's arguments
+    pub args: Vec<String>,
+    /// The task's variables, see `Task::resolved_env`
+    pub env: HashMap<String, String>,
+    pub profile: Option<String>,
+}
+
+impl TaskContext {
+    pub fn new(task: &Task) -> Result<Self> {
+        let args = match task.action() {
+            Action::Shell { .. } | Action::PlatformShell { .. } | Action::Container { .. } => {
+                task.action().cmd()?.to_vec()
+            }
+            Action::WithCleanup { .. } => {
+                bail!("custom executors can't run actions with a cleanup")
+            }
+        };
+        return Ok(TaskContext {
+            task_id: task.id().clone(),
+            args,
+            env: task.resolved_env()?,
+            profile: task.profile().map(str::to_string),
+        });
+    }
+}
+
+/// The environment a whole run sits on, e.g. for hermetic builds. When set on
+/// the executor, the processes don't inherit the runner's environment, every
+/// task gets exactly the variables of the run, whatever its
+/// `Task::with_inherit_env`.
+///
+/// The variables of a task are resolved in three layers, each overriding the
+/// one before it:
+/// 1. The base variables of the run, e.g. a prepared snapshot
+/// 2. The workspace's defaults, see `Workspace::run_env`
+/// 3. The task's own variables, see `Task::resolved_env`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunEnv {
+    base: HashMap<String, String>,
+    defaults: HashMap<String, String>,
+}
+
+impl RunEnv {
+    /// # Arguments
+    /// * `base` - The base variables of the run
+    pub fn new(base: HashMap<String, String>) -> Self {
+        RunEnv {
+            base,
+            defaults: HashMap::new(),
+        }
+    }
+
+    /// Set the workspace's default variables, overriding the base ones.
+    pub fn with_defaults(mut self, defaults: HashMap<String, String>) -> Self {
+        self.defaults = defaults;
+        self
+    }
+
+    /// Returns a variable of the run, the default if there is one, the base
+    /// one otherwise. The tasks' own variables are not looked at.
+    pub fn var(&self, name: &str) -> Option<&str> {
+        return self
+            .defaults
+            .get(name)
+            .or_else(|| self.base.get(name))
+            .map(String::as_str);
+    }
+
+    /// Returns the variables of a task: the base ones, overridden by the
+    /// defaults, overridden by the task's own.
+    pub fn resolve(&self, task: &Task) -> Result<HashMap<String, String>> {
+        let mut env = self.base.clone();
+        env.extend(self.defaults.clone());
+        env.extend(task.resolved_env()?);
+        return Ok(env);
+    }
+}
+
+/// The name the built-in `Shell` executor is registered under.
+pub const SHELL_EXECUTOR: &str = "shell";
+
+/// The built-in executor, running the arguments as a process with the
+/// runner's stdout and stderr. Registered by default as `SHELL_EXECUTOR`.
+/// Tasks without an executor are spawned the same way, but with the output
+/// handling configured on the `Executor`.
+pub struct Shell;
+
+impl TaskExecutor for Shell {
+    fn execute(&self, ctx: &TaskContext) -> Result<ExitStatus> {
+        let Some((program, args)) = ctx.args.split_first() else {
+            bail!("task `{}` has an empty command", ctx.task_id);
+        };
+        return Ok(Command::new(program).args(args).envs(&ctx.env).status()?);
+    }
+}
+
+/// The name the built-in `Noop` executor is registered under.
+pub const NOOP_EXECUTOR: &str = "noop";
+
+/// The built-in executor of tasks with nothing to do, e.g. targets grouping
+/// others. Succeeds without running anything. Registered by default as
+/// `NOOP_EXECUTOR`.
+pub struct Noop;
+
+impl TaskExecutor for Noop {
+    fn execute(&self, _ctx: &TaskContext) -> Result<ExitStatus> {
+        return Ok(exit_status(0));
+    }
+}
+
+/// A writer shared between the threads of a run.
+pub type SharedWriter = Arc<Mutex<Box<dyn Write + Send>>>;
+
+/// How the output of the tasks' processes is handled.
+#[derive(Clone)]
+pub enum OutputMode {
+    /// The processes write directly to the runner's stdout and stderr
+    Inherit,
+    /// Every line the processes write is prefixed, then written to the shared
+    /// writer. Lines are written whole, so concurrent tasks do not interleave
+    /// mid-line.
+    Prefixed {
+        /// The prefix format, see `Executor::prefix_output`
+        format: String,
+        writer: SharedWriter,
+    },
+    /// The stdout and stderr of each task are collected into its report
+    Capture,
+    /// Only the last lines of each task are kept, readable while it runs,
+    /// see `Executor::tail`
+    Tail {
+        /// The most lines kept per task
+        lines: usize,
+    },
+}
+
+/// The default prefix of the variables skipping tasks, see
+/// `Executor::skip_env_prefix`.
+pub const DEFAULT_SKIP_ENV_PREFIX: &str = "SKIP_";
+
+/// Called for every cache lookup of a run, from the task's thread.
+pub type CacheCallback = Arc<dyn Fn(&CacheEvent) + Send + Sync>;
+
+/// Runs the tasks of a graph in parallel, respecting their dependencies.
+pub struct Executor {
+    /// The maximum number of tasks running at once
+    concurrency: usize,
+    /// The maximum number of tasks of each pool running at once
+    pools: HashMap<String, usize>,
+    output: OutputMode,
+    /// The most captured output kept per task, in bytes
+    max_output_bytes: Option<usize>,
+    /// Orders the ready tasks, the first ones are started first
+    priority: Option<fn(&Task, &Task) -> Ordering>,
+    /// The processes of the running tasks, shared with the `TaskHandle`s
+    children: Arc<Children>,
+    /// The custom executors, by name
+    executors: HashMap<String, Arc<dyn TaskExecutor>>,
+    /// The cache, and the directory the inputs of the tasks are relative to
+    cache: Option<(Arc<dyn CacheBackend>, PathBuf)>,
+    on_cache_event: Option<CacheCallback>,
+    /// Runs the processes instead of spawning them, if set
+    runner: Option<Arc<dyn ProcessRunner>>,
+    /// The environment of the run, instead of the runner's
+    env: Option<RunEnv>,
+    /// Where the lifecycle events of the tasks are streamed, as JSON Lines
+    events: Option<SharedWriter>,
+    /// Where the reason of each task is written when it starts
+    verbose: Option<SharedWriter>,
+    /// The last lines of each task, in `OutputMode::Tail`
+    tails: Mutex<HashMap<TaskID, Arc<Mutex<TailBuffer>>>>,
+    /// The most time a whole run may take
+    total_timeout: Option<Duration>,
+    /// The prefix of the variables skipping tasks
+    skip_env_prefix: Option<String>,
+}
+
+impl Executor {
+    pub fn new(concurrency: usize) -> Self {
+        Executor {
+            concurrency: concurrency.max(1),
+            pools: HashMap::new(),
+            output: OutputMode::Inherit,
+            max_output_bytes: None,
+            priority: None,
+            children: Arc::default(),
+            executors: HashMap::from([
+                (
+                    SHELL_EXECUTOR.to_string(),
+                    Arc::new(Shell) as Arc<dyn TaskExecutor>,
+                ),
+                (NOOP_EXECUTOR.to_string(), Arc::new(Noop)),
+            ]),
+            cache: None,
+            on_cache_event: None,
+            runner: None,
+            env: None,
+            events: None,
+            verbose: None,
+            tails: Mutex::default(),
+            total_timeout: None,
+            skip_env_prefix: Some(DEFAULT_SKIP_ENV_PREFIX.to_string()),
+        }
+    }
+
+    /// Limit how many tasks of a pool run at once, on top of the global
+    /// concurrency. Tasks in several pools need a free slot in each of them.
+    /// Pools without a limit are unlimited.
+    /// # Arguments
+    /// * `pool` - The name of the pool, see `Task::with_pools`
+    /// * `limit` - The maximum number of tasks of the pool running at once
+    pub fn pool_limit(&mut self, pool: &str, limit: usize) {
+        self.pools.insert(pool.to_string(), limit.max(1));
+    }
+
+    /// Register a custom executor, run for the tasks whose `executor` is the
+    /// given name. Replaces any executor registered with the same name,
+    /// including the built-in `SHELL_EXECUTOR` and `NOOP_EXECUTOR`.
+    /// # Arguments
+    /// * `name` - The name tasks refer to the executor by
+    /// * `executor` - The executor
+    pub fn register_executor(&mut self, name: &str, executor: impl TaskExecutor + 'static) {
+        self.executors.insert(name.to_string(), Arc::new(executor));
+    }
+
+    /// Skip the tasks whose results are cached, and cache the results of the
+    /// tasks that succeed.
+    /// # Arguments
+    /// * `root` - The directory the input globs are relative to, see
+    ///   `Task::cache_key`
+    /// * `backend` - Where the results are cached
+    pub fn enable_cache(&mut self, root: &Path, backend: impl CacheBackend + 'static) {
+        self.cache = Some((Arc::new(backend), root.to_path_buf()));
+    }
+
+    /// Be told whether each task was found in the cache, e.g. to compute hit
+    /// rates or find out why a task is not cached. Does nothing unless the
+    /// cache is enabled.
+    /// # Arguments
+    /// * `callback` - Called with every hit and miss
+    pub fn on_cache_event(&mut self, callback: impl Fn(&CacheEvent) + Send + Sync + 'static) {
+        self.on_cache_event = Some(Arc::new(callback));
+    }
+
+    /// Run the processes of the tasks with a runner instead of spawning them,
+    /// e.g. a `MockRunner` in tests. Their output is left to the runner, the
+    /// output mode is ignored.
+    pub fn process_runner(&mut self, runner: Arc<dyn ProcessRunner>) {
+        self.runner = Some(runner);
+    }
+
+    /// Run every task in the given environment instead of the runner's, see
+    /// `RunEnv`. Custom executors get the resolved variables in their
+    /// context.
+    pub fn run_env(&mut self, env: RunEnv) {
+        self.env = Some(env);
+    }
+
+    /// Stream the lifecycle events of the tasks as they happen, one JSON
+    /// object per line, e.g. for CI integrations tailing the logs. See
+    /// `RunEvent`. Each event is written whole and flushed.
+    /// # Arguments
+    /// * `writer` - Where the events are written
+    pub fn emit_jsonl(&mut self, writer: impl Write + Send + 'static) {
+        self.events = Some(Arc::new(Mutex::new(Box::new(writer))));
+    }
+
+    /// Explain why each task runs when it starts, one line per task, e.g.
+    /// `lib:build - dependency of app:build`. See `TaskGraph::reason`.
+    /// # Arguments
+    /// * `writer` - Where the lines are written
+    pub fn verbose(&mut self, writer: impl Write + Send + 'static) {
+        self.verbose = Some(Arc::new(Mutex::new(Box::new(writer))));
+    }
+
+    /// Prefix every output line with its task, e.g. `[app:build] compiling...`.
+    /// # Arguments
+    /// * `format` - The prefix format. `{id}` is replaced by the task ID and
+    ///   `{label}` by its action's label, or command if it has none. Without
+    ///   `{label}` in the format, the label of an action having one is shown
+    ///   after its ID, e.g. `[app:bundle (bundle app)]` for `[{id}]`
+    /// * `writer` - Where the prefixed lines of all the tasks are written
+    pub fn prefix_output(&mut self, format: &str, writer: impl Write + Send + 'static) {
+        self.output = OutputMode::Prefixed {
+            format: format.to_string(),
+            writer: Arc::new(Mutex::new(Box::new(writer))),
+        };
+    }
+
+    /// Collect the output of each task into its report instead of printing it.
+    pub fn capture_output(&mut self) {
+        self.output = OutputMode::Capture;
+    }
+
+    /// Keep only the last lines of each task's output, e.g. for a terminal UI
+    /// showing what the running tasks print, without holding all of it in
+    /// memory. The lines are read with `tail`.
+    /// # Arguments
+    /// * `lines` - The most lines kept per task
+    pub fn tail_output(&mut self, lines: usize) {
+        self.output = OutputMode::Tail {
+            lines: lines.max(1),
+        };
+    }
+
+    /// Returns the last lines the task printed, oldest first, while it runs
+    /// or after it ran. Empty unless the output mode is `Tail`, or if the task
+    /// hasn't printed anything yet.
+    /// # Arguments
+    /// * `id` - The ID of the task
+    pub fn tail(&self, id: &TaskID) -> Vec<String> {
+        return match self.tails.lock().unwrap().get(id) {
+            Some(buffer) => buffer.lock().unwrap().lines.iter().cloned().collect(),
+            None => vec![],
+        };
+    }
+
+    /// Limit how much output is captured for each task.
+    /// Output past the limit is dropped and replaced by a truncation marker,
+    /// but the process keeps running until it exits. Does not affect output
+    /// that isn't captured.
+    /// # Arguments
+    /// * `limit` - The maximum number of bytes kept, `None` for no limit
+    pub fn max_output_bytes(&mut self, limit: Option<usize>) {
+        self.max_output_bytes = limit;
+    }
+
+    /// Limit how long a whole run may take, on top of the tasks' own
+    /// timeouts, e.g. to keep a runaway pipeline within the CI budget.
+    /// Once the limit is reached, no more tasks are started and the running
+    /// ones are cancelled. Their reports, and those of the tasks left pending,
+    /// are `TaskReport::timed_out` with `TIMED_OUT` as error, see
+    /// `RunResult::timed_out`.
+    /// # Arguments
+    /// * `limit` - The most time a run may take, `None` for no limit
+    pub fn total_timeout(&mut self, limit: Option<Duration>) {
+        self.total_timeout = limit;
+    }
+
+    /// Set the prefix of the environment variables skipping tasks, e.g. to
+    /// skip a slow test locally with `SKIP_app_test=1`. The variable of a
+    /// task is the prefix followed by its ID, with every character other
+    /// than ASCII letters, digits and `_` replaced by `_`, so `app:test`
+    /// becomes `SKIP_app_test`. IDs differing only by those characters share
+    /// their variable, e.g. `app:test` and `app-test`, so it skips both.
+    /// Set to anything but an empty string or `0`, it skips the task like a
+    /// failure would, skipping its dependents too. The variables are read
+    /// from the `RunEnv` when there is one, see `run_env`, and from the
+    /// runner's environment otherwise.
+    /// Enabled by default with `DEFAULT_SKIP_ENV_PREFIX`.
+    /// # Arguments
+    /// * `prefix` - The prefix, `None` to never skip tasks
+    pub fn skip_env_prefix(&mut self, prefix: Option<&str>) {
+        self.skip_env_prefix = prefix.map(str::to_string);
+    }
+
+    /// Set how tasks that are ready at the same time are prioritized.
+    /// When there are more ready tasks than free slots, the ones sorted first
+    /// are started first. Ties keep the topological order, which is also the
+    /// order used when no priority is set.
+    /// # Arguments
+    /// * `cmp` - Compares two ready tasks
+    pub fn priority(&mut self, cmp: fn(&Task, &Task) -> Ordering) {
+        self.priority = Some(cmp);
+    }
+
+    /// Returns a handle to cancel a task of the runs, e.g. from another thread
+    /// while the graph runs. See `TaskHandle::cancel`.
+    /// # Arguments
+    /// * `id` - The ID of the task
+    pub fn task_handle(&self, id: &TaskID) -> TaskHandle {
+        return TaskHandle {
+            id: id.clone(),
+            children: self.children.clone(),
+        };
+    }
+
+    /// Run all the tasks of the graph.
+    /// Tasks that fail, or can't be started, are marked as failed in the graph
+    /// and their dependents are skipped.
+    /// # Arguments
+    /// * `graph` - The graph to run
+    /// # Returns
+    /// * `Vec<TaskReport>` - The report of every task in the graph, in the
+    ///   order they finished, followed by the tasks that never ran
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(tasks = graph.statuses().len()))
+    )]
+    pub fn run(&self, graph: &mut TaskGraph) -> Vec<TaskReport> {
+        let (tx, rx) = mpsc::channel::<TaskReport>();
+        let mut reports: Vec<TaskReport> = vec![];
+        let deadline = self.total_timeout.map(|limit| Instant::now() + limit);
+        let mut timed_out = false;
+        // The tasks cancelled because the run timed out
+        let mut timed_out_ids: HashSet<TaskID> = HashSet::new();
+
+        thread::scope(|scope| {
+            // Dropped before the scope waits for the task threads. If the loop
+            // panics, the processes are killed instead of being waited for, or
+            // kept running once the runner exits
+            let _guard = KillOnDrop(&self.children);
+
+            let mut running: HashMap<TaskID, Vec<String>> = HashMap::new();
+            let mut pool_usage: HashMap<String, usize> = HashMap::new();
+            loop {
+                // Queued reports don't hold the deadline off
+                if !timed_out && deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(running = running.len(), "the run timed out");
+                    timed_out = true;
+                    for id in running.keys() {
+                        self.children.cancel(id);
+                        timed_out_ids.insert(id.clone());
+                    }
+                }
+
+                // Start as many ready tasks as allowed
+                let mut ready = graph.ready_tasks();
+                if timed_out {
+                    ready.clear();
+                }
+                // Skipping a task may make its soft dependents ready
+                let mut skipped = false;
+                ready.retain(|task| {
+                    let Some(var) = self.skip_var(task.id()) else {
+                        return true;
+                    };
+                    #[cfg(feature = "tracing")]
+                    tracing::info!(task_id = %task.id(), %var, "task skipped");
+                    graph.skip(task.id());
+                    let mut report = TaskReport::not_run(task.id(), TaskStatus::Skipped);
+                    report.error = Some(format!("skipped by `{var}`"));
+                    reports.push(report);
+                    skipped = true;
+                    return false;
+                });
+                if let Some(cmp) = self.priority {
+                    ready.sort_by(cmp);
+                }
+
+                for task in ready {
+                    if running.len() == self.concurrency {
+                        break;
+                    }
+                    // Left for later, tasks of other pools may still start
+                    let pool_full = |pool: &String| {
+                        self.pools.get(pool).is_some_and(|limit| {
+                            pool_usage.get(pool).copied().unwrap_or_default() >= *limit
+                        })
+                    };
+                    if task.pools().iter().any(pool_full) {
+                        continue;
+                    }
+
+                    graph.start(task.id());
+                    if let Some(reason) = graph.reason(task.id()) {
+                        #[cfg(feature = "tracing")]
+                        tracing::debug!(task_id = %task.id(), %reason, "task started");
+                        if let Some(verbose) = &self.verbose {
+                            // Informational, like the events
+                            let _ = writeln!(verbose.lock().unwrap(), "{} - {reason}", task.id());
+                        }
+                    }
+                    self.emit(&RunEvent::started(task.id()));
+                    // The lines of a previous run are stale
+                    self.tails.lock().unwrap().remove(task.id());
+                    for pool in task.pools() {
+                        *pool_usage.entry(pool.clone()).or_default() += 1;
+                    }
+                    running.insert(task.id().clone(), task.pools().to_vec());
+
+                    let tx = tx.clone();
+                    scope.spawn(move || {
+                        // The receiver outlives every task thread
+                        tx.send(self.run_task(&task)).unwrap();
+                    });
+                }
+
+                // Nothing is running, so nothing else can become ready
+                if running.is_empty() {
+                    if skipped {
+                        continue;
+                    }
+                    break;
+                }
+
+                let received = match deadline {
+                    Some(deadline) if !timed_out => {
+                        rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    }
+                    _ => rx.recv().map_err(RecvTimeoutError::from),
+                };
+                let mut report = match received {
+                    Ok(report) => report,
+                    // Handled at the top of the loop
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => panic!("task threads hold a sender"),
+                };
+                if timed_out_ids.contains(&report.id) && report.status != TaskStatus::Succeeded {
+                    report.error = Some(TIMED_OUT.to_string());
+                    report.timed_out = true;
+                }
+                #[cfg(feature = "tracing")]
+                tracing::info!(
+                    task_id = %report.id,
+                    status = ?report.status,
+                    duration_ms = report.duration.as_millis() as u64,
+                    attempts = report.attempts,
+                    "task finished"
+                );
+                for pool in running.remove(&report.id).unwrap_or_default() {
+                    *pool_usage.entry(pool).or_default() -= 1;
+                }
+                if report.status == TaskStatus::Succeeded {
+                    graph.done(&report.id);
+                } else {
+                    graph.fail(&report.id);
+                }
+                self.emit(&RunEvent::ended(&report));
+                reports.push(report);
+            }
+        });
+        self.children.cancelled.lock().unwrap().clear();
+
+        // The tasks skipped by their variable are already reported
+        let reported = reports
+            .iter()
+            .map(|r| r.id.as_str())
+            .collect::<HashSet<&str>>();
+        let mut not_run = graph
+            .statuses()
+            .iter()
+            .filter(|(id, _)| !reported.contains(id.as_str()))
+            .filter(|(_, status)| matches!(status, TaskStatus::Pending | TaskStatus::Skipped))
+            .map(|(id, status)| {
+                let mut report = TaskReport::not_run(&id.to_string(), *status);
+                if timed_out && *status == TaskStatus::Pending {
+                    report.error = Some(TIMED_OUT.to_string());
+                    report.timed_out = true;
+                }
+                report
+            })
+            .collect::<Vec<TaskReport>>();
+        not_run.sort_by(|a, b| a.id.cmp(&b.id));
+        reports.extend(not_run);
+
+        return reports;
+    }
+
+    /// Returns the variable skipping the task, if it is set, see
+    /// `skip_env_prefix`.
+    fn skip_var(&self, id: &TaskID) -> Option<String> {
+        let prefix = self.skip_env_prefix.as_ref()?;
+        let mangled = id
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+        let var = format!("{prefix}{mangled}");
+        let value = match &self.env {
+            Some(env) => env.var(&var).map(OsString::from),
+            None => std::env::var_os(&var),
+        };
+        return match value {
+            Some(value) if !value.is_empty() && value != "0" => Some(var),
+            _ => None,
+        };
+    }
+
+    /// Writes an event to the JSON Lines stream, if there is one.
+    /// The line is written with a single call while holding the writer, so
+    /// events never interleave.
+    fn emit(&self, event: &RunEvent) {
+        let Some(events) = &self.events else {
+            return;
+        };
+
+        let mut line = serde_json::to_string(event).expect("events serialize");
+        line.push('\n');
+        let mut writer = events.lock().unwrap();
+        // The events are informational, a failed write doesn't fail the run
+        if let Err(e) = writer
+            .write_all(line.as_bytes())
+            .and_then(|_| writer.flush())
+        {
+            #[cfg(feature = "tracing")]
+            tracing::warn!("failed to write a run event: {e}");
+            #[cfg(not(feature = "tracing"))]
+            let _ = e;
+        }
+    }
+
+    /// Runs a single task, producing its report.
+    /// Failed runs are retried as configured on the task, the report is of
+    /// the last run. Cached tasks succeed without running, with no attempts.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(task_id = %task.id()))
+    )]
+    fn run_task(&self, task: &Task) -> TaskReport {
+        let start = Instant::now();
+        let mut report = TaskReport::not_run(task.id(), TaskStatus::Failed);
+        if self.children.is_cancelled(task.id()) {
+            report.error = Some(CANCELLED.to_string());
+            return report;
+        }
+
+        let key = match self.cache_lookup(task) {
+            Ok(Some(CacheLookup::Hit)) => {
+                report.status = TaskStatus::Succeeded;
+                report.duration = start.elapsed();
+                return report;
+            }
+            Ok(Some(CacheLookup::Miss(key))) => Some(key),
+            Ok(None) => None,
+            Err(e) => {
+                report.error = Some(e.to_string());
+                report.duration = start.elapsed();
+                return report;
+            }
+        };
+
+        loop {
+            report.attempts += 1;
+
+            match self.spawn(task) {
+                Ok((status, output, cleanup_error)) => {
+                    report.exit_code = status.code();
+                    report.termination = Some(TerminationReason::from(&status));
+                    report.output = output;
+                    report.cleanup_error = cleanup_error;
+                    if task.action().is_success(&status) {
+                        report.status = TaskStatus::Succeeded;
+                        break;
+                    }
+                }
+                Err(e) => {
+                    // Not a failure of the process, running it again won't help
+                    report.error = Some(e.to_string());
+                    break;
+                }
+            }
+
+            if self.children.is_cancelled(task.id()) {
+                report.status = TaskStatus::Failed;
+                report.error = Some(CANCELLED.to_string());
+                break;
+            }
+            if report.attempts > task.retries() || !task.is_retryable(report.exit_code) {
+                break;
+            }
+        }
+
+        let succeeded = report.status == TaskStatus::Succeeded;
+        if let Some(hook) = task.hook(succeeded) {
+            let hook_error = match self.spawn_action(task, hook) {
+                Ok((status, output, cleanup_error)) => {
+                    report.output.extend(output);
+                    if cleanup_error.is_some() {
+                        report.cleanup_error = cleanup_error;
+                    }
+                    match hook.is_success(&status) {
+                        true => None,
+                        false => Some(format!("the hook failed with {status}")),
+                    }
+                }
+                Err(e) => Some(e.to_string()),
+            };
+            if hook_error.is_some() && succeeded && task.strict_hooks() {
+                report.status = TaskStatus::Failed;
+            }
+            report.hook_error = hook_error;
+        }
+
+        if let (Some(key), Some((cache, root))) = (key, &self.cache) {
+            if report.status == TaskStatus::Succeeded {
+                // The task did succeed, not caching it only costs a rerun
+                let stored = cache
+                    .store_outputs(task.cache_scope(), &key, task.outputs(), root)
+                    .and_then(|_| cache.store(task.cache_scope(), &key));
+                if let Err(e) = stored {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(task_id = %task.id(), "failed to cache the task: {e}");
+                    report.cache_error = Some(e.to_string());
+                }
+            }
+        }
+
+        report.duration = start.elapsed();
+        return report;
+    }
+
+    /// Looks a task up in the cache, reporting the outcome to the callback.
+    /// # Returns
+    /// * `Option<CacheLookup>` - The outcome, `None` if the cache is disabled
+    fn cache_lookup(&self, task: &Task) -> Result<Option<CacheLookup>> {
+        let Some((cache, root)) = &self.cache else {
+            return Ok(None);
+        };
+
+        let key = task.cache_key(root)?;
+        // A hit whose outputs can't be restored has to run again
+        let hit = cache.contains(task.cache_scope(), &key)?
+            && cache.restore_outputs(task.cache_scope(), &key, task.outputs(), root)?;
+        if let Some(callback) = &self.on_cache_event {
+            let id = task.id().clone();
+            callback(&if hit {
+                CacheEvent::Hit {
+                    id,
+                    key: key.clone(),
+                }
+            } else {
+                CacheEvent::Miss {
+                    id,
+                    key: key.clone(),
+                }
+            });
+        }
+
+        if hit {
+            return Ok(Some(CacheLookup::Hit));
+        }
+        return Ok(Some(CacheLookup::Miss(key)));
+    }
+
+    /// Spawns the process of a task and waits for it to exit.
+    /// Tasks with a custom executor are run by it instead, their output is
+    /// left to the executor.
+    /// # Returns
+    /// * `ExitStatus` - The exit status of the process
+    /// * `Vec<u8>` - The captured output, if output is captured
+    /// * `Option<String>` - Why the cleanup of the action failed, if it did
+    fn spawn(&self, task: &Task) -> Result<(ExitStatus, Vec<u8>, Option<String>)> {
+        if let Some(name) = task.executor() {
+            let Some(executor) = self.executors.get(name) else {
+                bail!("unknown executor `{name}`");
+            };
+            let mut ctx = TaskContext::new(task)?;
+            if let Some(env) = &self.env {
+                ctx.env = env.resolve(task)?;
+            }
+            return Ok((executor.execute(&ctx)?, vec![], None));
+        }
+
+        return self.spawn_action(task, task.action());
+    }
+
+    /// Runs an action of a task, see `spawn`.
+    fn spawn_action(
+        &self,
+        task: &Task,
+        action: &Action,
+    ) -> Result<(ExitStatus, Vec<u8>, Option<String>)> {
+        match action {
+            Action::Shell { .. } | Action::PlatformShell { .. } | Action::Container { .. } => {
+                let mut command = action.command()?;
+                match &self.env {
+                    Some(env) => {
+                        command.env_clear().envs(env.resolve(task)?);
+                    }
+                    None => task.apply_env(&mut command)?,
+                };
+                let (status, output) = self.spawn_process(task, command)?;
+                return Ok((status, output, None));
+            }
+            Action::WithCleanup { body, cleanup } => {
+                let result = self.spawn_action(task, body);
+                let cleaned = self.spawn_action(task, cleanup);
+
+                let (status, mut output, body_error) = result?;
+                // The outcome is the body's, a failed cleanup is only reported
+                let cleanup_error = match cleaned {
+                    Ok((cleanup_status, cleanup_output, nested_error)) => {
+                        output.extend(cleanup_output);
+                        match cleanup.is_success(&cleanup_status) {
+                            true => nested_error,
+                            false => Some(format!("the cleanup failed with {cleanup_status}")),
+                        }
+                    }
+                    Err(e) => Some(e.to_string()),
+                };
+                return Ok((status, output, cleanup_error.or(body_error)));
+            }
+        }
+    }
+
+    /// Spawns a process of a task and waits for it to exit, handling its
+    /// output as configured.
+    fn spawn_process(&self, task: &Task, mut command: Command) -> Result<(ExitStatus, Vec<u8>)> {
+        if let Some(runner) = &self.runner {
+            return Ok((runner.run(&mut command)?, vec![]));
+        }
+
+        match &self.output {
+            OutputMode::Inherit => {
+                let child = self.children.spawn(task.id(), &mut command)?;
+                return Ok((child.wait()?, vec![]));
+            }
+            OutputMode::Prefixed { format, writer } => {
+                let prefix = match task.action().label() {
+                    Some(label) if !format.contains("{label}") => {
+                        format.replace("{id}", &format!("{} ({label})", task.id()))
+                    }
+                    _ => format
+                        .replace("{id}", task.id())
+                        .replace("{label}", &task.action().display_name()),
+                };
+
+                let child = self.children.spawn(
+                    task.id(),
+                    command.stdout(Stdio::piped()).stderr(Stdio::piped()),
+                )?;
+                let copied = copy_output(&child, &prefix, &**writer, true);
+                let status = child.wait()?;
+                copied?;
+                return Ok((status, vec![]));
+            }
+            OutputMode::Capture => {
+                let output = Mutex::new(CapturedOutput::new(self.max_output_bytes));
+
+                let child = self.children.spawn(
+                    task.id(),
+                    command.stdout(Stdio::piped()).stderr(Stdio::piped()),
+                )?;
+                // Captured as written, without completing the last line
+                let copied = copy_output(&child, "", &output, false);
+                let status = child.wait()?;
+                copied?;
+                return Ok((status, output.into_inner().unwrap().data));
+            }
+            OutputMode::Tail { lines } => {
+                // Shared by the attempts and cleanup of the task
+                let buffer = self
+                    .tails
+                    .lock()
+                    .unwrap()
+                    .entry(task.id().clone())
+                    .or_insert_with(|| Arc::new(Mutex::new(TailBuffer::new(*lines))))
+                    .clone();
+
+                let child = self.children.spawn(
+                    task.id(),
+                    command.stdout(Stdio::piped()).stderr(Stdio::piped()),
+                )?;
+                let copied = copy_output(&child, "", &*buffer, true);
+                let status = child.wait()?;
+                copied?;
+                return Ok((status, vec![]));
+            }
+        }
+    }
+}
+
+/// The outcome of looking a task up in the cache.
+enum CacheLookup {
+    Hit,
+    /// The task has to run, its results are cached under the key
+    Miss(String),
+}
+
+/// The error of the reports of cancelled tasks.
+const CANCELLED: &str = "the task was cancelled";
+
+/// Cancels a single task of the runs of an `Executor`, leaving the other
+/// tasks running, e.g. when a user kills a stuck build from a TUI. Created by
+/// `Executor::task_handle`.
+#[derive(Clone)]
+pub struct TaskHandle {
+    id: TaskID,
+    children: Arc<Children>,
+}
+
+impl TaskHandle {
+    pub fn id(&self) -> &TaskID {
+        &self.id
+    }
+
+    /// Kills the processes of the task and fails it, so its dependents are
+    /// skipped. A task that did not start yet fails without running.
+    /// The cancellation lasts until the end of the current run, tasks that
+    /// already finished are left as they are.
+    pub fn cancel(&self) {
+        #[cfg(feature = "tracing")]
+        tracing::info!(task_id = %self.id, "task cancelled");
+        self.children.cancel(&self.id);
+    }
+}
+
+/// The processes of the running tasks, by process ID, so they can be killed
+/// if the run is interrupted or their task cancelled.
+#[derive(Default)]
+struct Children {
+    /// The processes, with the ID of their task
+    processes: Mutex<HashMap<u32, (TaskID, Arc<SharedChild>)>>,
+    /// The tasks cancelled during the run
+    cancelled: Mutex<HashSet<TaskID>>,
+}
+
+impl Children {
+    /// Spawns a process of a task, tracking it until the returned handle is
+    /// dropped.
+    fn spawn(&self, task_id: &TaskID, command: &mut Command) -> Result<RunningChild<'_>> {
+        let child = Arc::new(SharedChild::spawn(command)?);
+        self.processes
+            .lock()
+            .unwrap()
+            .insert(child.id(), (task_id.clone(), child.clone()));
+        // The task may have been cancelled while the process was spawned
+        if self.is_cancelled(task_id) {
+            let _ = child.kill();
+        }
+        return Ok(RunningChild {
+            children: self,
+            child,
+        });
+    }
+
+    fn is_cancelled(&self, task_id: &TaskID) -> bool {
+        return self.cancelled.lock().unwrap().contains(task_id);
+    }
+
+    /// Cancels a task, killing its processes.
+    fn cancel(&self, task_id: &TaskID) {
+        self.cancelled.lock().unwrap().insert(task_id.clone());
+        let processes = self.processes.lock().unwrap_or_else(|e| e.into_inner());
+        for (_, child) in processes.values().filter(|(id, _)| id == task_id) {
+            let _ = child.kill();
+        }
+    }
+
+    /// Kills every tracked process. Processes that already exited are left
+    /// alone.
+    fn kill_all(&self) {
+        // A panicking task thread may have poisoned the lock, the map is
+        // still usable
+        let processes = self.processes.lock().unwrap_or_else(|e| e.into_inner());
+        for (_, child) in processes.values() {
+            let _ = child.kill();
+        }
+    }
+}
+
+/// A tracked process. Killed on drop if it is still running, e.g. when the
+/// thread waiting for it panics.
+struct RunningChild<'a> {
+    children: &'a Children,
+    child: Arc<SharedChild>,
+}
+
+impl std::ops::Deref for RunningChild<'_> {
+    type Target = SharedChild;
+
+    fn deref(&self) -> &SharedChild {
+        &self.child
+    }
+}
+
+impl Drop for RunningChild<'_> {
+    fn drop(&mut self) {
+        // No-op once the process was waited for
+        let _ = self.child.kill();
+        let mut processes = self
+            .children
+            .processes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        processes.remove(&self.child.id());
+    }
+}
+
+/// Kills the running processes when dropped. Once a run completed normally
+/// nothing is running anymore, so it does nothing.
+struct KillOnDrop<'a>(&'a Children);
+
+impl Drop for KillOnDrop<'_> {
+    fn drop(&mut self) {
+        self.0.kill_all();
+    }
+}
+
+/// Appended to captured output that went over the limit.
+const TRUNCATED_MARKER: &[u8] = b"... (truncated)\n";
+
+/// Output captured from a task, bounded by an optional limit.
+struct CapturedOutput {
+    data: Vec<u8>,
+    limit: Option<usize>,
+    truncated: bool,
+}
+
+impl CapturedOutput {
+    fn new(limit: Option<usize>) -> Self {
+        CapturedOutput {
+            data: vec![],
+            limit,
+            truncated: false,
+        }
+    }
+}
+
+impl Write for CapturedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // Everything is accepted, so the process is never blocked on its pipes
+        if self.truncated {
+            return Ok(buf.len());
+        }
+
+        match self.limit {
+            Some(limit) if self.data.len() + buf.len() > limit => {
+                let room = limit - self.data.len();
+                self.data.extend_from_slice(&buf[..room]);
+                self.data.extend_from_slice(TRUNCATED_MARKER);
+                self.truncated = true;
+            }
+            _ => self.data.extend_from_slice(buf),
+        }
+
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The last lines written to it, dropping the oldest ones past the limit.
+struct TailBuffer {
+    lines: VecDeque<String>,
+    limit: usize,
+    /// The start of a line not written whole yet
+    partial: Vec<u8>,
+}
+
+impl TailBuffer {
+    fn new(limit: usize) -> Self {
+        TailBuffer {
+            lines: VecDeque::new(),
+            limit,
+            partial: vec![],
+        }
+    }
+}
+
+impl Write for TailBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.partial.extend_from_slice(buf);
+        while let Some(end) = self.partial.iter().position(|b| *b == b'\n') {
+            let line = self.partial.drain(..=end).collect::<Vec<u8>>();
+            let line = String::from_utf8_lossy(&line);
+            self.lines
+                .push_back(line.trim_end_matches(['\n', '\r']).to_string());
+            if self.lines.len() > self.limit {
+                self.lines.pop_front();
+            }
+        }
+
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The longest line written whole. Longer lines are written in pieces, so a
+/// process printing without newlines doesn't buffer all of its output.
+const MAX_LINE_BYTES: usize = 64 * 1024;
+
+/// Copies the stdout and stderr of a process to `writer` until they are
+/// closed, see `write_prefixed`.
+/// Fails if the output of the process can't be read.
+fn copy_output<W: Write + Send>(
+    child: &SharedChild,
+    prefix: &str,
+    writer: &Mutex<W>,
+    terminate: bool,
+) -> std::io::Result<()> {
+    let stdout = child.take_stdout().unwrap();
+    let stderr = child.take_stderr().unwrap();
+
+    let (stdout, stderr) = thread::scope(|scope| {
+        let stdout = scope.spawn(|| write_prefixed(stdout, prefix, writer, terminate));
+        let stderr = scope.spawn(|| write_prefixed(stderr, prefix, writer, terminate));
+        (stdout.join().unwrap(), stderr.join().unwrap())
+    });
+    stdout?;
+    return stderr;
+}
+
+/// Copies the lines of `source` to `writer`, prefixing each one.
+/// The writer is locked for each whole line, so lines from different sources
+/// are never mixed. Lines longer than `MAX_LINE_BYTES` are written in pieces,
+/// each prefixed like a line.
+/// # Arguments
+/// * `terminate` - Whether a newline is added to the lines that don't end
+///   with one, e.g. a last line or a piece of a long one, so they are
+///   displayed on their own
+fn write_prefixed<W: Write>(
+    mut source: impl Read,
+    prefix: &str,
+    writer: &Mutex<W>,
+    terminate: bool,
+) -> std::io::Result<()> {
+    let write_line = |line: &[u8]| {
+        let mut writer = writer.lock().unwrap();
+        // Output is best effort, a broken writer should not fail the task
+        let _ = writer.write_all(prefix.as_bytes());
+        let _ = writer.write_all(line);
+        if terminate && !line.ends_with(b"\n") {
+            let _ = writer.write_all(b"\n");
+        }
+        let _ = writer.flush();
+    };
+
+    let mut chunk = [0; 8 * 1024];
+    let mut line: Vec<u8> = vec![];
+    loop {
+        let read = match source.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        for piece in chunk[..read].split_inclusive(|b| *b == b'\n') {
+            line.extend_from_slice(piece);
+            if line.ends_with(b"\n") || line.len() >= MAX_LINE_BYTES {
+                write_line(&line);
+                line.clear();
+            }
+        }
+    }
+    if !line.is_empty() {
+        write_line(&line);
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::graphing::cache::LocalCache;
+    use crate::graphing::report::{RunResult, RUN_RESULT_SCHEMA_VERSION};
+    use crate::graphing::runner::MockRunner;
+    use crate::graphing::types::{MissingEnvFile, TaskGraphBuilder};
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+    /// A writer whose content can be read after it was handed to the executor
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn shell(id: &str, script: &str) -> Task {
+        Task::new(
+            id,
+            id,
+            Action::shell(vec!["sh".to_string(), "-c".to_string(), script.to_string()]),
+        )
+    }
+
+    #[test]
+    fn test_prefixed_output() {
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(shell("a", "echo one; echo two >&2"));
+        builder.add_task(shell("b", "echo three"));
+        builder.add_dependency("b".to_string(), "a".to_string());
+        let labelled = Action::shell(vec!["echo".to_string(), "four".to_string()]);
+        builder.add_task(Task::new("c", "c", labelled.with_label("say four")));
+        let graph = builder.build().unwrap();
+
+        let run = |format: &str| {
+            let buffer = Buffer::default();
+            let mut executor = Executor::new(2);
+            executor.prefix_output(format, buffer.clone());
+            executor.run(&mut graph.clone());
+
+            let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+            let mut lines = output.lines().map(str::to_string).collect::<Vec<String>>();
+            lines.sort();
+            return lines;
+        };
+        assert_eq!(
+            run("[{id}] "),
+            vec!["[a] one", "[a] two", "[b] three", "[c (say four)] four"]
+        );
+        assert_eq!(
+            run("{label}| "),
+            vec![
+                "say four| four",
+                "sh -c echo one; echo two >&2| one",
+                "sh -c echo one; echo two >&2| two",
+                "sh -c echo three| three",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_output_without_newlines() {
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(shell("a", "printf 'one\\ntwo'"));
+        builder.add_task(shell("long", "head -c 200000 /dev/zero | tr '\\0' x"));
+        let graph = builder.build().unwrap();
+
+        // Captured as written
+        let mut executor = Executor::new(2);
+        executor.capture_output();
+        let reports = executor.run(&mut graph.clone());
+        let output = |id: &str| reports.iter().find(|r| r.id == id).unwrap().output.clone();
+        assert_eq!(output("a"), b"one\ntwo");
+        assert_eq!(output("long"), vec![b'x'; 200000]);
+
+        // Printed on their own lines, long lines in pieces
+        let buffer = Buffer::default();
+        let mut executor = Executor::new(2);
+        executor.prefix_output("[{id}] ", buffer.clone());
+        executor.run(&mut graph.clone());
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(output.ends_with('\n'));
+        let (a, long): (Vec<&str>, Vec<&str>) = output.lines().partition(|l| l.starts_with("[a] "));
+        assert_eq!(a, vec!["[a] one", "[a] two"]);
+        assert!(long.len() > 1);
+        assert!(long.iter().all(|l| l.len() < MAX_LINE_BYTES + 8 * 1024));
+        let joined = long
+            .iter()
+            .map(|l| &l["[long] ".len()..])
+            .collect::<String>();
+        assert_eq!(joined.len(), 200000);
+    }
+
+    #[test]
+    fn test_verbose() {
+        let mut builder = TaskGraphBuilder::new();
+        for id in ["app:build", "lib:build", "docs"] {
+            builder.add_task(shell(id, "true"));
+        }
+        builder.add_dependency("app:build".to_string(), "lib:build".to_string());
+        let mut graph = builder.build_for(&["app:build".to_string()]).unwrap();
+
+        let buffer = Buffer::default();
+        let mut executor = Executor::new(1);
+        executor.verbose(buffer.clone());
+        executor.run(&mut graph);
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            output,
+            "lib:build - dependency of app:build\napp:build - requested target\n"
+        );
+    }
+
+    #[test]
+    fn test_emit_jsonl() {
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(shell("a", "true"));
+        builder.add_task(shell("b", "exit 2"));
+        builder.add_task(shell("c", "true"));
+        builder.add_dependency("b".to_string(), "a".to_string());
+        builder.add_dependency("c".to_string(), "b".to_string());
+        let mut graph = builder.build().unwrap();
+
+        let buffer = Buffer::default();
+        let mut executor = Executor::new(1);
+        executor.emit_jsonl(buffer.clone());
+        executor.run(&mut graph);
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let events = output
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        let summary = events
+            .iter()
+            .map(|e| {
+                (
+                    e["event"].as_str().unwrap(),
+                    e["id"].as_str().unwrap(),
+                    e["exit_code"].as_i64(),
+                )
+            })
+            .collect::<Vec<_>>();
+        // `c` is skipped, so never started
+        assert_eq!(
+            summary,
+            vec![
+                ("started", "a", None),
+                ("finished", "a", Some(0)),
+                ("started", "b", None),
+                ("failed", "b", Some(2)),
+            ]
+        );
+        assert!(events
+            .iter()
+            .all(|e| e["timestamp_ms"].as_u64().unwrap() > 0));
+    }
+
+    #[test]
+    fn test_tail_output() {
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(shell("a", "for i in 1 2 3 4 5; do echo line $i; done"));
+        builder.add_task(shell("b", "true"));
+        let mut graph = builder.build().unwrap();
+
+        let mut executor = Executor::new(2);
+        executor.tail_output(2);
+        let reports = executor.run(&mut graph);
+
+        assert_eq!(
+            executor.tail(&"a".to_string()),
+            vec!["line 4".to_string(), "line 5".to_string()]
+        );
+        assert!(executor.tail(&"b".to_string()).is_empty());
+        assert!(executor.tail(&"c".to_string()).is_empty());
+        assert!(
+            reports.iter().all(|r| r.output.is_empty()),
+            "The output isn't captured"
+        );
+    }
+
+    #[test]
+    fn test_hooks() {
+        let dir = std::env::temp_dir().join(format!("nx-rs-hooks-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let hook = |name: &str| {
+            let script = format!("echo {name} >> {}/hooks", dir.display());
+            Action::shell(vec!["sh".to_string(), "-c".to_string(), script])
+        };
+        let failing = Action::shell(vec!["false".to_string()]);
+
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(
+            shell("ok", "true")
+                .with_on_success(hook("ok-success"))
+                .with_on_failure(hook("ok-failure")),
+        );
+        builder.add_task(shell("ko", "exit 1").with_on_failure(hook("ko-failure")));
+        builder.add_task(shell("lenient", "true").with_on_success(failing.clone()));
+        builder.add_task(
+            shell("strict", "true")
+                .with_on_success(failing)
+                .with_strict_hooks(true),
+        );
+        let mut graph = builder.build().unwrap();
+
+        let reports = Executor::new(1).run(&mut graph);
+        let report = |id: &str| reports.iter().find(|r| r.id == id).unwrap();
+        let mut hooks = std::fs::read_to_string(dir.join("hooks"))
+            .unwrap()
+            .lines()
+            .map(|l| l.to_string())
+            .collect::<Vec<String>>();
+        hooks.sort();
+        assert_eq!(hooks, vec!["ko-failure", "ok-success"]);
+
+        assert_eq!(report("ok").hook_error, None);
+        assert_eq!(report("ko").status, TaskStatus::Failed);
+        assert_eq!(
+            report("ko").exit_code,
+            Some(1),
+            "The hook keeps the task's exit code"
+        );
+        assert_eq!(report("lenient").status, TaskStatus::Succeeded);
+        assert!(report("lenient").hook_error.is_some());
+        assert_eq!(report("strict").status, TaskStatus::Failed);
+        assert!(report("strict").hook_error.is_some());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_override_action() {
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(shell("app:test", "exit 1"));
+        let mut graph = builder.build().unwrap();
+
+        let id = "app:test".to_string();
+        let echo = Action::shell(vec!["echo".to_string(), "skipped".to_string()]);
+        graph.override_action(&id, echo).unwrap();
+        assert_eq!(
+            graph
+                .override_action(&"app:build".to_string(), Action::shell(vec![]))
+                .unwrap_err()
+                .to_string(),
+            "unknown task `app:build`"
+        );
+
+        let mut executor = Executor::new(1);
+        executor.capture_output();
+        let reports = executor.run(&mut graph);
+        assert_eq!(reports[0].status, TaskStatus::Succeeded);
+        assert_eq!(reports[0].output, b"skipped\n");
+    }
+
+    #[test]
+    fn test_failure_skips_dependents() {
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(shell("a", "exit 1"));
+        builder.add_task(shell("b", "true"));
+        builder.add_task(shell("c", "true"));
+        builder.add_dependency("b".to_string(), "a".to_string());
+        let mut graph = builder.build().unwrap();
+
+        let reports = Executor::new(4).run(&mut graph);
+        assert_eq!(reports.len(), 3, "Every task should have a report");
+
+        assert_eq!(graph.status(&"a".to_string()), Some(TaskStatus::Failed));
+        assert_eq!(graph.status(&"b".to_string()), Some(TaskStatus::Skipped));
+        assert_eq!(graph.status(&"c".to_string()), Some(TaskStatus::Succeeded));
+    }
+
+    #[test]
+    fn test_run_result() {
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(shell("a", "printf hello; exit 3"));
+        builder.add_task(shell("b", "true"));
+        builder.add_dependency("b".to_string(), "a".to_string());
+        let mut graph = builder.build().unwrap();
+
+        let mut executor = Executor::new(1);
+        executor.capture_output();
+        let result = RunResult::from_reports(&executor.run(&mut graph));
+
+        assert_eq!(result.schema_version, RUN_RESULT_SCHEMA_VERSION);
+        assert_eq!(result.tasks.len(), 2);
+        assert_eq!(result.tasks[0].id, "a");
+        assert_eq!(result.tasks[0].status, TaskStatus::Failed);
+        assert_eq!(result.tasks[0].exit_code, Some(3));
+        assert_eq!(result.tasks[0].output_bytes, "hello".len());
+        assert_eq!(result.tasks[1].status, TaskStatus::Skipped);
+        assert_eq!(result.tasks[1].exit_code, None);
+
+        let json: serde_json::Value = serde_json::from_str(&result.to_json().unwrap()).unwrap();
+        assert_eq!(json["schema_version"], 1);
+        assert_eq!(json["tasks"][0]["status"], "failed");
+    }
+
+    #[test]
+    fn test_max_output_bytes() {
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(shell("a", "for i in 1 2 3 4 5; do echo 0123456789; done"));
+        let mut graph = builder.build().unwrap();
+
+        let mut executor = Executor::new(1);
+        executor.capture_output();
+        executor.max_output_bytes(Some(15));
+        let reports = executor.run(&mut graph);
+
+        assert_eq!(reports[0].status, TaskStatus::Succeeded);
+        assert_eq!(
+            String::from_utf8(reports[0].output.clone()).unwrap(),
+            "0123456789\n0123... (truncated)\n"
+        );
+    }
+
+    #[test]
+    fn test_priority() {
+        let mut builder = TaskGraphBuilder::new();
+        for id in ["a", "b", "c"] {
+            builder.add_task(shell(id, "true"));
+        }
+        let mut graph = builder.build().unwrap();
+
+        let mut executor = Executor::new(1);
+        executor.priority(|a, b| b.id().cmp(a.id()));
+        let order = executor
+            .run(&mut graph)
+            .into_iter()
+            .map(|r| r.id)
+            .collect::<Vec<TaskID>>();
+
+        assert_eq!(
+            order,
+            vec!["c", "b", "a"],
+            "Tasks should run in priority order"
+        );
+    }
+
+    #[test]
+    fn test_success_codes() {
+        let diff = |id: &str, codes: Vec<i32>| {
+            Task::new(
+                id,
+                id,
+                Action::shell(vec![
+                    "sh".to_string(),
+                    "-c".to_string(),
+                    "exit 1".to_string(),
+                ])
+                .with_success_codes(codes),
+            )
+        };
+
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(diff("informational", vec![0, 1]));
+        builder.add_task(diff("strict", vec![0]));
+        let mut graph = builder.build().unwrap();
+
+        Executor::new(2).run(&mut graph);
+
+        assert_eq!(
+            graph.status(&"informational".to_string()),
+            Some(TaskStatus::Succeeded),
+            "Exit code 1 was configured as a success"
+        );
+        assert_eq!(
+            graph.status(&"strict".to_string()),
+            Some(TaskStatus::Failed)
+        );
+    }
+
+    #[test]
+    fn test_env_file() {
+        let dir = std::env::temp_dir().join(format!("nx-rs-env-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let env_file = dir.join(".env");
+        std::fs::write(&env_file, "FROM_FILE=file\nOVERRIDDEN=file\n").unwrap();
+
+        let env = HashMap::from([("OVERRIDDEN".to_string(), "task".to_string())]);
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(
+            shell("env", "echo $FROM_FILE $OVERRIDDEN")
+                .with_env(env)
+                .with_env_file(env_file, MissingEnvFile::Error),
+        );
+        builder.add_task(
+            shell("ignored", "true").with_env_file(dir.join("missing"), MissingEnvFile::Ignore),
+        );
+        builder.add_task(
+            shell("required", "true").with_env_file(dir.join("missing"), MissingEnvFile::Error),
+        );
+        let mut graph = builder.build().unwrap();
+
+        let mut executor = Executor::new(3);
+        executor.capture_output();
+        let reports = executor.run(&mut graph);
+        let report = |id: &str| reports.iter().find(|r| r.id == id).unwrap();
+
+        assert_eq!(report("env").output, b"file task\n");
+        assert_eq!(report("ignored").status, TaskStatus::Succeeded);
+        assert_eq!(report("required").status, TaskStatus::Failed);
+        assert!(report("required").error.is_some());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_inherit_env() {
+        // Set by cargo for the test process
+        assert!(std::env::var("CARGO_MANIFEST_DIR").is_ok());
+        let script = r#"echo "${CARGO_MANIFEST_DIR-unset} ${CARGO_PKG_NAME-unset} $OWN""#;
+        let own = HashMap::from([("OWN".to_string(), "own".to_string())]);
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(
+            shell("allowed", script)
+                .with_inherit_env(vec!["PATH".to_string(), "CARGO_PKG_NAME".to_string()])
+                .with_env(own.clone()),
+        );
+        builder.add_task(shell("all", script).with_env(own));
+        let mut graph = builder.build().unwrap();
+
+        let mut executor = Executor::new(2);
+        executor.capture_output();
+        let reports = executor.run(&mut graph);
+        let output = |id: &str| {
+            let report = reports.iter().find(|r| r.id == id).unwrap();
+            String::from_utf8_lossy(&report.output).to_string()
+        };
+
+        assert_eq!(output("allowed"), "unset nx-rs-lib own\n");
+        assert_eq!(
+            output("all"),
+            format!("{} nx-rs-lib own\n", env!("CARGO_MANIFEST_DIR"))
+        );
+    }
+
+    #[test]
+    fn test_retry_on() {
+        let dir = std::env::temp_dir().join(format!("nx-rs-retry-on-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // Records each attempt, then exits with the given code
+        let flaky = |id: &str, code: i32| {
+            let script = format!("echo run >> '{}'; exit {}", dir.join(id).display(), code);
+            shell(id, &script).with_retries(2).with_retry_on(vec![75])
+        };
+
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(flaky("tempfail", 75));
+        builder.add_task(flaky("compile-error", 1));
+        let mut graph = builder.build().unwrap();
+
+        let reports = Executor::new(2).run(&mut graph);
+        let attempts = |id: &str| {
+            let report = reports.iter().find(|r| r.id == id).unwrap();
+            let runs = std::fs::read_to_string(dir.join(id))
+                .unwrap()
+                .lines()
+                .count();
+            assert_eq!(report.attempts as usize, runs);
+            assert_eq!(report.status, TaskStatus::Failed);
+            return runs;
+        };
+
+        assert_eq!(attempts("tempfail"), 3, "75 should be retried twice");
+        assert_eq!(attempts("compile-error"), 1, "1 should fail right away");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_kill_on_panic() {
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(shell("slow", "exec sleep 30"));
+        builder.add_task(shell("quick", "true"));
+        builder.add_task(shell("b", "true"));
+        builder.add_task(shell("c", "true"));
+        builder.add_dependency("b".to_string(), "quick".to_string());
+        builder.add_dependency("c".to_string(), "quick".to_string());
+        let mut graph = builder.build().unwrap();
+
+        // Panics on the loop after `quick` is done, while `slow` is running
+        let mut executor = Executor::new(2);
+        executor.priority(|a, b| {
+            if a.id() == "b" || b.id() == "b" {
+                panic!("priority panicked");
+            }
+            return a.id().cmp(b.id());
+        });
+
+        let start = Instant::now();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            executor.run(&mut graph);
+        }));
+
+        assert!(result.is_err());
+        assert!(
+            start.elapsed().as_secs() < 10,
+            "slow should be killed instead of waited for"
+        );
+        assert!(executor.children.processes.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_cancel() {
+        /// Cancels `stuck` when it runs. `stuck` fails the same way whether
+        /// it started by then or not
+        struct Cancel(TaskHandle);
+        impl TaskExecutor for Cancel {
+            fn execute(&self, _ctx: &TaskContext) -> Result<ExitStatus> {
+                self.0.cancel();
+                return Ok(exit_status(0));
+            }
+        }
+
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(shell("stuck", "exec sleep 300"));
+        builder.add_task(shell("after-stuck", "true"));
+        builder.add_task(shell("other", "true").with_executor("cancel"));
+        builder.add_task(shell("after-other", "true"));
+        builder.add_dependency("after-stuck".to_string(), "stuck".to_string());
+        builder.add_dependency("after-other".to_string(), "other".to_string());
+        let mut graph = builder.build().unwrap();
+
+        let mut executor = Executor::new(4);
+        let handle = executor.task_handle(&"stuck".to_string());
+        executor.register_executor("cancel", Cancel(handle));
+        let start = Instant::now();
+        let reports = executor.run(&mut graph);
+
+        assert!(start.elapsed().as_secs() < 60, "stuck should be killed");
+        let report = |id: &str| reports.iter().find(|r| r.id == id).unwrap().clone();
+        assert_eq!(report("stuck").status, TaskStatus::Failed);
+        assert_eq!(report("stuck").error.as_deref(), Some(CANCELLED));
+        assert_eq!(report("after-stuck").status, TaskStatus::Skipped);
+        assert_eq!(report("other").status, TaskStatus::Succeeded);
+        assert_eq!(report("after-other").status, TaskStatus::Succeeded);
+
+        // The cancellation ends with the run
+        let mut graph = TaskGraphBuilder::new();
+        graph.add_task(shell("stuck", "true"));
+        let reports = executor.run(&mut graph.build().unwrap());
+        assert_eq!(reports[0].status, TaskStatus::Succeeded);
+    }
+
+    #[test]
+    fn test_total_timeout() {
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(shell("a-quick", "true"));
+        builder.add_task(shell("b-long", "exec sleep 300"));
+        builder.add_task(shell("c-queued", "true"));
+        builder.add_task(shell("d-after-long", "true"));
+        builder.add_dependency("d-after-long".to_string(), "b-long".to_string());
+        let mut graph = builder.build().unwrap();
+
+        // Plenty of time for `a-quick`, and none for `b-long`
+        let mut executor = Executor::new(1);
+        executor.priority(|a, b| a.id().cmp(b.id()));
+        executor.total_timeout(Some(std::time::Duration::from_secs(2)));
+        let start = Instant::now();
+        let reports = executor.run(&mut graph);
+
+        assert!(start.elapsed().as_secs() < 60, "b-long should be killed");
+        let report = |id: &str| reports.iter().find(|r| r.id == id).unwrap().clone();
+        assert_eq!(report("a-quick").status, TaskStatus::Succeeded);
+        assert_eq!(report("a-quick").error, None);
+        assert_eq!(report("b-long").status, TaskStatus::Failed);
+        assert_eq!(report("b-long").error.as_deref(), Some(TIMED_OUT));
+        assert_eq!(report("c-queued").status, TaskStatus::Pending);
+        assert_eq!(report("c-queued").error.as_deref(), Some(TIMED_OUT));
+        assert_eq!(report("d-after-long").status, TaskStatus::Skipped);
+        let timed_out = reports
+            .iter()
+            .filter(|r| r.timed_out)
+            .map(|r| r.id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(timed_out, ["b-long", "c-queued"]);
+        assert!(RunResult::from_reports(&reports).timed_out);
+
+        // Nothing starts once the deadline has passed
+        let mut expired = Executor::new(1);
+        expired.total_timeout(Some(std::time::Duration::ZERO));
+        let mut graph = TaskGraphBuilder::new();
+        graph.add_task(shell("a-quick", "true"));
+        let reports = expired.run(&mut graph.build().unwrap());
+        assert_eq!(reports[0].status, TaskStatus::Pending);
+        assert!(reports[0].timed_out);
+
+        // The budget is per run
+        let mut graph = TaskGraphBuilder::new();
+        graph.add_task(shell("b-long", "true"));
+        let reports = executor.run(&mut graph.build().unwrap());
+        assert_eq!(reports[0].status, TaskStatus::Succeeded);
+        assert!(!RunResult::from_reports(&reports).timed_out);
+    }
+
+    #[test]
+    fn test_skip_env() {
+        let mut builder = TaskGraphBuilder::new();
+        for id in [
+            "app:test",
+            "app-test",
+            "app:test-e2e",
+            "app:build",
+            "app:lint",
+            "app:docs",
+        ] {
+            builder.add_task(shell(id, "true"));
+        }
+        builder.add_dependency("app:lint".to_string(), "app:test".to_string());
+        builder.add_soft_dependency("app:docs".to_string(), "app:test".to_string());
+        let graph = builder.build().unwrap();
+
+        let vars = |vars: &[(&str, &str)]| {
+            vars.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<String, String>>()
+        };
+        let mut executor = Executor::new(2);
+        executor.skip_env_prefix(Some("NXRS_TEST_SKIP_"));
+        executor.run_env(
+            RunEnv::new(vars(&[("NXRS_TEST_SKIP_app_test", "1")]))
+                .with_defaults(vars(&[("NXRS_TEST_SKIP_app_build", "0")])),
+        );
+        let reports = executor.run(&mut graph.clone());
+
+        let report = |id: &str| reports.iter().find(|r| r.id == id).unwrap().clone();
+        assert_eq!(report("app:test").status, TaskStatus::Skipped);
+        assert_eq!(
+            report("app:test").error.as_deref(),
+            Some("skipped by `NXRS_TEST_SKIP_app_test`")
+        );
+        // Mangled to the same variable
+        assert_eq!(report("app-test").status, TaskStatus::Skipped);
+        assert_eq!(report("app:test-e2e").status, TaskStatus::Succeeded);
+        assert_eq!(report("app:build").status, TaskStatus::Succeeded);
+        assert_eq!(report("app:lint").status, TaskStatus::Skipped);
+        assert_eq!(report("app:docs").status, TaskStatus::Succeeded);
+        assert_eq!(reports.len(), 6);
+
+        executor.skip_env_prefix(None);
+        let reports = executor.run(&mut graph.clone());
+        assert!(reports.iter().all(|r| r.status == TaskStatus::Succeeded));
+    }
+
+    #[test]
+    fn test_run_env() {
+        assert!(std::env::var("CARGO_MANIFEST_DIR").is_ok());
+        let vars = |vars: &[(&str, &str)]| {
+            vars.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<String, String>>()
+        };
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(
+            shell(
+                "a",
+                r#"echo "$RUN $DEFAULT $TASK ${CARGO_MANIFEST_DIR-unset}""#,
+            )
+            .with_env(vars(&[("TASK", "task")])),
+        );
+        let mut graph = builder.build().unwrap();
+
+        let env = RunEnv::new(vars(&[
+            ("PATH", &std::env::var("PATH").unwrap()),
+            ("RUN", "run"),
+            ("DEFAULT", "run"),
+            ("TASK", "run"),
+        ]))
+        .with_defaults(vars(&[("DEFAULT", "default"), ("TASK", "default")]));
+
+        let mut executor = Executor::new(1);
+        executor.capture_output();
+        executor.run_env(env);
+        let reports = executor.run(&mut graph);
+        assert_eq!(
+            String::from_utf8_lossy(&reports[0].output),
+            "run default task unset\n",
+            "The runner's variables should not be inherited"
+        );
+    }
+
+    #[test]
+    fn test_custom_executor() {
+        /// Counts the tasks it runs, failing the ones with a `fail` argument
+        struct Counter(Arc<AtomicUsize>);
+
+        impl TaskExecutor for Counter {
+            fn execute(&self, ctx: &TaskContext) -> Result<ExitStatus> {
+                self.0.fetch_add(1, AtomicOrdering::SeqCst);
+                let code = if ctx.args.contains(&"fail".to_string()) {
+                    1
+                } else {
+                    0
+                };
+                return Ok(exit_status(code));
+            }
+        }
+
+        let custom = |id: &str, args: &[&str], executor: &str| {
+            let args = args.iter().map(|a| a.to_string()).collect();
+            Task::new(id, id, Action::shell(args)).with_executor(executor)
+        };
+
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(custom("ok", &["copy"], "counter"));
+        builder.add_task(custom("bad", &["fail"], "counter"));
+        builder.add_task(custom("shell", &["true"], "shell"));
+        builder.add_task(custom("unknown", &[], "missing"));
+        let mut graph = builder.build().unwrap();
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let mut executor = Executor::new(2);
+        executor.register_executor("counter", Counter(count.clone()));
+        let reports = executor.run(&mut graph);
+
+        assert_eq!(count.load(AtomicOrdering::SeqCst), 2);
+        let status = |id: &str| graph.status(&id.to_string()).unwrap();
+        assert_eq!(status("ok"), TaskStatus::Succeeded);
+        assert_eq!(status("bad"), TaskStatus::Failed);
+        assert_eq!(status("shell"), TaskStatus::Succeeded);
+        assert_eq!(status("unknown"), TaskStatus::Failed);
+
+        let unknown = reports.iter().find(|r| r.id == "unknown").unwrap();
+        assert_eq!(unknown.error.as_deref(), Some("unknown executor `missing`"));
+    }
+
+    #[test]
+    fn test_shell_executor() {
+        let task = |id: &str, script: &str, executor: Option<&str>| {
+            let env = HashMap::from([("GREETING".to_string(), "hi".to_string())]);
+            let task = shell(id, script).with_env(env);
+            match executor {
+                Some(executor) => task.with_executor(executor),
+                None => task,
+            }
+        };
+
+        let mut builder = TaskGraphBuilder::new();
+        for executor in [Some(SHELL_EXECUTOR), None] {
+            let name = executor.unwrap_or("default");
+            builder.add_task(task(
+                &format!("{name}-env"),
+                r#"test "$GREETING" = hi"#,
+                executor,
+            ));
+            builder.add_task(task(&format!("{name}-exit"), "exit 3", executor));
+        }
+        let mut graph = builder.build().unwrap();
+
+        let mut reports = Executor::new(2)
+            .run(&mut graph)
+            .into_iter()
+            .map(|r| (r.id, r.status, r.exit_code))
+            .collect::<Vec<_>>();
+        reports.sort_by(|a, b| a.0.cmp(&b.0));
+
+        assert_eq!(
+            reports,
+            [
+                ("default-env".into(), TaskStatus::Succeeded, Some(0)),
+                ("default-exit".into(), TaskStatus::Failed, Some(3)),
+                ("shell-env".into(), TaskStatus::Succeeded, Some(0)),
+                ("shell-exit".into(), TaskStatus::Failed, Some(3)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cleanup() {
+        let dir = std::env::temp_dir().join(format!("nx-rs-cleanup-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let with_cleanup = |id: &str, body: &str| {
+            let marker = dir.join(id);
+            let sh = |script: &str| {
+                Action::shell(vec!["sh".to_string(), "-c".to_string(), script.to_string()])
+            };
+            let action = sh(body).with_cleanup(sh(&format!("touch '{}'", marker.display())));
+            Task::new(id, id, action)
+        };
+
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(with_cleanup("passing", "true"));
+        builder.add_task(with_cleanup("failing", "exit 3"));
+        builder.add_task(Task::new(
+            "bad-cleanup",
+            "bad-cleanup",
+            Action::shell(vec!["true".to_string()])
+                .with_cleanup(Action::shell(vec!["false".to_string()])),
+        ));
+        let mut graph = builder.build().unwrap();
+
+        let reports = Executor::new(2).run(&mut graph);
+        let report = |id: &str| reports.iter().find(|r| r.id == id).unwrap();
+
+        assert_eq!(report("passing").status, TaskStatus::Succeeded);
+        assert_eq!(report("failing").status, TaskStatus::Failed);
+        assert_eq!(
+            report("failing").exit_code,
+            Some(3),
+            "The body's status is kept"
+        );
+        assert!(
+            dir.join("passing").exists(),
+            "Cleanup should run on success"
+        );
+        assert!(
+            dir.join("failing").exists(),
+            "Cleanup should run on failure"
+        );
+        assert_eq!(report("passing").cleanup_error, None);
+        assert_eq!(report("bad-cleanup").status, TaskStatus::Succeeded);
+        assert!(report("bad-cleanup")
+            .cleanup_error
+            .as_deref()
+            .is_some_and(|e| e.starts_with("the cleanup failed with")));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_termination_reason() {
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(shell("killed", "kill -9 $$"));
+        builder.add_task(shell("exited", "exit 137"));
+        let mut graph = builder.build().unwrap();
+
+        let reports = Executor::new(2).run(&mut graph);
+        let report = |id: &str| reports.iter().find(|r| r.id == id).unwrap();
+
+        assert_eq!(
+            report("killed").termination,
+            Some(TerminationReason::Signaled { signal: 9 })
+        );
+        assert_eq!(report("killed").exit_code, None);
+        assert_eq!(
+            report("exited").termination,
+            Some(TerminationReason::Exited { code: 137 })
+        );
+
+        let json = RunResult::from_reports(&reports).to_json().unwrap();
+        assert!(json.contains(r#""termination":{"kind":"signaled","signal":9}"#));
+    }
+
+    /// The (scope, key) pairs of a `MemoryCache`
+    type ScopedKeys = Vec<(Option<String>, String)>;
+
+    /// A cache in memory, shared with the test
+    #[derive(Clone, Default)]
+    struct MemoryCache(Arc<Mutex<ScopedKeys>>);
+
+    impl CacheBackend for MemoryCache {
+        fn contains(&self, scope: Option<&str>, key: &str) -> Result<bool> {
+            Ok(self
+                .0
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|(s, k)| s.as_deref() == scope && k == key))
+        }
+
+        fn store(&self, scope: Option<&str>, key: &str) -> Result<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .push((scope.map(str::to_string), key.to_string()));
+            Ok(())
+        }
+
+        fn invalidate_scope(&self, scope: &str) -> Result<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .retain(|(s, _)| s.as_deref() != Some(scope));
+            Ok(())
+        }
+    }
+
+    /// A cache that can't store anything.
+    struct ReadOnlyCache;
+
+    impl CacheBackend for ReadOnlyCache {
+        fn contains(&self, _scope: Option<&str>, _key: &str) -> Result<bool> {
+            Ok(false)
+        }
+
+        fn store(&self, _scope: Option<&str>, _key: &str) -> Result<()> {
+            bail!("the cache is read-only")
+        }
+
+        fn invalidate_scope(&self, _scope: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_cache_store_error() {
+        let mut executor = Executor::new(1);
+        executor.capture_output();
+        executor.enable_cache(Path::new("."), ReadOnlyCache);
+
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(shell("a", "echo a"));
+        let reports = executor.run(&mut builder.build().unwrap());
+
+        assert_eq!(reports[0].status, TaskStatus::Succeeded);
+        assert_eq!(
+            reports[0].cache_error.as_deref(),
+            Some("the cache is read-only")
+        );
+    }
+
+    #[test]
+    fn test_cache_events() {
+        let cache = MemoryCache::default();
+        let events = Arc::new(Mutex::new(vec![]));
+        let mut executor = Executor::new(1);
+        executor.capture_output();
+        executor.enable_cache(Path::new("."), cache.clone());
+        let sink = events.clone();
+        executor.on_cache_event(move |e| sink.lock().unwrap().push(e.clone()));
+
+        let run = |executor: &Executor, script: &str| {
+            let mut builder = TaskGraphBuilder::new();
+            builder.add_task(shell("a", script));
+            builder.add_task(shell("b", "exit 1"));
+            return executor.run(&mut builder.build().unwrap());
+        };
+
+        let reports = run(&executor, "echo a");
+        assert!(reports.iter().all(|r| r.attempts == 1));
+
+        // Only the successful task was cached
+        let reports = run(&executor, "echo a");
+        let a = reports.iter().find(|r| r.id == "a").unwrap();
+        assert_eq!(a.status, TaskStatus::Succeeded);
+        assert_eq!(a.attempts, 0);
+        assert_eq!(cache.0.lock().unwrap().len(), 1);
+
+        // A changed command changes the key
+        run(&executor, "echo changed");
+
+        let key = |script: &str| shell("a", script).cache_key(Path::new(".")).unwrap();
+        let mut events = events.lock().unwrap().clone();
+        events.sort_by_key(|e| match e {
+            CacheEvent::Hit { id, .. } | CacheEvent::Miss { id, .. } => id.clone(),
+        });
+        let a = |hit: bool, script: &str| {
+            let (id, key) = ("a".to_string(), key(script));
+            if hit {
+                CacheEvent::Hit { id, key }
+            } else {
+                CacheEvent::Miss { id, key }
+            }
+        };
+        assert_eq!(
+            events[..3],
+            [
+                a(false, "echo a"),
+                a(true, "echo a"),
+                a(false, "echo changed")
+            ]
+        );
+        assert!(events[3..]
+            .iter()
+            .all(|e| matches!(e, CacheEvent::Miss { id, .. } if id == "b")));
+    }
+
+    #[test]
+    fn test_pools() {
+        /// Records the most tasks it ran at once. Its tasks wait for each
+        /// other in groups of the pool's limit, so the limit is reached
+        struct Probe {
+            running: AtomicUsize,
+            max: Arc<AtomicUsize>,
+            barrier: std::sync::Barrier,
+        }
+
+        impl Probe {
+            fn new(limit: usize) -> Self {
+                Probe {
+                    running: AtomicUsize::new(0),
+                    max: Arc::default(),
+                    barrier: std::sync::Barrier::new(limit),
+                }
+            }
+        }
+
+        impl TaskExecutor for Probe {
+            fn execute(&self, _: &TaskContext) -> Result<ExitStatus> {
+                let running = self.running.fetch_add(1, AtomicOrdering::SeqCst) + 1;
+                self.max.fetch_max(running, AtomicOrdering::SeqCst);
+                self.barrier.wait();
+                self.running.fetch_sub(1, AtomicOrdering::SeqCst);
+                return Ok(exit_status(0));
+            }
+        }
+
+        let mut builder = TaskGraphBuilder::new();
+        for i in 0..6 {
+            for pool in ["network", "cpu"] {
+                let id = format!("{pool}-{i}");
+                builder.add_task(
+                    Task::new(&id, &id, Action::shell(vec![]))
+                        .with_executor(pool)
+                        .with_pools(vec![pool.to_string()]),
+                );
+            }
+        }
+        let mut graph = builder.build().unwrap();
+
+        // Both limits divide the 6 tasks of their pool, so no group is short
+        let (network, cpu) = (Probe::new(2), Probe::new(3));
+        let (max_network, max_cpu) = (network.max.clone(), cpu.max.clone());
+        let mut executor = Executor::new(8);
+        executor.pool_limit("network", 2);
+        executor.pool_limit("cpu", 3);
+        executor.register_executor("network", network);
+        executor.register_executor("cpu", cpu);
+        let reports = executor.run(&mut graph);
+
+        assert!(reports.iter().all(|r| r.status == TaskStatus::Succeeded));
+        assert_eq!(max_network.load(AtomicOrdering::SeqCst), 2);
+        assert_eq!(max_cpu.load(AtomicOrdering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_cache_scope() {
+        let cache = MemoryCache::default();
+        let mut executor = Executor::new(2);
+        executor.capture_output();
+        executor.enable_cache(Path::new("."), cache.clone());
+
+        let run = |executor: &Executor| {
+            let mut builder = TaskGraphBuilder::new();
+            builder.add_task(shell("a", "echo a").with_cache_scope("gen"));
+            builder.add_task(shell("b", "echo b").with_cache_scope("gen"));
+            builder.add_task(shell("c", "echo c"));
+            let mut attempts = executor
+                .run(&mut builder.build().unwrap())
+                .into_iter()
+                .map(|r| (r.id, r.attempts))
+                .collect::<Vec<(TaskID, u32)>>();
+            attempts.sort();
+            return attempts;
+        };
+
+        run(&executor);
+        let scopes = cache
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(s, _)| s.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            scopes
+                .iter()
+                .filter(|s| s.as_deref() == Some("gen"))
+                .count(),
+            2
+        );
+        assert_eq!(
+            run(&executor),
+            [("a".into(), 0), ("b".into(), 0), ("c".into(), 0)]
+        );
+
+        cache.invalidate_scope("gen").unwrap();
+        assert_eq!(
+            run(&executor),
+            [("a".into(), 1), ("b".into(), 1), ("c".into(), 0)]
+        );
+    }
+
+    #[test]
+    fn test_cache_restores_outputs() {
+        let dir = std::env::temp_dir().join(format!("nx-rs-restore-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let root = dir.join("root");
+        std::fs::create_dir_all(&root).unwrap();
+
+        let mut executor = Executor::new(1);
+        executor.enable_cache(&root, LocalCache::new(dir.join("cache")));
+        let script = format!(
+            "mkdir -p {0}/dist && echo built > {0}/dist/out.txt",
+            root.display()
+        );
+        let run = |outputs: &[&str]| {
+            let mut builder = TaskGraphBuilder::new();
+            builder.add_task(
+                shell("a", &script).with_outputs(outputs.iter().map(PathBuf::from).collect()),
+            );
+            return executor.run(&mut builder.build().unwrap())[0].attempts;
+        };
+
+        assert_eq!(run(&["dist/out.txt"]), 1);
+        std::fs::remove_dir_all(root.join("dist")).unwrap();
+        assert_eq!(run(&["dist/out.txt"]), 0, "The task should be a hit");
+        assert_eq!(
+            std::fs::read_to_string(root.join("dist/out.txt")).unwrap(),
+            "built\n"
+        );
+
+        // The outputs were defined differently when cached
+        assert_eq!(run(&["dist"]), 1);
+        assert_eq!(run(&["dist"]), 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_process_runner() {
+        let mut builder = TaskGraphBuilder::new();
+        for id in ["a", "b", "c", "d"] {
+            builder.add_task(Task::new(id, id, Action::shell(vec![id.to_string()])));
+        }
+        builder.add_dependency("a".to_string(), "b".to_string());
+        builder.add_dependency("b".to_string(), "c".to_string());
+        builder.add_dependency("d".to_string(), "a".to_string());
+        let mut graph = builder.build().unwrap();
+
+        let runner = Arc::new(MockRunner::new().with_exit_code("a", 1));
+        let mut executor = Executor::new(1);
+        executor.process_runner(runner.clone());
+        executor.run(&mut graph);
+
+        assert_eq!(runner.commands(), [["c"], ["b"], ["a"]]);
+        assert_eq!(graph.status(&"a".to_string()), Some(TaskStatus::Failed));
+        assert_eq!(graph.status(&"d".to_string()), Some(TaskStatus::Skipped));
+    }
+}
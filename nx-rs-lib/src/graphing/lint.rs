@@ -0,0 +1,172 @@
+use super::types::{TaskGraph, TaskID};
+use crate::project::types::Severity;
+
+/// What a lint is about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphLintKind {
+    /// The task has no dependencies and nothing depends on it, which may be
+    /// fine or forgotten wiring
+    Isolated,
+    /// The task ends a chain of dependencies longer than the threshold, which
+    /// runs one task at a time
+    /// # Arguments
+    /// * `length` - The number of tasks in the longest chain, the task
+    ///   included
+    LongChain { length: usize },
+    /// More tasks than the threshold directly depend on the task, so it holds
+    /// all of them up
+    /// # Arguments
+    /// * `dependents` - The number of tasks depending on it
+    Hotspot { dependents: usize },
+}
+
+/// An issue with the shape of a graph, see `TaskGraph::lint`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphLint {
+    pub task: TaskID,
+    pub kind: GraphLintKind,
+    /// How much the lint matters, `Info` or `Warning`. Lints are advisory,
+    /// none of them breaks a run
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// The thresholds of `TaskGraph::lint_with`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LintThresholds {
+    /// The longest chain of tasks allowed, in tasks
+    pub max_chain_length: usize,
+    /// The most tasks allowed to directly depend on a single task
+    pub max_dependents: usize,
+}
+
+impl Default for LintThresholds {
+    fn default() -> Self {
+        LintThresholds {
+            max_chain_length: 10,
+            max_dependents: 20,
+        }
+    }
+}
+
+impl TaskGraph {
+    /// Reports the issues with the shape of the graph, with the default
+    /// thresholds. See `lint_with`.
+    pub fn lint(&self) -> Vec<GraphLint> {
+        return self.lint_with(&LintThresholds::default());
+    }
+
+    /// Reports the issues with the shape of the graph, e.g. to keep large
+    /// graphs healthy. Unlike cycles and dangling dependencies, which fail the
+    /// build, these are advisory.
+    /// # Arguments
+    /// * `thresholds` - Past which chains and dependents are reported
+    ///
+    /// # Returns
+    /// * `Vec<GraphLint>` - The lints, sorted by task
+    pub fn lint_with(&self, thresholds: &LintThresholds) -> Vec<GraphLint> {
+        let mut task_ids = self
+            .statuses()
+            .keys()
+            .map(|id| id.to_string())
+            .collect::<Vec<TaskID>>();
+        task_ids.sort();
+        let levels = self.levels();
+
+        let mut lints: Vec<GraphLint> = vec![];
+        for task_id in task_ids {
+            let dependencies = self.dependencies(&task_id).len();
+            let dependents = self.dependents(&task_id).len();
+
+            // A single task has nothing to be wired to
+            if dependencies == 0 && dependents == 0 && self.statuses().len() > 1 {
+                lints.push(GraphLint {
+                    message: format!("`{task_id}` has no dependencies and no dependents"),
+                    task: task_id.clone(),
+                    kind: GraphLintKind::Isolated,
+                    severity: Severity::Info,
+                });
+            }
+
+            // Only reported at the end of the chain, not for every task in it
+            let length = levels.get(task_id.as_str()).copied().unwrap_or_default() + 1;
+            if dependents == 0 && length > thresholds.max_chain_length {
+                lints.push(GraphLint {
+                    message: format!(
+                        "`{task_id}` ends a chain of {length} tasks, more than {}",
+                        thresholds.max_chain_length
+                    ),
+                    task: task_id.clone(),
+                    kind: GraphLintKind::LongChain { length },
+                    severity: Severity::Warning,
+                });
+            }
+
+            if dependents > thresholds.max_dependents {
+                lints.push(GraphLint {
+                    message: format!(
+                        "{dependents} tasks depend on `{task_id}`, more than {}",
+                        thresholds.max_dependents
+                    ),
+                    task: task_id,
+                    kind: GraphLintKind::Hotspot { dependents },
+                    severity: Severity::Warning,
+                });
+            }
+        }
+
+        return lints;
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::graphing::types::{Action, Task, TaskGraphBuilder};
+
+    fn task(id: &str) -> Task {
+        Task::new(id, id, Action::shell(vec!["true".to_string()]))
+    }
+
+    #[test]
+    fn test_lint() {
+        let mut builder = TaskGraphBuilder::new();
+        for id in ["a", "b", "c", "d", "lonely"] {
+            builder.add_task(task(id));
+        }
+        // A chain a -> b -> c, and d also depending on c
+        builder.add_dependency("a".to_string(), "b".to_string());
+        builder.add_dependency("b".to_string(), "c".to_string());
+        builder.add_dependency("d".to_string(), "c".to_string());
+        let graph = builder.build().unwrap();
+
+        assert_eq!(
+            graph.lint(),
+            vec![GraphLint {
+                task: "lonely".to_string(),
+                kind: GraphLintKind::Isolated,
+                severity: Severity::Info,
+                message: "`lonely` has no dependencies and no dependents".to_string(),
+            }]
+        );
+
+        let thresholds = LintThresholds {
+            max_chain_length: 2,
+            max_dependents: 1,
+        };
+        let lints = graph
+            .lint_with(&thresholds)
+            .into_iter()
+            .map(|l| (l.task, l.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lints,
+            vec![
+                ("a".to_string(), GraphLintKind::LongChain { length: 3 }),
+                ("c".to_string(), GraphLintKind::Hotspot { dependents: 2 }),
+                ("lonely".to_string(), GraphLintKind::Isolated),
+            ]
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use anyhow::{bail, Result};
+
+use super::executor::NOOP_EXECUTOR;
+use super::types::{Action, Task, TaskGraph, TaskGraphBuilder, TaskID};
+
+/// A target of a Makefile, before it is turned into a task.
+struct Rule {
+    name: TaskID,
+    dependencies: Vec<TaskID>,
+    commands: Vec<String>,
+}
+
+impl TaskGraphBuilder {
+    /// Builds a graph from a Makefile-like text, a lighter config than JSON
+    /// for simple cases:
+    /// ```text
+    /// # Comments start with `#`
+    /// build: codegen
+    ///     cargo build
+    ///
+    /// codegen:
+    ///     ./codegen.sh
+    ///     cargo fmt
+    /// ```
+    /// Each `target: dep1 dep2` line is a task depending on the listed
+    /// targets, which may be defined later. The target ends at the first
+    /// colon followed by whitespace or the end of the line, so names may
+    /// contain colons, e.g. `app:build: lib:build`. The lines indented with
+    /// tabs or spaces after it are its commands, run in order by `sh -ec` so
+    /// the first failing one fails the task. Targets without commands run
+    /// nothing, with the `NOOP_EXECUTOR`, and succeed once their dependencies
+    /// do.
+    /// Blank lines and lines starting with `#` are skipped, and a `#` starts
+    /// a comment anywhere in a target line. In command lines it is left to
+    /// the shell.
+    pub fn from_makefile(src: &str) -> Result<TaskGraph> {
+        let mut rules: Vec<Rule> = vec![];
+
+        for (i, line) in src.lines().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with([' ', '\t']) {
+                let Some(rule) = rules.last_mut() else {
+                    bail!("line {}: command outside of a target", i + 1);
+                };
+                rule.commands.push(trimmed.to_string());
+                continue;
+            }
+
+            let rule_line = match line.find('#') {
+                Some(comment) => &line[..comment],
+                None => line,
+            };
+            let separator = rule_line.match_indices(':').find(|(at, _)| {
+                rule_line[at + 1..]
+                    .chars()
+                    .next()
+                    .is_none_or(char::is_whitespace)
+            });
+            let Some((at, _)) = separator else {
+                bail!("line {}: expected `target: dependencies`", i + 1);
+            };
+            let (name, dependencies) = (&rule_line[..at], &rule_line[at + 1..]);
+
+            let name = name.trim();
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                bail!("line {}: invalid target name `{}`", i + 1, name);
+            }
+            if rules.iter().any(|r| r.name == name) {
+                bail!("line {}: target `{}` is defined twice", i + 1, name);
+            }
+
+            rules.push(Rule {
+                name: name.to_string(),
+                dependencies: dependencies
+                    .split_whitespace()
+                    .map(|d| d.to_string())
+                    .collect(),
+                commands: vec![],
+            });
+        }
+
+        let mut builder = TaskGraphBuilder::new();
+        for rule in rules {
+            let task = match rule.commands.is_empty() {
+                true => Task::new(&rule.name, &rule.name, Action::shell(vec![]))
+                    .with_executor(NOOP_EXECUTOR),
+                false => {
+                    let action = Action::shell(vec![
+                        "sh".to_string(),
+                        "-ec".to_string(),
+                        rule.commands.join("\n"),
+                    ]);
+                    Task::new(&rule.name, &rule.name, action)
+                }
+            };
+            builder.add_task(task);
+            for dependency in rule.dependencies {
+                builder.add_dependency(rule.name.clone(), dependency);
+            }
+        }
+
+        return Ok(builder.build()?);
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::graphing::executor::Executor;
+    use crate::graphing::types::TaskStatus;
+
+    #[test]
+    fn test_from_makefile() {
+        let src = "
+# The default target
+all: build docs  # both
+
+build: codegen
+    cargo build
+    cargo test
+
+codegen:
+\t./codegen.sh # keeps the comment
+
+docs:
+";
+        let mut graph = TaskGraphBuilder::from_makefile(src).unwrap();
+        assert_eq!(graph.remaining(), 4);
+        let mut deps = graph.dependencies(&"all".to_string());
+        deps.sort();
+        assert_eq!(deps, vec!["build", "docs"]);
+
+        let mut actions = vec![];
+        while let Some(task) = graph.next() {
+            let task = task.unwrap();
+            graph.done(task.id());
+            let script = match task.executor() {
+                Some(executor) => format!("<{executor}>"),
+                None => task.action().cmd().unwrap()[2].clone(),
+            };
+            actions.push((task.id().clone(), script));
+        }
+        actions.sort();
+        assert_eq!(
+            actions,
+            vec![
+                ("all".to_string(), "<noop>".to_string()),
+                ("build".to_string(), "cargo build\ncargo test".to_string()),
+                (
+                    "codegen".to_string(),
+                    "./codegen.sh # keeps the comment".to_string()
+                ),
+                ("docs".to_string(), "<noop>".to_string()),
+            ]
+        );
+
+        let error = |src: &str| {
+            TaskGraphBuilder::from_makefile(src)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("\tcargo build"),
+            "line 1: command outside of a target"
+        );
+        assert_eq!(error("build"), "line 1: expected `target: dependencies`");
+        assert_eq!(
+            error("build:codegen"),
+            "line 1: expected `target: dependencies`"
+        );
+        assert_eq!(error("a:\nb:\na:"), "line 3: target `a` is defined twice");
+        assert_eq!(
+            error("a: missing"),
+            "task `a` depends on unknown task `missing`"
+        );
+    }
+
+    #[test]
+    fn test_from_makefile_colons() {
+        let src = "
+app:build: lib:build gen
+\ttrue
+lib:build:
+\ttrue
+gen:
+";
+        let mut graph = TaskGraphBuilder::from_makefile(src).unwrap();
+        let mut deps = graph.dependencies(&"app:build".to_string());
+        deps.sort();
+        assert_eq!(deps, vec!["gen", "lib:build"]);
+
+        let reports = Executor::new(1).run(&mut graph);
+        assert_eq!(reports.len(), 3);
+        assert!(reports.iter().all(|r| r.status == TaskStatus::Succeeded));
+    }
+}
@@ -1 +1,10 @@
+pub mod cache;
+pub mod dot;
+pub mod dotenv;
+pub mod executor;
+pub mod lint;
+pub mod makefile;
+pub mod report;
+pub mod runner;
+pub mod shared;
 pub mod types;
@@ -0,0 +1,341 @@
+use std::{
+    fmt::Write,
+    process::ExitStatus,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use super::types::{TaskID, TaskStatus};
+
+/// The version of the `RunResult` JSON format.
+/// Bump it whenever a field is renamed, removed or changes meaning.
+pub const RUN_RESULT_SCHEMA_VERSION: u32 = 1;
+
+/// The error of the reports of tasks stopped or never started because the
+/// run went past its total timeout, see `Executor::total_timeout`.
+pub const TIMED_OUT: &str = "the run timed out";
+
+/// The outcome of a single task in a run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskReport {
+    pub id: TaskID,
+    pub status: TaskStatus,
+    /// How long the task ran for. Zero for tasks that never started
+    pub duration: Duration,
+    /// The exit code of the process, if it exited normally
+    pub exit_code: Option<i32>,
+    /// The captured stdout and stderr. Empty unless output is captured
+    pub output: Vec<u8>,
+    /// Why the task could not be run, if it could not
+    pub error: Option<String>,
+    /// How many times the task was run, including retries
+    pub attempts: u32,
+    /// How the task's process ended, `None` if it never ran
+    pub termination: Option<TerminationReason>,
+    /// Why the hook run after the task failed, if it did, see
+    /// `Task::with_on_success`
+    pub hook_error: Option<String>,
+    /// Why the cleanup of the task's action failed, if it did, see
+    /// `Action::with_cleanup`. The outcome of the task is the one of its body
+    pub cleanup_error: Option<String>,
+    /// Why the task could not be cached after succeeding, if it could not.
+    /// The task still succeeded, it only runs again next time
+    pub cache_error: Option<String>,
+    /// Whether the task was cancelled, or never started, because the run
+    /// reached its total timeout, see `Executor::total_timeout`
+    pub timed_out: bool,
+}
+
+/// How a process ended.
+/// Tells a process killed by a signal, e.g. by the OOM killer, apart from one
+/// that exited with the code a shell reports for it, e.g. 137.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum TerminationReason {
+    /// The process exited with a code
+    Exited { code: i32 },
+    /// The process was killed by a signal. Unix only
+    Signaled { signal: i32 },
+    /// The platform did not say
+    Unknown,
+}
+
+impl From<&ExitStatus> for TerminationReason {
+    fn from(status: &ExitStatus) -> Self {
+        if let Some(code) = status.code() {
+            return TerminationReason::Exited { code };
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            if let Some(signal) = status.signal() {
+                return TerminationReason::Signaled { signal };
+            }
+        }
+
+        return TerminationReason::Unknown;
+    }
+}
+
+impl TaskReport {
+    /// A report for a task that never started.
+    pub fn not_run(id: &TaskID, status: TaskStatus) -> Self {
+        TaskReport {
+            id: id.clone(),
+            status,
+            duration: Duration::ZERO,
+            exit_code: None,
+            output: vec![],
+            error: None,
+            attempts: 0,
+            termination: None,
+            hook_error: None,
+            cleanup_error: None,
+            cache_error: None,
+            timed_out: false,
+        }
+    }
+}
+
+/// A task lifecycle event of a run, streamed as one JSON object per line by
+/// `Executor::emit_jsonl`, e.g.
+/// `{"event":"finished","id":"app:build","timestamp_ms":1700000000000,"exit_code":0}`.
+/// The live companion of `RunResult`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum RunEvent {
+    /// The task was started
+    Started { id: TaskID, timestamp_ms: u64 },
+    /// The task succeeded
+    Finished {
+        id: TaskID,
+        timestamp_ms: u64,
+        exit_code: Option<i32>,
+    },
+    /// The task failed, or could not be run
+    Failed {
+        id: TaskID,
+        timestamp_ms: u64,
+        exit_code: Option<i32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+impl RunEvent {
+    pub fn started(id: &TaskID) -> Self {
+        RunEvent::Started {
+            id: id.clone(),
+            timestamp_ms: now_ms(),
+        }
+    }
+
+    /// The event for a task that ran, `Finished` or `Failed` by its status.
+    pub fn ended(report: &TaskReport) -> Self {
+        if report.status == TaskStatus::Succeeded {
+            return RunEvent::Finished {
+                id: report.id.clone(),
+                timestamp_ms: now_ms(),
+                exit_code: report.exit_code,
+            };
+        }
+
+        return RunEvent::Failed {
+            id: report.id.clone(),
+            timestamp_ms: now_ms(),
+            exit_code: report.exit_code,
+            error: report.error.clone(),
+        };
+    }
+}
+
+/// The milliseconds since the Unix epoch.
+fn now_ms() -> u64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+}
+
+/// The machine-readable outcome of a whole run, e.g. for CI annotations.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunResult {
+    /// See `RUN_RESULT_SCHEMA_VERSION`
+    pub schema_version: u32,
+    /// The result of every task, sorted by ID
+    pub tasks: Vec<TaskResult>,
+    /// Whether the run was stopped by its total timeout, so the results are
+    /// partial
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub timed_out: bool,
+}
+
+/// The serialized outcome of a single task.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TaskResult {
+    pub id: TaskID,
+    pub status: TaskStatus,
+    pub duration_ms: u64,
+    pub exit_code: Option<i32>,
+    /// The length of the captured output, in bytes
+    pub output_bytes: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub termination: Option<TerminationReason>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hook_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cleanup_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_error: Option<String>,
+}
+
+impl RunResult {
+    /// Creates the result of a run from the reports of its tasks.
+    /// # Arguments
+    /// * `reports` - The reports produced by `Executor::run`
+    pub fn from_reports(reports: &[TaskReport]) -> Self {
+        let mut tasks = reports
+            .iter()
+            .map(|r| TaskResult {
+                id: r.id.clone(),
+                status: r.status,
+                duration_ms: r.duration.as_millis() as u64,
+                exit_code: r.exit_code,
+                output_bytes: r.output.len(),
+                error: r.error.clone(),
+                termination: r.termination,
+                hook_error: r.hook_error.clone(),
+                cleanup_error: r.cleanup_error.clone(),
+                cache_error: r.cache_error.clone(),
+            })
+            .collect::<Vec<TaskResult>>();
+        tasks.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let timed_out = reports.iter().any(|r| r.timed_out);
+
+        RunResult {
+            schema_version: RUN_RESULT_SCHEMA_VERSION,
+            tasks,
+            timed_out,
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    /// Formats the metrics of the run in the Prometheus text format, e.g. for
+    /// a CI scraping build metrics:
+    /// * `nxrs_task_duration_seconds`, a gauge of how long each task that ran
+    ///   took, labelled with the task ID
+    /// * `nxrs_tasks_total`, and `nxrs_tasks_<status>_total` for the tasks
+    ///   that succeeded, failed or were skipped
+    /// * `nxrs_run_timed_out`, 1 if the run timed out, see `timed_out`
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP nxrs_task_duration_seconds How long the task ran for.\n");
+        out.push_str("# TYPE nxrs_task_duration_seconds gauge\n");
+        for task in &self.tasks {
+            if matches!(task.status, TaskStatus::Succeeded | TaskStatus::Failed) {
+                let seconds = task.duration_ms as f64 / 1000.0;
+                let _ = writeln!(
+                    out,
+                    "nxrs_task_duration_seconds{{task=\"{}\"}} {seconds}",
+                    escape_label(&task.id)
+                );
+            }
+        }
+
+        let count = |status: TaskStatus| self.tasks.iter().filter(|t| t.status == status).count();
+        let counters = [
+            (
+                "nxrs_tasks_total",
+                "The tasks of the run.",
+                self.tasks.len(),
+            ),
+            (
+                "nxrs_tasks_succeeded_total",
+                "The tasks that succeeded.",
+                count(TaskStatus::Succeeded),
+            ),
+            (
+                "nxrs_tasks_failed_total",
+                "The tasks that failed.",
+                count(TaskStatus::Failed),
+            ),
+            (
+                "nxrs_tasks_skipped_total",
+                "The tasks skipped, because a dependency failed or by their skip variable.",
+                count(TaskStatus::Skipped),
+            ),
+        ];
+        for (name, help, value) in counters {
+            let _ = write!(
+                out,
+                "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}\n"
+            );
+        }
+
+        out.push_str("# HELP nxrs_run_timed_out Whether the run timed out.\n");
+        out.push_str("# TYPE nxrs_run_timed_out gauge\n");
+        let _ = writeln!(out, "nxrs_run_timed_out {}", self.timed_out as u8);
+
+        return out;
+    }
+}
+
+/// Escapes a label value of the Prometheus text format.
+fn escape_label(value: &str) -> String {
+    return value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_to_prometheus() {
+        let report = |id: &str, status: TaskStatus, ms: u64| TaskReport {
+            duration: Duration::from_millis(ms),
+            ..TaskReport::not_run(&id.to_string(), status)
+        };
+        let reports = vec![
+            report("app:build", TaskStatus::Succeeded, 1500),
+            report("lib:\"odd\"", TaskStatus::Failed, 20),
+            report("app:test", TaskStatus::Skipped, 0),
+        ];
+
+        assert_eq!(
+            RunResult::from_reports(&reports).to_prometheus(),
+            r#"# HELP nxrs_task_duration_seconds How long the task ran for.
+# TYPE nxrs_task_duration_seconds gauge
+nxrs_task_duration_seconds{task="app:build"} 1.5
+nxrs_task_duration_seconds{task="lib:\"odd\""} 0.02
+# HELP nxrs_tasks_total The tasks of the run.
+# TYPE nxrs_tasks_total counter
+nxrs_tasks_total 3
+# HELP nxrs_tasks_succeeded_total The tasks that succeeded.
+# TYPE nxrs_tasks_succeeded_total counter
+nxrs_tasks_succeeded_total 1
+# HELP nxrs_tasks_failed_total The tasks that failed.
+# TYPE nxrs_tasks_failed_total counter
+nxrs_tasks_failed_total 1
+# HELP nxrs_tasks_skipped_total The tasks skipped, because a dependency failed or by their skip variable.
+# TYPE nxrs_tasks_skipped_total counter
+nxrs_tasks_skipped_total 1
+# HELP nxrs_run_timed_out Whether the run timed out.
+# TYPE nxrs_run_timed_out gauge
+nxrs_run_timed_out 0
+"#
+        );
+    }
+}
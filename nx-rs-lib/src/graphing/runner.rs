@@ -0,0 +1,83 @@
+use std::{
+    process::{Command, ExitStatus},
+    sync::Mutex,
+};
+
+use anyhow::Result;
+
+/// Runs the processes of actions, see `Action::run_with` and
+/// `Executor::process_runner`. Replaced by a `MockRunner` in tests, so they
+/// don't spawn real processes, or to simulate a run.
+pub trait ProcessRunner: Send + Sync {
+    /// Runs a process and waits for it to exit, with the runner's stdout and
+    /// stderr.
+    fn run(&self, command: &mut Command) -> Result<ExitStatus>;
+}
+
+/// Spawns the processes for real.
+pub struct SystemRunner;
+
+impl ProcessRunner for SystemRunner {
+    fn run(&self, command: &mut Command) -> Result<ExitStatus> {
+        return Ok(command.status()?);
+    }
+}
+
+/// Records the processes instead of running them. Every process "exits" with
+/// the code configured for its program, 0 by default.
+#[derive(Default)]
+pub struct MockRunner {
+    /// The program followed by its arguments, of every process run
+    commands: Mutex<Vec<Vec<String>>>,
+    /// The exit codes of the programs that don't exit with 0
+    exit_codes: Vec<(String, i32)>,
+}
+
+impl MockRunner {
+    pub fn new() -> Self {
+        MockRunner::default()
+    }
+
+    /// Make the processes of a program exit with the given code.
+    pub fn with_exit_code(mut self, program: &str, code: i32) -> Self {
+        self.exit_codes.push((program.to_string(), code));
+        self
+    }
+
+    /// Returns the program followed by its arguments of every process run so
+    /// far, in the order they were run.
+    pub fn commands(&self) -> Vec<Vec<String>> {
+        self.commands.lock().unwrap().clone()
+    }
+}
+
+impl ProcessRunner for MockRunner {
+    fn run(&self, command: &mut Command) -> Result<ExitStatus> {
+        let program = command.get_program().to_string_lossy().to_string();
+        let code = self
+            .exit_codes
+            .iter()
+            .find(|(p, _)| *p == program)
+            .map_or(0, |(_, code)| *code);
+
+        let mut args = vec![program];
+        args.extend(command.get_args().map(|a| a.to_string_lossy().to_string()));
+        self.commands.lock().unwrap().push(args);
+
+        return Ok(exit_status(code));
+    }
+}
+
+/// An exit status with the given code, as if a process exited with it.
+#[cfg(unix)]
+pub(crate) fn exit_status(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    return ExitStatus::from_raw(code << 8);
+}
+
+/// An exit status with the given code, as if a process exited with it.
+#[cfg(windows)]
+pub(crate) fn exit_status(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    return ExitStatus::from_raw(code as u32);
+}
@@ -0,0 +1,133 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use super::types::{Task, TaskGraph, TaskID, TaskStatus};
+
+/// A task graph shared between threads, e.g. by the workers of a custom
+/// runner each looping on `claim_next`. Clones share the same graph.
+#[derive(Clone)]
+pub struct SharedTaskGraph {
+    /// The graph, and the condition notified whenever a task finishes
+    inner: Arc<(Mutex<TaskGraph>, Condvar)>,
+}
+
+impl SharedTaskGraph {
+    pub fn new(graph: TaskGraph) -> Self {
+        SharedTaskGraph {
+            inner: Arc::new((Mutex::new(graph), Condvar::new())),
+        }
+    }
+
+    /// Claims the next ready task, marking it as running. Waits for the
+    /// running tasks to finish while none is ready, so every task is claimed
+    /// by a single worker.
+    /// # Returns
+    /// * `Option<Task>` - The task, or `None` once no task is left to claim
+    pub fn claim_next(&self) -> Option<Task> {
+        let (graph, finished) = &*self.inner;
+        let mut graph = graph.lock().unwrap();
+        loop {
+            match graph.next() {
+                None => return None,
+                Some(Some(task)) => return Some(task),
+                Some(None) => {
+                    // Nothing running could make a remaining task ready
+                    let running = graph.statuses().values().any(|s| *s == TaskStatus::Running);
+                    if !running {
+                        return None;
+                    }
+                    graph = finished.wait(graph).unwrap();
+                }
+            }
+        }
+    }
+
+    /// Marks a claimed task as done, see `TaskGraph::done`, waking up the
+    /// workers waiting for a task.
+    pub fn complete(&self, task_id: &TaskID) {
+        self.update(|graph| graph.done(task_id));
+    }
+
+    /// Marks a claimed task as failed, see `TaskGraph::fail`, waking up the
+    /// workers waiting for a task.
+    pub fn fail(&self, task_id: &TaskID) {
+        self.update(|graph| graph.fail(task_id));
+    }
+
+    /// Locks the graph, e.g. to read the statuses. Workers claiming or
+    /// completing tasks wait until the guard is dropped.
+    pub fn lock(&self) -> MutexGuard<'_, TaskGraph> {
+        return self.inner.0.lock().unwrap();
+    }
+
+    fn update(&self, f: impl FnOnce(&mut TaskGraph)) {
+        let (graph, finished) = &*self.inner;
+        f(&mut graph.lock().unwrap());
+        finished.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::thread;
+
+    use super::*;
+    use crate::graphing::types::{Action, TaskGraphBuilder};
+
+    fn task(id: &str) -> Task {
+        Task::new(id, id, Action::shell(vec!["true".to_string()]))
+    }
+
+    #[test]
+    fn test_shared_task_graph() {
+        let mut builder = TaskGraphBuilder::new();
+        for i in 0..20 {
+            builder.add_task(task(&format!("lib-{i}")));
+            builder.add_dependency("app".to_string(), format!("lib-{i}"));
+        }
+        builder.add_task(task("app"));
+        builder.add_task(task("codegen"));
+        builder.add_task(task("docs"));
+        builder.add_dependency("docs".to_string(), "codegen".to_string());
+        let graph = SharedTaskGraph::new(builder.build().unwrap());
+
+        let claimed: Mutex<Vec<TaskID>> = Mutex::new(vec![]);
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                let graph = graph.clone();
+                let claimed = &claimed;
+                scope.spawn(move || {
+                    while let Some(task) = graph.claim_next() {
+                        let mut claimed = claimed.lock().unwrap();
+                        if task.id() == "app" {
+                            let libs = claimed.iter().filter(|id| id.starts_with("lib-"));
+                            assert_eq!(libs.count(), 20, "`app` waits for every lib");
+                        }
+                        claimed.push(task.id().clone());
+                        drop(claimed);
+
+                        if task.id() == "codegen" {
+                            graph.fail(task.id());
+                        } else {
+                            graph.complete(task.id());
+                        }
+                    }
+                });
+            }
+        });
+
+        let mut claimed = claimed.into_inner().unwrap();
+        claimed.sort();
+        let count = claimed.len();
+        claimed.dedup();
+        assert_eq!(claimed.len(), count, "Every task is claimed once");
+        assert_eq!(count, 22, "`docs` is skipped");
+        let graph = graph.lock();
+        assert_eq!(
+            graph.status(&"app".to_string()),
+            Some(TaskStatus::Succeeded)
+        );
+        assert_eq!(graph.status(&"docs".to_string()), Some(TaskStatus::Skipped));
+        assert_eq!(graph.remaining(), 0);
+    }
+}
@@ -1,298 +1,3788 @@
 #![allow(dead_code)]
-use std::collections::{HashMap, HashSet};
+use std::{
+    borrow::Borrow,
+    cmp::Reverse,
+    collections::{BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque},
+    fmt,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+    time::{Duration, SystemTime},
+};
 
-use pathfinding::prelude::topological_sort;
+use anyhow::{bail, Result};
 
-type TaskID = String;
+use ignore::{overrides::OverrideBuilder, WalkBuilder};
+
+use super::dotenv;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use super::runner::{ProcessRunner, SystemRunner};
+
+pub type TaskID = String;
+type Edges = HashMap<TaskID, Vec<TaskID>>;
+type IdEdges = HashMap<TaskId, Vec<TaskId>>;
+
+/// The ID of a task, as used as a key inside the task graph. The public API
+/// takes and returns `TaskID`s.
+/// Borrows as a `str`, so maps keyed by it can be queried with a `&TaskID`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct TaskId(String);
+
+impl TaskId {
+    fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for TaskId {
+    fn from(id: &str) -> Self {
+        TaskId(id.to_string())
+    }
+}
+
+impl From<String> for TaskId {
+    fn from(id: String) -> Self {
+        TaskId(id)
+    }
+}
+
+impl From<TaskId> for String {
+    fn from(id: TaskId) -> Self {
+        id.0
+    }
+}
+
+impl Borrow<str> for TaskId {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq<str> for TaskId {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<TaskID> for TaskId {
+    fn eq(&self, other: &TaskID) -> bool {
+        &self.0 == other
+    }
+}
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Converts the IDs of the graph for the public API, empty if there are none.
+fn ids(ids: Option<&Vec<TaskId>>) -> Vec<TaskID> {
+    return ids.into_iter().flatten().map(TaskId::to_string).collect();
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Task {
     id: TaskID,
     name: String,
     action: Action,
+    /// Globs of the files the task reads, relative to the workspace root
+    inputs: Vec<String>,
+    /// Whether files ignored by `.gitignore` still count as inputs
+    include_ignored_inputs: bool,
+    /// Environment variables set for the task's process
+    env: HashMap<String, String>,
+    /// The only variables inherited from the runner, `None` to inherit all
+    inherit_env: Option<Vec<String>>,
+    /// A dotenv file with more variables, overridden by `env`
+    env_file: Option<PathBuf>,
+    missing_env_file: MissingEnvFile,
+    /// The build profile the task runs with, e.g. `debug` or `release`
+    profile: Option<String>,
+    /// The paths the task produces, relative to the workspace root
+    outputs: Vec<PathBuf>,
+    /// The name of the custom executor running the task, if not spawned
+    executor: Option<String>,
+    /// The estimated cost of running the task, e.g. its usual duration in
+    /// milliseconds
+    cost: u64,
+    /// Tags to select tasks by, e.g. `slow` or `flaky`
+    tags: Vec<String>,
+    /// How many more times the task is run after it fails
+    retries: u32,
+    /// The exit codes worth retrying. Empty retries any failure
+    retry_on: Vec<i32>,
+    /// The concurrency pools the task takes a slot of while it runs
+    pools: Vec<String>,
+    /// The namespace the task's results are cached in
+    cache_scope: Option<String>,
+    /// Run after the task's action when the task succeeds
+    on_success: Option<Action>,
+    /// Run after the task's action when the task fails
+    on_failure: Option<Action>,
+    /// Whether a failed hook fails the task
+    strict_hooks: bool,
 }
 
-/// Actions define different actions that a task can do.
-#[derive(Debug, Clone, PartialEq)]
-pub enum Action {
-    /// Run a shell command
-    Shell(Vec<String>),
+/// A task compared and hashed by its ID only, for sets and maps of tasks by
+/// identity. E.g. when merging graphs from several sources, the same task may
+/// come with a slightly different action or metadata, and is still the same
+/// task. Which of the copies is kept is up to the caller.
+#[derive(Debug, Clone, Copy)]
+pub struct ById<'a>(pub &'a Task);
+
+impl PartialEq for ById<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.same_id(other.0)
+    }
 }
 
-impl Action {
-    fn run(&self) {
-        match self {
-            Action::Shell(cmd) => {
-                // TODO: add support for environment variables
-                let mut child = std::process::Command::new(&cmd[0])
-                    .args(&cmd[1..])
-                    .spawn()
-                    .expect("failed to execute process");
-                child.wait().expect("failed to wait on child");
-            }
-        }
+impl Eq for ById<'_> {}
+
+impl Hash for ById<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.id.hash(state);
     }
 }
 
-/// A task graph is a directed acyclic graph (DAG) where each node is a task
-/// and each edge is a dependency.
-/// a -> b means that a depends on b. So b must be done before a.
-/// The nodes are stored in a hashmap for easy access, TaskID -> Task.
-/// The edges are stored in a hashmap for easy access, TaskID -> Vec<TaskID>.
-#[derive(Debug)]
-pub struct TaskGraph {
-    /// The tasks in the graph. Use the ID to get the task
-    tasks: HashMap<TaskID, Task>,
-    /// Edges are stored as a -> (b, c, d).
-    /// This means a depends on b, c, and d. So tasks b, c, and d must be done
-    /// before starting with a
-    edges: HashMap<TaskID, Vec<TaskID>>,
-    /// Tasks that are done.
-    done: HashSet<TaskID>,
-    /// Tasks ordered using topological sort
-    ordered_tasks: Vec<TaskID>,
+/// What to do when the env file of a task does not exist.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MissingEnvFile {
+    /// Fail the task
+    #[default]
+    Error,
+    /// Run the task without the file's variables
+    Ignore,
 }
 
-impl TaskGraph {
-    pub fn done(&mut self, task_id: &TaskID) {
-        self.done.insert(task_id.clone());
+impl Task {
+    pub fn new(id: &str, name: &str, action: Action) -> Self {
+        Task {
+            id: id.to_string(),
+            name: name.to_string(),
+            action,
+            inputs: vec![],
+            include_ignored_inputs: false,
+            env: HashMap::new(),
+            env_file: None,
+            missing_env_file: MissingEnvFile::Error,
+            profile: None,
+            outputs: vec![],
+            executor: None,
+            cost: 1,
+            tags: vec![],
+            retries: 0,
+            retry_on: vec![],
+            pools: vec![],
+            cache_scope: None,
+            inherit_env: None,
+            on_success: None,
+            on_failure: None,
+            strict_hooks: false,
+        }
     }
 
-    pub fn remaining(&self) -> usize {
-        self.ordered_tasks.len()
+    /// Set the paths the task produces, relative to the workspace root.
+    pub fn with_outputs(mut self, outputs: Vec<PathBuf>) -> Self {
+        self.outputs = outputs;
+        self
     }
-}
 
-impl Iterator for TaskGraph {
-    // Some means a task is ready to be run
-    // None means there are tasks remaining but none are ready to be run
-    type Item = Option<Task>;
+    pub fn outputs(&self) -> &[PathBuf] {
+        &self.outputs
+    }
 
-    // Has to be called manually due to returning None even when there are tasks
-    // left
-    fn next(&mut self) -> Option<Self::Item> {
-        // task candidate
-        // Later tasks should not be possible before this task
+    pub fn inputs(&self) -> &[String] {
+        &self.inputs
+    }
 
-        // loop through all the tasks to find a task that is ready to be run
-        if self.ordered_tasks.is_empty() {
-            return None;
-        }
+    /// Set the estimated cost of running the task, in any unit as long as all
+    /// the tasks use the same. Defaults to 1, so every task costs the same.
+    pub fn with_cost(mut self, cost: u64) -> Self {
+        self.cost = cost;
+        self
+    }
 
-        for i in 0..self.ordered_tasks.len() {
-            let task_id = self.ordered_tasks.get(i)?.clone();
+    pub fn cost(&self) -> u64 {
+        self.cost
+    }
 
-            // If all dependencies are done
-            if self.edges.get(&task_id).is_none()
-                || self.done.is_superset(
-                    &self
-                        .edges
-                        .get(&task_id)
-                        .unwrap()
-                        .iter()
-                        .cloned()
-                        .collect::<HashSet<TaskID>>(),
-                )
-            {
-                // return the task
-                self.ordered_tasks.remove(i);
-                return Some(Some(self.tasks.get(&task_id).unwrap().clone()));
-            }
-        }
-        return Some(None);
+    /// Set the tags of the task.
+    pub fn with_tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = tags;
+        self
     }
-}
 
-#[derive(Clone, Default)]
-pub struct TaskGraphBuilder {
-    tasks: HashMap<TaskID, Task>,
-    edges: HashMap<TaskID, Vec<TaskID>>,
-}
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
 
-impl TaskGraphBuilder {
-    pub fn new() -> Self {
-        Self::default()
+    /// Set the concurrency pools of the task, e.g. `network`. The task only
+    /// starts once every one of its pools has a free slot, see
+    /// `Executor::pool_limit`.
+    pub fn with_pools(mut self, pools: Vec<String>) -> Self {
+        self.pools = pools;
+        self
     }
 
-    pub fn add_task(&mut self, task: Task) {
-        self.edges.entry(task.id.clone()).or_insert_with(Vec::new);
-        self.tasks.insert(task.id.clone(), task);
+    pub fn pools(&self) -> &[String] {
+        &self.pools
     }
 
-    /// Add a dependency to a task
+    /// Cache the task's results in a scope shared with related tasks, e.g.
+    /// all the `test` tasks of a project, so they can be invalidated at once.
+    /// The task is still cached under its own key, see `CacheBackend`.
+    pub fn with_cache_scope(mut self, scope: &str) -> Self {
+        self.cache_scope = Some(scope.to_string());
+        self
+    }
+
+    pub fn cache_scope(&self) -> Option<&str> {
+        self.cache_scope.as_deref()
+    }
+
+    /// Retry the task when it fails.
     /// # Arguments
-    /// * `task` - The task to add a dependency to
-    /// * `dependency` - The task that the task depends on
-    pub fn add_dependency(&mut self, task: TaskID, dependency: TaskID) {
-        self.edges
-            .entry(task)
-            .or_insert_with(Vec::new)
-            .push(dependency);
+    /// * `retries` - How many more times the task is run at most
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
     }
 
-    /// Build the task graph
-    /// Also creates the ordered tasks
-    /// # Returns
-    /// * `TaskGraph` - The task graph
-    pub fn build(self) -> Result<TaskGraph, TaskID> {
-        let edges = self.edges.clone();
-        let start_edges = edges
-            .iter()
-            .filter(|(_, ts)| ts.is_empty())
-            .map(|(t, _)| t.clone())
-            .clone()
-            .collect::<Vec<TaskID>>();
-        let successors = |task_id: &TaskID| -> Vec<String> {
-            // Edges stored as a -> (b, c, d). Filter get all `a` where a -> (..., task_id, ...)
-            // Since a -> b means a depends on b. Getting the `b` first means we
-            // are getting all the dependencies first
-            edges
-                .iter()
-                .filter(|(_, ts)| ts.contains(task_id))
-                .map(|(t, _)| t.clone())
-                .clone()
-                .collect::<Vec<TaskID>>()
-        };
+    /// Only retry the task when it exits with one of the given codes, e.g. 75
+    /// for temporary failures. Other failures, and tasks killed by a signal,
+    /// fail right away. Retries any failure when empty, the default.
+    pub fn with_retry_on(mut self, codes: Vec<i32>) -> Self {
+        self.retry_on = codes;
+        self
+    }
 
-        let ordered_tasks = topological_sort(&start_edges, successors)?;
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
 
-        return Ok(TaskGraph {
-            tasks: self.tasks,
-            edges: self.edges,
-            done: HashSet::new(),
-            ordered_tasks,
-        });
+    /// Run an action after the task's one when the task succeeds, e.g. to post
+    /// a notification. See `with_strict_hooks` for when the hook fails.
+    pub fn with_on_success(mut self, hook: Action) -> Self {
+        self.on_success = Some(hook);
+        self
     }
-}
 
-#[cfg(test)]
-mod test {
+    /// Run an action after the task's one when the task fails, after its
+    /// retries, e.g. to clean a temporary directory.
+    pub fn with_on_failure(mut self, hook: Action) -> Self {
+        self.on_failure = Some(hook);
+        self
+    }
 
-    use super::*;
+    /// Set whether a failed `on_success` hook fails the task. Defaults to
+    /// `false`: the failure is reported, and the task still succeeds.
+    pub fn with_strict_hooks(mut self, strict: bool) -> Self {
+        self.strict_hooks = strict;
+        self
+    }
 
-    fn task(id: &str) -> Task {
-        Task {
-            id: id.to_string(),
-            name: id.to_string(),
-            action: Action::Shell(vec![]),
+    /// Returns the hook to run for the outcome of the task, if it has one.
+    /// # Arguments
+    /// * `succeeded` - Whether the task succeeded
+    pub fn hook(&self, succeeded: bool) -> Option<&Action> {
+        match succeeded {
+            true => self.on_success.as_ref(),
+            false => self.on_failure.as_ref(),
         }
     }
 
-    #[test]
-    fn test_graph_creation() {
-        let n_a = task("a");
-        let n_b = task("b");
-        let n_c = task("c");
-        let n_d = task("d");
+    pub fn strict_hooks(&self) -> bool {
+        self.strict_hooks
+    }
 
-        let mut builder = TaskGraphBuilder::new();
+    /// Whether a failed run of the task is worth retrying, ignoring how many
+    /// retries are left.
+    /// # Arguments
+    /// * `exit_code` - The exit code of the failed run, `None` if it has none
+    pub fn is_retryable(&self, exit_code: Option<i32>) -> bool {
+        if self.retry_on.is_empty() {
+            return true;
+        }
+        return exit_code.is_some_and(|code| self.retry_on.contains(&code));
+    }
 
-        builder.add_task(n_a.clone());
-        builder.add_task(n_b.clone());
-        builder.add_task(n_c.clone());
-        builder.add_task(n_d.clone());
+    /// Set the build profile the task runs with.
+    pub fn with_profile(mut self, profile: &str) -> Self {
+        self.profile = Some(profile.to_string());
+        self
+    }
 
-        builder.add_dependency(n_a.id.clone(), n_b.id.clone());
-        builder.add_dependency(n_a.id.clone(), n_d.id.clone());
-        builder.add_dependency(n_b.id.clone(), n_c.id.clone());
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
 
-        assert!(builder.clone().build().is_ok(), "Graph should be valid");
+    /// Computes the key the task's results are cached under.
+    /// The key changes whenever anything that could change the results does:
+    /// the command, the environment, the profile, or the content of the
+    /// inputs.
+    /// # Arguments
+    /// * `root` - The directory the input globs are relative to
+    ///
+    /// # Returns
+    /// * `String` - The key, as a hex encoded SHA-256
+    pub fn cache_key(&self, root: &Path) -> Result<String> {
+        let mut hasher = Sha256::new();
+        // Every field is length prefixed so that they can't run into each other
+        let mut update = |data: &[u8]| {
+            hasher.update((data.len() as u64).to_le_bytes());
+            hasher.update(data);
+        };
 
-        builder.add_dependency(n_d.id.clone(), n_c.id.clone());
-        assert!(
-            builder.clone().build().is_ok(),
-            "Graph should be valid with shared dependencies"
-        );
+        update(self.id.as_bytes());
+        self.action.update_key(&mut update);
 
-        builder.add_dependency(n_d.id.clone(), n_b.id.clone());
-        assert!(
-            builder.clone().build().is_ok(),
-            "Graph should be valid with multiple shared dependencies"
-        );
+        let mut env = self.env.iter().collect::<Vec<(&String, &String)>>();
+        env.sort();
+        for (key, value) in env {
+            update(key.as_bytes());
+            update(value.as_bytes());
+        }
+        if let Some(env_file) = &self.env_file {
+            update(env_file.to_string_lossy().as_bytes());
+        }
 
-        builder.add_dependency(n_b.id.clone(), n_d.id.clone());
-        assert!(
-            builder.clone().build().is_err(),
-            "Graph should be invalid with circular dependencies"
-        );
-    }
+        update(self.profile.as_deref().unwrap_or_default().as_bytes());
+        update(self.executor.as_deref().unwrap_or_default().as_bytes());
 
-    #[test]
-    fn test_get_tasks() {
-        for _ in 0..10 {
-            let n_a = task("a");
-            let n_b = task("b");
-            let n_c = task("c");
-            let n_d = task("d");
+        for input in self.resolve_inputs(root)? {
+            let relative = input.strip_prefix(root).unwrap_or(&input);
+            update(relative.to_string_lossy().as_bytes());
+            update(&std::fs::read(&input)?);
+        }
 
-            let mut builder = TaskGraphBuilder::new();
+        return Ok(hex(&hasher.finalize()));
+    }
 
-            builder.add_task(n_a.clone());
-            builder.add_task(n_b.clone());
-            builder.add_task(n_c.clone());
-            builder.add_task(n_d.clone());
+    /// Set environment variables for the task's process.
+    pub fn with_env(mut self, env: HashMap<String, String>) -> Self {
+        self.env = env;
+        self
+    }
 
-            builder.add_dependency(n_a.id.clone(), n_b.id.clone());
-            builder.add_dependency(n_a.id.clone(), n_d.id.clone());
-            builder.add_dependency(n_a.id.clone(), n_c.id.clone());
-            builder.add_dependency(n_b.id.clone(), n_c.id.clone());
+    /// Only pass the given variables through from the runner's environment,
+    /// dropping the others, e.g. to keep CI secrets away from the task. The
+    /// task's own variables are still set. Tasks inherit every variable by
+    /// default.
+    /// # Arguments
+    /// * `names` - The names of the variables to inherit, e.g. `PATH`
+    pub fn with_inherit_env(mut self, names: Vec<String>) -> Self {
+        self.inherit_env = Some(names);
+        self
+    }
 
-            let mut graph = builder.build().unwrap();
+    /// Sets the environment of the task on a process: the inherited variables
+    /// allowed by `with_inherit_env`, and the task's own variables.
+    /// Fails if the env file can't be read, unless it is missing and
+    /// configured to be ignored.
+    pub fn apply_env(&self, command: &mut Command) -> Result<()> {
+        if let Some(names) = &self.inherit_env {
+            command.env_clear();
+            for name in names {
+                if let Some(value) = std::env::var_os(name) {
+                    command.env(name, value);
+                }
+            }
+        }
+        command.envs(self.resolved_env()?);
+        return Ok(());
+    }
 
-            let first = graph.next().unwrap().unwrap();
-            assert!(
-                vec![n_c.clone(), n_d.clone()].contains(&first),
-                "First task should be c or d",
-            );
+    /// Load environment variables from a dotenv file when the task runs.
+    /// Variables set with `with_env` take precedence over the file's.
+    /// # Arguments
+    /// * `path` - The dotenv file
+    /// * `missing` - What to do if the file doesn't exist
+    pub fn with_env_file(mut self, path: PathBuf, missing: MissingEnvFile) -> Self {
+        self.env_file = Some(path);
+        self.missing_env_file = missing;
+        self
+    }
 
-            if first == n_d {
-                // first task is d, so we need to do c first
-                graph.done(&n_d.id);
-                assert_eq!(
-                    graph.next(),
-                    Some(Some(n_c.clone())),
-                    "Next task should be c"
-                );
-            } else {
-                // else first task is c, so we need to do d first
-                assert_eq!(
-                    graph.next(),
-                    Some(Some(n_d.clone())),
-                    "Next task should be d"
-                );
+    /// Creates the process for the task, with its environment.
+    /// Fails if the env file can't be read, unless it is missing and
+    /// configured to be ignored.
+    pub fn command(&self) -> Result<Command> {
+        let mut command = self.action.command()?;
+        self.apply_env(&mut command)?;
+        return Ok(command);
+    }
 
-                graph.done(&n_d.id);
+    /// Returns the variables set for the task: the ones of its env file,
+    /// overridden by its `env`.
+    /// Fails if the env file can't be read, unless it is missing and
+    /// configured to be ignored.
+    pub fn resolved_env(&self) -> Result<HashMap<String, String>> {
+        let mut env: HashMap<String, String> = HashMap::new();
+
+        if let Some(path) = &self.env_file {
+            if path.exists() || self.missing_env_file == MissingEnvFile::Error {
+                env.extend(dotenv::read(path)?);
             }
+        }
+        env.extend(self.env.clone());
 
-            assert_eq!(
-                graph.next(),
-                Some(None),
-                "There are tasks remaining, but none are ready"
-            );
+        return Ok(env);
+    }
 
-            graph.done(&n_c.id);
-            assert_eq!(
-                graph.next(),
-                Some(Some(n_b.clone())),
-                "Next task should be b"
-            );
-            graph.done(&n_b.id);
+    /// Run the task with a custom executor instead of spawning its command.
+    /// See `Executor::register_executor`.
+    /// # Arguments
+    /// * `name` - The name the executor was registered with
+    pub fn with_executor(mut self, name: &str) -> Self {
+        self.executor = Some(name.to_string());
+        self
+    }
 
-            assert_eq!(
-                graph.next(),
-                Some(Some(n_a.clone())),
-                "Final task should be a"
-            );
-            graph.done(&n_a.id);
+    pub fn executor(&self) -> Option<&str> {
+        self.executor.as_deref()
+    }
 
-            assert_eq!(
-                graph.next(),
-                None,
-                "Should return None if no tasks are ready"
-            );
+    /// Set the globs of the files the task reads.
+    pub fn with_inputs(mut self, inputs: Vec<String>) -> Self {
+        self.inputs = inputs;
+        self
+    }
 
-            // remaining tasks should be 0
-            assert_eq!(graph.remaining(), 0, "Should have no remaining tasks");
+    /// Set whether files ignored by `.gitignore` still count as inputs.
+    /// Defaults to `false`, so build artifacts like `target/` are left out.
+    pub fn include_ignored_inputs(mut self, include: bool) -> Self {
+        self.include_ignored_inputs = include;
+        self
+    }
+
+    /// Finds the files matching the task's input globs.
+    /// Unless `include_ignored_inputs` is set, files ignored by `.gitignore`
+    /// are skipped. The `.git` directory is always skipped.
+    /// # Arguments
+    /// * `root` - The directory the globs are relative to
+    ///
+    /// # Returns
+    /// * `Vec<PathBuf>` - The matching files, sorted
+    pub fn resolve_inputs(&self, root: &Path) -> Result<Vec<PathBuf>> {
+        if self.inputs.is_empty() {
+            return Ok(vec![]);
         }
+
+        let mut overrides = OverrideBuilder::new(root);
+        for input in &self.inputs {
+            overrides.add(input)?;
+        }
+
+        let respect_gitignore = !self.include_ignored_inputs;
+        let walker = WalkBuilder::new(root)
+            .hidden(false)
+            .git_ignore(respect_gitignore)
+            .git_exclude(respect_gitignore)
+            .git_global(false)
+            .ignore(false)
+            .parents(false)
+            // Honor .gitignore files even outside of a git repository
+            .require_git(false)
+            .overrides(overrides.build()?)
+            .filter_entry(|e| e.file_name() != ".git")
+            .build();
+
+        let mut files: Vec<PathBuf> = vec![];
+        for entry in walker {
+            let entry = entry?;
+            if entry.file_type().is_some_and(|t| t.is_file()) {
+                files.push(entry.into_path());
+            }
+        }
+
+        files.sort();
+        return Ok(files);
+    }
+
+    /// Whether the outputs are newer than the inputs, like `make` decides, so
+    /// the task can be skipped without hashing its inputs.
+    /// A task is not up to date if it has no outputs, or if any of them is
+    /// missing. Otherwise a task without inputs is always up to date.
+    /// # Arguments
+    /// * `root` - The directory the inputs and outputs are relative to
+    pub fn outputs_up_to_date(&self, root: &Path) -> Result<bool> {
+        if self.outputs.is_empty() {
+            return Ok(false);
+        }
+
+        let mut oldest_output: Option<SystemTime> = None;
+        for output in &self.outputs {
+            let modified = match std::fs::metadata(root.join(output)) {
+                Ok(metadata) => metadata.modified()?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+                Err(e) => return Err(e.into()),
+            };
+            oldest_output = Some(oldest_output.map_or(modified, |t| t.min(modified)));
+        }
+        let Some(oldest_output) = oldest_output else {
+            return Ok(false);
+        };
+
+        for input in self.resolve_inputs(root)? {
+            if std::fs::metadata(&input)?.modified()? >= oldest_output {
+                return Ok(false);
+            }
+        }
+
+        return Ok(true);
+    }
+
+    pub fn id(&self) -> &TaskID {
+        &self.id
+    }
+
+    /// Whether both tasks have the same ID, regardless of what they run.
+    /// `==` compares every field, which is rarely what graph code wants.
+    pub fn same_id(&self, other: &Task) -> bool {
+        self.id == other.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn action(&self) -> &Action {
+        &self.action
+    }
+}
+
+/// Actions define different actions that a task can do.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum Action {
+    /// Run a shell command
+    Shell {
+        /// The program followed by its arguments
+        cmd: Vec<String>,
+        /// The exit codes that count as a success
+        success_codes: Vec<i32>,
+        /// A short description shown instead of the command, e.g. `bundle app`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+        /// The niceness the process runs with, higher is lower priority
+        #[serde(skip_serializing_if = "Option::is_none")]
+        niceness: Option<i32>,
+    },
+    /// Run a shell command picked for the platform the task runs on, e.g.
+    /// `build.sh` on Unix and `build.ps1` on Windows. Fails to run on a
+    /// platform without a command
+    PlatformShell {
+        /// The program followed by its arguments, run on Unix
+        unix: Vec<String>,
+        /// The program followed by its arguments, run on Windows
+        windows: Vec<String>,
+        /// The exit codes that count as a success
+        success_codes: Vec<i32>,
+        /// A short description shown instead of the command, e.g. `bundle app`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+        /// The niceness the process runs with, higher is lower priority
+        #[serde(skip_serializing_if = "Option::is_none")]
+        niceness: Option<i32>,
+    },
+    /// Run a command inside a container, with `docker`, or `podman` if docker
+    /// is not installed. The container is removed once it exits. The task's
+    /// variables are set for the runtime, not passed into the container
+    Container {
+        /// The image the container is created from
+        image: String,
+        /// The program followed by its arguments, run in the container
+        cmd: Vec<String>,
+        /// The (host, container) paths mounted in the container. Host paths
+        /// should be absolute
+        mounts: Vec<(PathBuf, PathBuf)>,
+        /// The exit codes that count as a success
+        success_codes: Vec<i32>,
+        /// A short description shown instead of the command, e.g. `bundle app`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+    },
+    /// Run an action, then a cleanup action whatever the outcome of the first,
+    /// like `try`/`finally`. The outcome is the one of the body
+    WithCleanup {
+        body: Box<Action>,
+        cleanup: Box<Action>,
+    },
+}
+
+impl Action {
+    /// A shell action that only succeeds with exit code 0.
+    pub fn shell(cmd: Vec<String>) -> Self {
+        Action::Shell {
+            cmd,
+            success_codes: vec![0],
+            label: None,
+            niceness: None,
+        }
+    }
+
+    /// A platform dependent shell action that only succeeds with exit code 0.
+    /// See `Action::PlatformShell`.
+    /// # Arguments
+    /// * `unix` - The command run on Unix, empty if there is none
+    /// * `windows` - The command run on Windows, empty if there is none
+    pub fn platform_shell(unix: Vec<String>, windows: Vec<String>) -> Self {
+        Action::PlatformShell {
+            unix,
+            windows,
+            success_codes: vec![0],
+            label: None,
+            niceness: None,
+        }
+    }
+
+    /// A container action that only succeeds with exit code 0.
+    /// See `Action::Container`.
+    /// # Arguments
+    /// * `image` - The image the container is created from
+    /// * `cmd` - The command run in the container
+    /// * `mounts` - The (host, container) paths mounted in the container
+    pub fn container(image: &str, cmd: Vec<String>, mounts: Vec<(PathBuf, PathBuf)>) -> Self {
+        Action::Container {
+            image: image.to_string(),
+            cmd,
+            mounts,
+            success_codes: vec![0],
+            label: None,
+        }
+    }
+
+    /// Run the process with the given niceness, e.g. 10 for background builds
+    /// that shouldn't slow down interactive work.
+    /// Only supported on Unix, elsewhere the niceness is ignored, with a
+    /// warning traced under the `tracing` feature. Actions with a cleanup
+    /// set the niceness of both steps.
+    /// Container actions ignore it, their command runs in the container.
+    pub fn with_niceness(mut self, value: i32) -> Self {
+        match &mut self {
+            Action::Shell { niceness, .. } | Action::PlatformShell { niceness, .. } => {
+                *niceness = Some(value)
+            }
+            Action::Container { .. } => {}
+            Action::WithCleanup { body, cleanup } => {
+                **body = body.clone().with_niceness(value);
+                **cleanup = cleanup.clone().with_niceness(value);
+            }
+        }
+        self
+    }
+
+    /// Run a cleanup action after this one, even if it fails.
+    /// See `Action::WithCleanup`.
+    pub fn with_cleanup(self, cleanup: Action) -> Self {
+        Action::WithCleanup {
+            body: Box::new(self),
+            cleanup: Box::new(cleanup),
+        }
+    }
+
+    /// Set a human-readable label, shown instead of the command in logs.
+    /// Actions with a cleanup set the label of their body.
+    pub fn with_label(mut self, text: &str) -> Self {
+        match &mut self {
+            Action::Shell { label, .. }
+            | Action::PlatformShell { label, .. }
+            | Action::Container { label, .. } => *label = Some(text.to_string()),
+            Action::WithCleanup { body, .. } => **body = body.clone().with_label(text),
+        }
+        self
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        match self {
+            Action::Shell { label, .. }
+            | Action::PlatformShell { label, .. }
+            | Action::Container { label, .. } => label.as_deref(),
+            Action::WithCleanup { body, .. } => body.label(),
+        }
+    }
+
+    /// What to show for the action in logs: its label if it has one,
+    /// otherwise its command.
+    pub fn display_name(&self) -> String {
+        if let Some(label) = self.label() {
+            return label.to_string();
+        }
+        return self.cmd().map(|cmd| cmd.join(" ")).unwrap_or_default();
+    }
+
+    /// Set the exit codes that count as a success, e.g. `[0, 1]` for `diff`.
+    /// Actions with a cleanup set the codes of their body.
+    pub fn with_success_codes(mut self, codes: Vec<i32>) -> Self {
+        match &mut self {
+            Action::Shell { success_codes, .. }
+            | Action::PlatformShell { success_codes, .. }
+            | Action::Container { success_codes, .. } => *success_codes = codes,
+            Action::WithCleanup { body, .. } => **body = body.clone().with_success_codes(codes),
+        }
+        self
+    }
+
+    /// Runs the action, inheriting the runner's stdout and stderr.
+    pub fn run(&self) -> Result<ExitStatus> {
+        return self.run_with(&SystemRunner);
+    }
+
+    /// Runs the action with the given process runner, e.g. a `MockRunner`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(action = %self.display_name()))
+    )]
+    pub fn run_with(&self, runner: &dyn ProcessRunner) -> Result<ExitStatus> {
+        match self {
+            Action::Shell { .. } | Action::PlatformShell { .. } | Action::Container { .. } => {
+                return runner.run(&mut self.command()?);
+            }
+            Action::WithCleanup { body, cleanup } => {
+                let status = body.run_with(runner);
+                cleanup.run_with(runner)?;
+                return status;
+            }
+        }
+    }
+
+    /// Creates the process for the action without spawning it, so the caller
+    /// can decide what to do with its output. The task's environment is set by
+    /// `Task::command`.
+    /// Actions with a cleanup run more than one process, this is the process
+    /// of their body.
+    /// Fails if the action has no command for the current platform, or if no
+    /// container runtime is installed for a container action.
+    pub fn command(&self) -> Result<Command> {
+        match self {
+            Action::Shell { niceness, .. } | Action::PlatformShell { niceness, .. } => {
+                let Some((program, args)) = self.cmd()?.split_first() else {
+                    bail!("the action has no command");
+                };
+                let mut command = Command::new(program);
+                command.args(args);
+                if let Some(niceness) = *niceness {
+                    set_niceness(&mut command, niceness);
+                }
+                return Ok(command);
+            }
+            Action::Container {
+                image, cmd, mounts, ..
+            } => {
+                let mut command = Command::new(container_runtime()?);
+                command.args(container_args(image, cmd, mounts));
+                return Ok(command);
+            }
+            Action::WithCleanup { body, .. } => return body.command(),
+        }
+    }
+
+    /// Returns the program and arguments the action runs on the current
+    /// platform, in the container for container actions. Actions with a
+    /// cleanup return the ones of their body.
+    pub fn cmd(&self) -> Result<&[String]> {
+        return self.cmd_for(cfg!(windows));
+    }
+
+    /// See `cmd`.
+    /// # Arguments
+    /// * `windows` - Whether the command is for Windows, or for Unix
+    fn cmd_for(&self, windows: bool) -> Result<&[String]> {
+        match self {
+            Action::Shell { cmd, .. } | Action::Container { cmd, .. } => return Ok(cmd),
+            Action::PlatformShell {
+                unix,
+                windows: windows_cmd,
+                ..
+            } => {
+                let (cmd, platform) = if windows {
+                    (windows_cmd, "Windows")
+                } else {
+                    (unix, "Unix")
+                };
+                if cmd.is_empty() {
+                    bail!("the action has no command for {platform}");
+                }
+                return Ok(cmd);
+            }
+            Action::WithCleanup { body, .. } => return body.cmd_for(windows),
+        }
+    }
+
+    /// Whether the exit status of the action's process counts as a success.
+    /// A process killed by a signal, without an exit code, never succeeds.
+    pub fn is_success(&self, status: &ExitStatus) -> bool {
+        match self {
+            Action::Shell { success_codes, .. }
+            | Action::PlatformShell { success_codes, .. }
+            | Action::Container { success_codes, .. } => {
+                return status.code().is_some_and(|c| success_codes.contains(&c));
+            }
+            Action::WithCleanup { body, .. } => return body.is_success(status),
+        }
+    }
+
+    /// Feeds what the action runs to a cache key, see `Task::cache_key`.
+    fn update_key(&self, update: &mut impl FnMut(&[u8])) {
+        match self {
+            Action::Shell {
+                cmd, success_codes, ..
+            } => {
+                for arg in cmd {
+                    update(arg.as_bytes());
+                }
+                for code in success_codes {
+                    update(&code.to_le_bytes());
+                }
+            }
+            Action::PlatformShell {
+                unix,
+                windows,
+                success_codes,
+                ..
+            } => {
+                update(b"platform-shell");
+                for arg in unix {
+                    update(arg.as_bytes());
+                }
+                update(b"windows");
+                for arg in windows {
+                    update(arg.as_bytes());
+                }
+                for code in success_codes {
+                    update(&code.to_le_bytes());
+                }
+            }
+            Action::Container {
+                image,
+                cmd,
+                mounts,
+                success_codes,
+                ..
+            } => {
+                update(b"container");
+                for arg in container_args(image, cmd, mounts) {
+                    update(arg.as_bytes());
+                }
+                for code in success_codes {
+                    update(&code.to_le_bytes());
+                }
+            }
+            Action::WithCleanup { body, cleanup } => {
+                update(b"with-cleanup");
+                body.update_key(update);
+                update(b"cleanup");
+                cleanup.update_key(update);
+            }
+        }
+    }
+}
+
+/// The container runtimes, in order of preference.
+const CONTAINER_RUNTIMES: [&str; 2] = ["docker", "podman"];
+
+/// Finds the container runtime to run container actions with.
+/// # Returns
+/// * `PathBuf` - The path of the runtime's program
+fn container_runtime() -> Result<PathBuf> {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    for runtime in CONTAINER_RUNTIMES {
+        let program = format!("{runtime}{}", std::env::consts::EXE_SUFFIX);
+        if let Some(found) = std::env::split_paths(&path)
+            .map(|dir| dir.join(&program))
+            .find(|p| p.is_file())
+        {
+            return Ok(found);
+        }
+    }
+    bail!(
+        "no container runtime found, install {} to run container actions",
+        CONTAINER_RUNTIMES.join(" or ")
+    );
+}
+
+/// The arguments of the runtime's `run` command for a container action.
+fn container_args(image: &str, cmd: &[String], mounts: &[(PathBuf, PathBuf)]) -> Vec<String> {
+    let mut args = vec!["run".to_string(), "--rm".to_string()];
+    for (host, container) in mounts {
+        args.push("--volume".to_string());
+        args.push(format!("{}:{}", host.display(), container.display()));
+    }
+    args.push(image.to_string());
+    args.extend(cmd.iter().cloned());
+    return args;
+}
+
+/// Orders the tasks of an acyclic graph so that every task comes after its
+/// dependencies, the same way on every build: of the tasks whose dependencies
+/// all came before, the one with the smallest ID comes first.
+fn stable_order(edges: &Edges) -> Vec<TaskID> {
+    let mut pending = edges
+        .iter()
+        .map(|(t, deps)| (t, deps.len()))
+        .collect::<HashMap<&TaskID, usize>>();
+    let mut dependents: HashMap<&TaskID, Vec<&TaskID>> = HashMap::new();
+    for (task, deps) in edges {
+        for dep in deps {
+            dependents.entry(dep).or_default().push(task);
+        }
+    }
+
+    let mut ready = pending
+        .iter()
+        .filter(|(_, count)| **count == 0)
+        .map(|(t, _)| *t)
+        .collect::<BTreeSet<&TaskID>>();
+    let mut ordered: Vec<TaskID> = vec![];
+    while let Some(task) = ready.pop_first() {
+        ordered.push(task.clone());
+        for dependent in dependents.get(task).into_iter().flatten() {
+            let count = pending.get_mut(dependent).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                ready.insert(dependent);
+            }
+        }
+    }
+
+    return ordered;
+}
+
+/// Finds a task in a cycle of a graph `stable_order` could not order fully,
+/// the same one on every build.
+/// # Arguments
+/// * `ordered` - The tasks `stable_order` did order
+fn cycle_task(edges: &Edges, ordered: &[TaskID]) -> TaskID {
+    let ordered = ordered.iter().collect::<HashSet<&TaskID>>();
+    let unordered = |id: &&TaskID| !ordered.contains(id);
+
+    // Every task left has a dependency left, so following the smallest one
+    // has to come back to a task already seen, and that task is in a cycle
+    let mut task = edges.keys().filter(unordered).min().unwrap();
+    let mut seen: HashSet<&TaskID> = HashSet::new();
+    while seen.insert(task) {
+        task = edges[task].iter().filter(unordered).min().unwrap();
+    }
+    return task.clone();
+}
+
+/// Makes a command run with the given niceness.
+#[cfg(unix)]
+fn set_niceness(command: &mut Command, niceness: i32) {
+    use std::os::unix::process::CommandExt;
+
+    // SAFETY: `setpriority` is async-signal-safe, so it can be called between
+    // fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            if libc::setpriority(libc::PRIO_PROCESS, 0, niceness) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(unix))]
+fn set_niceness(_command: &mut Command, niceness: i32) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(
+        niceness,
+        "process niceness is not supported on this platform, ignoring it"
+    );
+    #[cfg(not(feature = "tracing"))]
+    let _ = niceness;
+}
+
+/// How a task depends on another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DependencyKind {
+    /// The dependency must succeed for the task to run
+    Hard,
+    /// The dependency must finish for the task to run, but may fail
+    Soft,
+}
+
+/// The list of possible errors that can occur when building a task graph.
+#[derive(Debug, Error, PartialEq)]
+pub enum BuildError {
+    /// The dependencies form a cycle
+    /// # Arguments
+    /// * `TaskID` - A task in the cycle
+    #[error("dependency cycle detected at task `{0}`")]
+    Cycle(TaskID),
+
+    /// A dependency pattern did not match any task, and the builder is strict
+    /// about patterns
+    #[error("dependency pattern `{pattern}` of task `{task}` did not match any task")]
+    UnmatchedPattern { task: TaskID, pattern: String },
+
+    /// A task depends on a task that was never added
+    #[error("task `{task}` depends on unknown task `{dependency}`")]
+    MissingDependency { task: TaskID, dependency: TaskID },
+
+    /// A task hard depends on a task that was excluded
+    #[error("task `{task}` depends on excluded task `{dependency}`")]
+    ExcludedDependency { task: TaskID, dependency: TaskID },
+
+    /// A root of a partial build is not a known task
+    /// # Arguments
+    /// * `TaskID` - The unknown root
+    #[error("unknown root task `{0}`")]
+    UnknownRoot(TaskID),
+
+    /// Dependencies were added to a task that was never added
+    /// # Arguments
+    /// * `TaskID` - The unknown task
+    #[error("dependencies were added to unknown task `{0}`")]
+    UnknownTask(TaskID),
+}
+
+/// Non-fatal issues found while building a task graph.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuildWarning {
+    /// A dependency pattern did not match any task
+    UnmatchedPattern { task: TaskID, pattern: String },
+    /// A task depends on another both hard and soft, which is contradictory.
+    /// The hard dependency is kept
+    ConflictingDependencyKinds { task: TaskID, dependency: TaskID },
+}
+
+/// Whether `TaskGraph::patch` updated the graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchOutcome {
+    /// The graph was updated in place
+    Patched,
+    /// The change touches too much of the graph to be worth patching. The
+    /// graph was left as it was, build it again instead
+    RebuildNeeded,
+}
+
+/// Why a task was left out of a partial build.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnreachableReason {
+    /// The task was not requested, and no task depends on it
+    NotRequested,
+    /// The task is only needed by tasks that were left out too
+    /// # Arguments
+    /// * `Vec<TaskID>` - The tasks that depend on it, sorted
+    OnlyNeededBy(Vec<TaskID>),
+}
+
+/// Why a task is in a graph, see `TaskGraph::reason`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunReason {
+    /// The task was requested, e.g. a root of `build_for`
+    Requested,
+    /// The task is a dependency of another task of the graph
+    /// # Arguments
+    /// * `TaskID` - The task that pulled it in, the closest to a requested
+    ///   task
+    DependencyOf(TaskID),
+}
+
+impl fmt::Display for RunReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunReason::Requested => f.write_str("requested target"),
+            RunReason::DependencyOf(task_id) => write!(f, "dependency of {task_id}"),
+        }
+    }
+}
+
+/// The state of a task in a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskStatus {
+    /// The task is waiting to be run
+    Pending,
+    /// The task was handed out by the graph but is not done yet
+    Running,
+    /// The task finished successfully
+    Succeeded,
+    /// The task finished unsuccessfully
+    Failed,
+    /// The task will not run because one of its dependencies failed
+    Skipped,
+}
+
+/// A task graph is a directed acyclic graph (DAG) where each node is a task
+/// and each edge is a dependency.
+/// a -> b means that a depends on b. So b must be done before a.
+/// The nodes are stored in a hashmap for easy access, TaskID -> Task.
+/// The edges are stored in a hashmap for easy access, TaskID -> Vec<TaskID>.
+#[derive(Debug, Clone)]
+pub struct TaskGraph {
+    /// The tasks in the graph. Use the ID to get the task
+    tasks: HashMap<TaskId, Task>,
+    /// Edges are stored as a -> (b, c, d).
+    /// This means a depends on b, c, and d. So tasks b, c, and d must be done
+    /// before starting with a
+    edges: IdEdges,
+    /// The (task, dependency) pairs of `edges` that are soft. The task runs
+    /// once the dependency finished, even if it failed
+    soft: HashSet<(TaskId, TaskId)>,
+    /// The reverse of `edges`, b -> (a) when a depends on b
+    dependents: IdEdges,
+    /// Tasks that are done.
+    done: HashSet<TaskId>,
+    /// The number of dependencies of each task that are not done yet. A task
+    /// is ready once it reaches 0, so readiness is checked without going
+    /// through its dependencies.
+    pending_deps: HashMap<TaskId, usize>,
+    /// Tasks ordered using topological sort
+    ordered_tasks: Vec<TaskId>,
+    /// The status of every task in the graph
+    status: HashMap<TaskId, TaskStatus>,
+    /// Issues found while building the graph
+    warnings: Vec<BuildWarning>,
+    /// Tasks left out by `build_for`, and why
+    unreachable: Vec<(TaskID, UnreachableReason)>,
+    /// Why `build_for` kept each task. Empty when every task was requested
+    reasons: HashMap<TaskID, RunReason>,
+}
+
+impl TaskGraph {
+    pub fn done(&mut self, task_id: &TaskID) {
+        let task_id = TaskId::from(task_id.as_str());
+        let previous = self.status.insert(task_id.clone(), TaskStatus::Succeeded);
+        // A task that failed already released its soft dependents
+        let failed = matches!(previous, Some(TaskStatus::Failed | TaskStatus::Skipped));
+        if !self.done.insert(task_id.clone()) || failed {
+            return;
+        }
+
+        for dependent in self.dependents.get(&task_id).into_iter().flatten() {
+            if let Some(count) = self.pending_deps.get_mut(dependent) {
+                *count -= 1;
+            }
+        }
+    }
+
+    /// Mark several tasks as done at once, e.g. all the tasks that finished
+    /// since the last poll.
+    /// # Arguments
+    /// * `task_ids` - The tasks that are done
+    pub fn done_many(&mut self, task_ids: &[TaskID]) {
+        for task_id in task_ids {
+            self.done(task_id);
+        }
+    }
+
+    /// Mark a task as failed.
+    /// Every task that hard depends on it, directly or not, is skipped and will
+    /// not be returned by `next`. Soft dependents still run.
+    /// Does nothing if the task already failed, was skipped or is done.
+    /// # Arguments
+    /// * `task_id` - The task that failed
+    pub fn fail(&mut self, task_id: &TaskID) {
+        let task_id = TaskId::from(task_id.as_str());
+        let finished = matches!(
+            self.status.get(&task_id),
+            Some(TaskStatus::Failed | TaskStatus::Skipped)
+        );
+        if finished || self.done.contains(&task_id) {
+            return;
+        }
+        self.ordered_tasks.retain(|t| t != &task_id);
+        self.status.insert(task_id.clone(), TaskStatus::Failed);
+
+        let mut stack = vec![task_id];
+        while let Some(id) = stack.pop() {
+            for dependent in self.dependents_of(&id) {
+                // Soft dependents only wait for the dependency to finish
+                if self.soft.contains(&(dependent.clone(), id.clone())) {
+                    if let Some(count) = self.pending_deps.get_mut(&dependent) {
+                        *count -= 1;
+                    }
+                    continue;
+                }
+
+                if self.status.get(&dependent) == Some(&TaskStatus::Skipped) {
+                    continue;
+                }
+                self.ordered_tasks.retain(|t| t != &dependent);
+                self.status.insert(dependent.clone(), TaskStatus::Skipped);
+                stack.push(dependent);
+            }
+        }
+    }
+
+    /// Mark a pending task as skipped, e.g. when the user asked not to run
+    /// it. Its dependents are skipped as if it had failed, see `fail`.
+    /// # Arguments
+    /// * `task_id` - The task to skip
+    pub fn skip(&mut self, task_id: &TaskID) {
+        self.fail(task_id);
+        self.status
+            .insert(TaskId::from(task_id.as_str()), TaskStatus::Skipped);
+    }
+
+    /// Replaces the tasks of a project, e.g. when its project file changed in
+    /// watch mode, without building the whole graph again. The tasks of the
+    /// project are the ones whose ID starts with `<project>:`.
+    ///
+    /// Every edge to or from the old tasks of the project is dropped, even if
+    /// a new task has the same ID, and replaced by `new_edges`. The other
+    /// edges and the statuses of the other tasks are left as they are, the
+    /// new tasks are pending. The remaining tasks are only sorted again from
+    /// scratch if the new tasks can't be slotted into their order.
+    /// Nothing changes if the patch fails.
+    /// # Arguments
+    /// * `changed_project` - The project whose tasks are replaced
+    /// * `new_tasks` - The new tasks of the project
+    /// * `new_edges` - The new hard dependencies, as (task, dependency) pairs
+    ///
+    /// # Returns
+    /// * `PatchOutcome` - Whether the graph was patched, or should be built
+    ///   again because more than half of its tasks changed
+    pub fn patch(
+        &mut self,
+        changed_project: &str,
+        new_tasks: Vec<Task>,
+        new_edges: Vec<(TaskID, TaskID)>,
+    ) -> Result<PatchOutcome, BuildError> {
+        let prefix = format!("{changed_project}:");
+        let removed = self
+            .tasks
+            .keys()
+            .filter(|id| id.as_str().starts_with(&prefix))
+            .cloned()
+            .collect::<HashSet<TaskId>>();
+        if removed.len().max(new_tasks.len()) * 2 > self.tasks.len() {
+            return Ok(PatchOutcome::RebuildNeeded);
+        }
+
+        let added = new_tasks
+            .iter()
+            .map(|t| TaskId::from(t.id.as_str()))
+            .collect::<HashSet<TaskId>>();
+        let exists =
+            |id: &str| added.contains(id) || (self.tasks.contains_key(id) && !removed.contains(id));
+        let mut new_deps: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (task, dependency) in &new_edges {
+            if !exists(task) {
+                return Err(BuildError::UnknownTask(task.clone()));
+            }
+            if !exists(dependency) {
+                return Err(BuildError::MissingDependency {
+                    task: task.clone(),
+                    dependency: dependency.clone(),
+                });
+            }
+            new_deps.entry(task).or_default().push(dependency);
+        }
+
+        // Any new cycle goes through a new edge, so its task is reachable
+        // from its dependency
+        let deps_after = |id: &str| -> Vec<&str> {
+            let mut deps: Vec<&str> = vec![];
+            if !added.contains(id) {
+                deps.extend(
+                    self.edges
+                        .get(id)
+                        .into_iter()
+                        .flatten()
+                        .filter(|d| !removed.contains(*d))
+                        .map(TaskId::as_str),
+                );
+            }
+            deps.extend(new_deps.get(id).into_iter().flatten());
+            return deps;
+        };
+        for (task, dependency) in &new_edges {
+            let mut visited: HashSet<&str> = HashSet::new();
+            let mut stack = vec![dependency.as_str()];
+            while let Some(id) = stack.pop() {
+                if id == task {
+                    return Err(BuildError::Cycle(task.clone()));
+                }
+                if visited.insert(id) {
+                    stack.extend(deps_after(id));
+                }
+            }
+        }
+
+        // Drop the old tasks and their edges
+        let mut touched: HashSet<TaskId> = HashSet::new();
+        for id in &removed {
+            for dep in self.edges.remove(id).unwrap_or_default() {
+                if let Some(dependents) = self.dependents.get_mut(&dep) {
+                    dependents.retain(|d| d != id);
+                }
+            }
+            for dependent in self.dependents.remove(id).unwrap_or_default() {
+                if let Some(deps) = self.edges.get_mut(&dependent) {
+                    deps.retain(|d| d != id);
+                }
+                touched.insert(dependent);
+            }
+            self.tasks.remove(id);
+            self.status.remove(id);
+            self.done.remove(id);
+            self.pending_deps.remove(id);
+        }
+        self.soft
+            .retain(|(task, dep)| !removed.contains(task) && !removed.contains(dep));
+        self.ordered_tasks.retain(|id| !removed.contains(id));
+
+        // Add the new ones
+        for task in new_tasks {
+            let id = TaskId::from(task.id.as_str());
+            self.edges.entry(id.clone()).or_default();
+            self.status.insert(id.clone(), TaskStatus::Pending);
+            self.tasks.insert(id.clone(), task);
+            touched.insert(id);
+        }
+        for (task, dependency) in &new_edges {
+            let (task, dependency) = (
+                TaskId::from(task.as_str()),
+                TaskId::from(dependency.as_str()),
+            );
+            self.edges
+                .entry(task.clone())
+                .or_default()
+                .push(dependency.clone());
+            let dependents = self.dependents.entry(dependency).or_default();
+            dependents.push(task.clone());
+            dependents.sort();
+            touched.insert(task);
+        }
+        for id in touched {
+            let count = self.count_pending_deps(&id);
+            self.pending_deps.insert(id, count);
+        }
+
+        self.order_patched(&added, &new_edges);
+        return Ok(PatchOutcome::Patched);
+    }
+
+    /// The number of dependencies of a task it still waits for.
+    fn count_pending_deps(&self, task_id: &TaskId) -> usize {
+        let finished = |id: &TaskId| {
+            !matches!(
+                self.status.get(id),
+                Some(TaskStatus::Pending | TaskStatus::Running)
+            )
+        };
+
+        return self
+            .edges
+            .get(task_id)
+            .into_iter()
+            .flatten()
+            .filter(|dep| {
+                let soft = self.soft.contains(&(task_id.clone(), (*dep).clone()));
+                !(self.done.contains(*dep) || soft && finished(dep))
+            })
+            .count();
+    }
+
+    /// Slots the tasks added by `patch` into the order of the remaining
+    /// tasks, each right after its last dependency. Sorts the remaining tasks
+    /// again if that breaks the order of a new edge.
+    fn order_patched(&mut self, added: &HashSet<TaskId>, new_edges: &[(TaskID, TaskID)]) {
+        // The new tasks in dependency order among themselves
+        let among_added = added
+            .iter()
+            .map(|id| {
+                let deps = self.edges[id]
+                    .iter()
+                    .filter(|d| added.contains(*d))
+                    .map(|d| d.to_string())
+                    .collect();
+                (id.to_string(), deps)
+            })
+            .collect::<Edges>();
+
+        for id in stable_order(&among_added) {
+            let position = self
+                .ordered_tasks
+                .iter()
+                .rposition(|t| self.edges[id.as_str()].contains(t))
+                .map_or(0, |i| i + 1);
+            self.ordered_tasks.insert(position, TaskId::from(id));
+        }
+
+        let positions = self
+            .ordered_tasks
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (id.as_str(), i))
+            .collect::<HashMap<&str, usize>>();
+        let in_order = new_edges.iter().all(|(task, dep)| {
+            match (positions.get(task.as_str()), positions.get(dep.as_str())) {
+                (Some(task), Some(dep)) => dep < task,
+                _ => true,
+            }
+        });
+        if in_order {
+            return;
+        }
+
+        let remaining = self
+            .ordered_tasks
+            .iter()
+            .map(|id| {
+                let deps = self.edges[id]
+                    .iter()
+                    .filter(|d| positions.contains_key(d.as_str()))
+                    .map(|d| d.to_string())
+                    .collect();
+                (id.to_string(), deps)
+            })
+            .collect::<Edges>();
+        self.ordered_tasks = stable_order(&remaining)
+            .into_iter()
+            .map(TaskId::from)
+            .collect();
+    }
+
+    /// Returns the tasks that would be skipped if a task failed: the tasks that
+    /// hard depend on it, directly or not. Soft dependents still run, and so
+    /// do the tasks depending on them. See `fail`.
+    /// # Arguments
+    /// * `task_id` - The task that would fail
+    ///
+    /// # Returns
+    /// * `Vec<TaskID>` - The blocked tasks, sorted
+    pub fn blocked_by(&self, task_id: &TaskID) -> Vec<TaskID> {
+        let mut blocked: HashSet<TaskId> = HashSet::new();
+
+        let mut stack = vec![TaskId::from(task_id.as_str())];
+        while let Some(id) = stack.pop() {
+            for dependent in self.dependents_of(&id) {
+                if !self.soft.contains(&(dependent.clone(), id.clone()))
+                    && blocked.insert(dependent.clone())
+                {
+                    stack.push(dependent);
+                }
+            }
+        }
+
+        let mut blocked = blocked
+            .into_iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<TaskID>>();
+        blocked.sort();
+        return blocked;
+    }
+
+    /// Runs the action of a single task right away, e.g. to iterate on its
+    /// command. Bypasses readiness on purpose: the dependencies are neither
+    /// run nor waited for, and the task's status is left as it is. The
+    /// processes are created with the task's environment, see `Task::command`.
+    /// Fails for tasks with a custom executor, those are run by an `Executor`.
+    /// # Arguments
+    /// * `task_id` - The task to run
+    ///
+    /// # Returns
+    /// * `ExitStatus` - The exit status of the action
+    pub fn run_one(&self, task_id: &TaskID) -> Result<ExitStatus> {
+        let Some(task) = self.tasks.get(task_id.as_str()) else {
+            bail!("unknown task `{task_id}`");
+        };
+        if let Some(name) = task.executor() {
+            bail!("`{task_id}` runs with the executor `{name}`, use an `Executor` to run it");
+        }
+        return TaskGraph::run_action(task, task.action());
+    }
+
+    /// Runs an action of a task with the task's environment, and the cleanup
+    /// of the action even if its body fails, like `Action::run`.
+    fn run_action(task: &Task, action: &Action) -> Result<ExitStatus> {
+        match action {
+            Action::Shell { .. } | Action::PlatformShell { .. } | Action::Container { .. } => {
+                // `Task::command`, for the action at hand
+                let mut command = action.command()?;
+                task.apply_env(&mut command)?;
+                return SystemRunner.run(&mut command);
+            }
+            Action::WithCleanup { body, cleanup } => {
+                let status = TaskGraph::run_action(task, body);
+                TaskGraph::run_action(task, cleanup)?;
+                return status;
+            }
+        }
+    }
+
+    /// Replaces the action of a task, e.g. with `echo skipped` to bisect a
+    /// broken build without editing its config. Applies to every later run of
+    /// the task, by `next`, `run_one` or an `Executor`.
+    /// # Arguments
+    /// * `task_id` - The task to change
+    /// * `action` - The action it runs instead
+    pub fn override_action(&mut self, task_id: &TaskID, action: Action) -> Result<()> {
+        let Some(task) = self.tasks.get_mut(task_id.as_str()) else {
+            bail!("unknown task `{task_id}`");
+        };
+        task.action = action;
+        return Ok(());
+    }
+
+    /// Drops the finished tasks no remaining task needs anymore, to free
+    /// memory during long runs of huge graphs. A finished task is kept as long
+    /// as one of its dependents is pending or running.
+    /// The statuses of the dropped tasks are kept, so they are still counted
+    /// and reported, but their tasks and edges are gone, e.g. for
+    /// `dependencies` and `critical_path`.
+    /// # Returns
+    /// * `usize` - The number of dropped tasks
+    pub fn prune_done(&mut self) -> usize {
+        let finished = |status: Option<&TaskStatus>| {
+            !matches!(status, Some(TaskStatus::Pending | TaskStatus::Running))
+        };
+        let prunable = self
+            .tasks
+            .keys()
+            .filter(|id| finished(self.status.get(*id)))
+            .filter(|id| {
+                self.dependents
+                    .get(*id)
+                    .into_iter()
+                    .flatten()
+                    .all(|dependent| finished(self.status.get(dependent)))
+            })
+            .cloned()
+            .collect::<HashSet<TaskId>>();
+
+        // Tasks marked as done without being started are still ordered
+        self.ordered_tasks.retain(|id| !prunable.contains(id));
+        for id in &prunable {
+            self.tasks.remove(id);
+            self.edges.remove(id);
+            self.dependents.remove(id);
+            self.pending_deps.remove(id);
+        }
+        self.soft.retain(|(task, _)| self.tasks.contains_key(task));
+
+        return prunable.len();
+    }
+
+    /// Returns how a task depends on another, or `None` if it doesn't
+    /// directly.
+    /// # Arguments
+    /// * `task_id` - The dependent task
+    /// * `dependency` - The task it may depend on
+    pub fn dependency_kind(&self, task_id: &TaskID, dependency: &TaskID) -> Option<DependencyKind> {
+        let (task_id, dependency) = (
+            TaskId::from(task_id.as_str()),
+            TaskId::from(dependency.as_str()),
+        );
+        if !self.edges.get(&task_id)?.contains(&dependency) {
+            return None;
+        }
+        if self.soft.contains(&(task_id, dependency)) {
+            return Some(DependencyKind::Soft);
+        }
+        return Some(DependencyKind::Hard);
+    }
+
+    /// Returns the tasks a task directly depends on, soft or not.
+    /// Empty if the task has no dependencies or is not in the graph.
+    pub fn dependencies(&self, task_id: &TaskID) -> Vec<TaskID> {
+        return ids(self.edges.get(task_id.as_str()));
+    }
+
+    /// Returns the tasks that directly depend on a task, sorted by ID.
+    /// Empty if nothing depends on the task or it is not in the graph.
+    pub fn dependents(&self, task_id: &TaskID) -> Vec<TaskID> {
+        return ids(self.dependents.get(task_id.as_str()));
+    }
+
+    /// Returns the tasks that no task depends on, sorted by ID: the final
+    /// targets of the graph, e.g. to summarize a plan as "building 3
+    /// targets". These are the leaves of the build order, while
+    /// `GraphAnalysis` follows the edges, from a task to its dependencies,
+    /// and calls them its `roots`.
+    pub fn leaves(&self) -> Vec<&TaskID> {
+        let mut targets = self
+            .tasks
+            .values()
+            .map(Task::id)
+            .filter(|id| self.dependents(id).is_empty())
+            .collect::<Vec<&TaskID>>();
+        targets.sort();
+        return targets;
+    }
+
+    /// Returns the tasks without dependencies, sorted by ID: the ones a run
+    /// starts with. The reverse of `leaves`, these are the
+    /// `GraphAnalysis::leaves`.
+    pub fn roots(&self) -> Vec<&TaskID> {
+        let mut entries = self
+            .tasks
+            .values()
+            .map(Task::id)
+            .filter(|id| self.dependencies(id).is_empty())
+            .collect::<Vec<&TaskID>>();
+        entries.sort();
+        return entries;
+    }
+
+    /// Returns the tasks that have the given tag, sorted by ID.
+    pub fn tasks_with_tag(&self, tag: &str) -> Vec<TaskID> {
+        let mut task_ids = self
+            .tasks
+            .values()
+            .filter(|t| t.tags.iter().any(|t| t == tag))
+            .map(|t| t.id.clone())
+            .collect::<Vec<TaskID>>();
+        task_ids.sort();
+        return task_ids;
+    }
+
+    /// Returns the issues found while building the graph.
+    pub fn warnings(&self) -> &[BuildWarning] {
+        &self.warnings
+    }
+
+    /// Returns why a task is in the graph, e.g. to print
+    /// `lib:build - dependency of app:build` when it starts. Every task of a
+    /// graph built with `build` is requested.
+    /// # Returns
+    /// * `Option<RunReason>` - The reason, `None` if the task is not in the
+    ///   graph
+    pub fn reason(&self, task_id: &TaskID) -> Option<RunReason> {
+        if !self.status.contains_key(task_id.as_str()) {
+            return None;
+        }
+        return Some(
+            self.reasons
+                .get(task_id)
+                .cloned()
+                .unwrap_or(RunReason::Requested),
+        );
+    }
+
+    /// Returns the tasks that were left out of the run because none of the
+    /// requested tasks need them, sorted by ID. Always empty unless the graph
+    /// was made with `TaskGraphBuilder::build_for`.
+    pub fn unreachable_tasks(&self) -> &[(TaskID, UnreachableReason)] {
+        &self.unreachable
+    }
+
+    /// Returns the status of a task, if it is in the graph.
+    pub fn status(&self, task_id: &TaskID) -> Option<TaskStatus> {
+        self.status.get(task_id.as_str()).copied()
+    }
+
+    /// Returns the status of every task in the graph.
+    pub fn statuses(&self) -> HashMap<TaskID, TaskStatus> {
+        self.status
+            .iter()
+            .map(|(id, status)| (id.to_string(), *status))
+            .collect()
+    }
+
+    /// Counts the tasks in each status, for a run summary.
+    /// Statuses that no task is in are left out.
+    /// # Returns
+    /// * `HashMap<TaskStatus, usize>` - The number of tasks in each status
+    pub fn status_summary(&self) -> HashMap<TaskStatus, usize> {
+        let mut summary: HashMap<TaskStatus, usize> = HashMap::new();
+
+        for status in self.status.values() {
+            *summary.entry(*status).or_default() += 1;
+        }
+
+        return summary;
+    }
+
+    /// Returns the fraction of the tasks that finished, from 0 to 1, e.g. for
+    /// a progress bar. Failed and skipped tasks count as finished, so a run
+    /// always ends at 1. An empty graph is finished.
+    pub fn progress(&self) -> f32 {
+        if self.status.is_empty() {
+            return 1.0;
+        }
+
+        let finished = self
+            .status
+            .values()
+            .filter(|s| !matches!(s, TaskStatus::Pending | TaskStatus::Running))
+            .count();
+        return finished as f32 / self.status.len() as f32;
+    }
+
+    /// Returns all the tasks that are ready to be run, in topological order,
+    /// without marking them as running. Use `start` to take one of them.
+    pub fn ready_tasks(&self) -> Vec<Task> {
+        self.ordered_tasks
+            .iter()
+            .filter(|task_id| self.is_ready(task_id))
+            .map(|task_id| self.tasks[task_id.as_str()].clone())
+            .collect()
+    }
+
+    /// Marks a pending task as running, taking it out of the remaining tasks.
+    /// This is what `next` does with the first ready task.
+    /// # Arguments
+    /// * `task_id` - The task to start
+    ///
+    /// # Returns
+    /// * `Option<Task>` - The task, or `None` if it is not remaining
+    pub fn start(&mut self, task_id: &TaskID) -> Option<Task> {
+        let i = self.ordered_tasks.iter().position(|t| t == task_id)?;
+        let task_id = self.ordered_tasks.remove(i);
+        let task = self.tasks.get(&task_id).cloned();
+        self.status.insert(task_id, TaskStatus::Running);
+        return task;
+    }
+
+    /// Whether all the dependencies of a task are done.
+    fn is_ready(&self, task_id: &TaskId) -> bool {
+        self.pending_deps
+            .get(task_id)
+            .is_none_or(|count| *count == 0)
+    }
+
+    /// Returns the tasks that directly depend on the given task.
+    fn dependents_of(&self, task_id: &TaskId) -> Vec<TaskId> {
+        self.dependents.get(task_id).cloned().unwrap_or_default()
+    }
+
+    /// Consume the graph as a sequence of waves, each being all the tasks
+    /// that are ready at that point. The tasks of a wave are marked as
+    /// running, and as done once the next wave is requested, unless they were
+    /// failed with `Waves::fail` in between.
+    pub fn waves(&mut self) -> Waves<'_> {
+        Waves {
+            graph: self,
+            current: vec![],
+        }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.ordered_tasks.len()
+    }
+
+    /// Numbers the remaining tasks in the order they run, from 1, e.g. for a
+    /// `--plan` flag. The order is the same on every build of the same graph.
+    /// # Returns
+    /// * `Vec<(usize, TaskID)>` - The step number and task of every step
+    pub fn enumerate_plan(&self) -> Vec<(usize, TaskID)> {
+        self.ordered_tasks
+            .iter()
+            .enumerate()
+            .map(|(i, task_id)| (i + 1, task_id.to_string()))
+            .collect()
+    }
+
+    /// Assign each task a level for layered rendering.
+    /// The level of a task is the length of the longest dependency chain below
+    /// it, so tasks with no dependencies are level 0 and a task is always at a
+    /// higher level than all of its dependencies.
+    /// # Returns
+    /// * `HashMap<TaskID, usize>` - The level of each task
+    pub fn levels(&self) -> HashMap<TaskID, usize> {
+        let mut levels: HashMap<TaskId, usize> = HashMap::new();
+
+        for task_id in self.edges.keys() {
+            self.level_util(task_id, &mut levels);
+        }
+
+        return levels
+            .into_iter()
+            .map(|(id, level)| (String::from(id), level))
+            .collect();
+    }
+
+    /// Simulates a run of the remaining tasks, each taking its cost in
+    /// milliseconds, and returns how long it takes. See `simulate_schedule`.
+    /// # Arguments
+    /// * `max_concurrency` - The most tasks running at once
+    pub fn simulate(&self, max_concurrency: usize) -> Duration {
+        return self.simulate_schedule(max_concurrency).makespan;
+    }
+
+    /// Simulates a run of the remaining tasks without running them, e.g. to
+    /// compare concurrency limits offline. Each task takes its cost in
+    /// milliseconds, see `Task::with_cost`. Tasks are scheduled like
+    /// `Executor::run` does without pools or priority: whenever a task
+    /// finishes, the ready tasks start in order while there are free slots.
+    /// # Arguments
+    /// * `max_concurrency` - The most tasks running at once
+    ///
+    /// # Returns
+    /// * `SimulatedRun` - When every task starts and ends, and the total time
+    pub fn simulate_schedule(&self, max_concurrency: usize) -> SimulatedRun {
+        let max_concurrency = max_concurrency.max(1);
+        let mut graph = self.clone();
+        let mut run = SimulatedRun {
+            makespan: Duration::ZERO,
+            tasks: vec![],
+        };
+
+        // The running tasks by end time, then ID so ties end in order
+        let mut running: BinaryHeap<Reverse<(Duration, TaskID, Duration)>> = BinaryHeap::new();
+        let mut now = Duration::ZERO;
+        loop {
+            for task in graph.ready_tasks() {
+                if running.len() == max_concurrency {
+                    break;
+                }
+                graph.start(task.id());
+                let end = now + Duration::from_millis(task.cost);
+                running.push(Reverse((end, task.id().clone(), now)));
+            }
+
+            let Some(Reverse((end, id, start))) = running.pop() else {
+                break;
+            };
+            now = end;
+            graph.done(&id);
+            run.tasks.push(SimulatedTask { id, start, end });
+        }
+
+        run.makespan = now;
+        return run;
+    }
+
+    /// Finds the critical path: the dependency chain with the highest total
+    /// cost, which bounds how fast the graph can run however many tasks run
+    /// at once. Ties are broken by ID.
+    /// # Returns
+    /// * `Vec<TaskID>` - The tasks of the path in execution order,
+    ///   dependencies first. Empty for an empty graph
+    pub fn critical_path(&self) -> Vec<TaskID> {
+        // The cost of the costliest chain ending with each task, and the
+        // dependency before it in the chain
+        let mut chains: HashMap<TaskId, (u64, Option<TaskId>)> = HashMap::new();
+
+        let mut task_ids = self.tasks.keys().collect::<Vec<&TaskId>>();
+        task_ids.sort();
+        for task_id in &task_ids {
+            self.chain_util(task_id, &mut chains);
+        }
+
+        let mut last = None;
+        let mut best = 0;
+        for task_id in task_ids {
+            let (cost, _) = chains[task_id];
+            if last.is_none() || cost > best {
+                last = Some(task_id.clone());
+                best = cost;
+            }
+        }
+
+        let mut path: Vec<TaskID> = vec![];
+        while let Some(task_id) = last {
+            last = chains[&task_id].1.clone();
+            path.push(task_id.into());
+        }
+        path.reverse();
+        return path;
+    }
+
+    /// Computes the costliest chain ending with a task, memoizing the result.
+    /// Relies on the graph being acyclic, which `build` guarantees.
+    fn chain_util(
+        &self,
+        task_id: &TaskId,
+        chains: &mut HashMap<TaskId, (u64, Option<TaskId>)>,
+    ) -> u64 {
+        if let Some((cost, _)) = chains.get(task_id) {
+            return *cost;
+        }
+
+        let mut deps = self.edges.get(task_id).cloned().unwrap_or_default();
+        // Pruned tasks are finished, they are not part of any chain
+        deps.retain(|dep| self.tasks.contains_key(dep));
+        deps.sort();
+        let mut before: Option<(u64, TaskId)> = None;
+        for dep in deps {
+            let cost = self.chain_util(&dep, chains);
+            if before.as_ref().is_none_or(|(best, _)| cost > *best) {
+                before = Some((cost, dep));
+            }
+        }
+
+        let (before_cost, before) = before.map_or((0, None), |(c, d)| (c, Some(d)));
+        let cost = before_cost + self.tasks[task_id].cost;
+        chains.insert(task_id.clone(), (cost, before));
+        return cost;
+    }
+
+    /// Computes the level of a task, memoizing the result.
+    /// Relies on the graph being acyclic, which `build` guarantees.
+    fn level_util(&self, task_id: &TaskId, levels: &mut HashMap<TaskId, usize>) -> usize {
+        if let Some(level) = levels.get(task_id) {
+            return *level;
+        }
+
+        let level = self
+            .edges
+            .get(task_id)
+            .and_then(|deps| deps.iter().map(|d| self.level_util(d, levels) + 1).max())
+            .unwrap_or(0);
+
+        levels.insert(task_id.clone(), level);
+        return level;
+    }
+}
+
+/// Iterates over the waves of ready tasks of a graph, see `TaskGraph::waves`.
+pub struct Waves<'a> {
+    graph: &'a mut TaskGraph,
+    /// The tasks of the last wave returned
+    current: Vec<TaskID>,
+}
+
+impl Waves<'_> {
+    /// Mark a task of the current wave as failed, see `TaskGraph::fail`.
+    pub fn fail(&mut self, task_id: &TaskID) {
+        self.graph.fail(task_id);
+    }
+}
+
+impl Iterator for Waves<'_> {
+    type Item = Vec<Task>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for task_id in std::mem::take(&mut self.current) {
+            if self.graph.status(&task_id) == Some(TaskStatus::Running) {
+                self.graph.done(&task_id);
+            }
+        }
+
+        let wave = self.graph.ready_tasks();
+        if wave.is_empty() {
+            return None;
+        }
+        for task in &wave {
+            self.graph.start(task.id());
+            self.current.push(task.id().clone());
+        }
+
+        return Some(wave);
+    }
+}
+
+impl Iterator for TaskGraph {
+    // Some means a task is ready to be run
+    // None means there are tasks remaining but none are ready to be run
+    type Item = Option<Task>;
+
+    // Has to be called manually due to returning None even when there are tasks
+    // left
+    fn next(&mut self) -> Option<Self::Item> {
+        // task candidate
+        // Later tasks should not be possible before this task
+
+        // loop through all the tasks to find a task that is ready to be run
+        if self.ordered_tasks.is_empty() {
+            return None;
+        }
+
+        let ready = self
+            .ordered_tasks
+            .iter()
+            .find(|task_id| self.is_ready(task_id))
+            .cloned();
+
+        if let Some(task_id) = ready {
+            // return the task
+            return Some(self.start(&task_id.into()));
+        }
+        return Some(None);
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct TaskGraphBuilder {
+    tasks: HashMap<TaskID, Task>,
+    edges: HashMap<TaskID, Vec<TaskID>>,
+    /// Soft dependencies, stored as a -> (b, c) like `edges`
+    soft_edges: Edges,
+    /// Dependency patterns, expanded against the known tasks on build
+    dependency_patterns: HashMap<TaskID, Vec<String>>,
+    /// Whether a pattern matching no tasks is an error rather than a warning
+    strict_patterns: bool,
+    /// Patterns of the tasks left out of the graph on build
+    exclude_patterns: Vec<String>,
+}
+
+impl TaskGraphBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_task(&mut self, task: Task) {
+        self.edges.entry(task.id.clone()).or_default();
+        self.tasks.insert(task.id.clone(), task);
+    }
+
+    /// Whether a task with the ID was added.
+    pub fn has_task(&self, id: &str) -> bool {
+        self.tasks.contains_key(id)
+    }
+
+    /// Add a dependency to a task
+    /// # Arguments
+    /// * `task` - The task to add a dependency to
+    /// * `dependency` - The task that the task depends on
+    pub fn add_dependency(&mut self, task: TaskID, dependency: TaskID) {
+        self.edges.entry(task).or_default().push(dependency);
+    }
+
+    /// Add several dependencies to a task at once, e.g. all the dependencies
+    /// a task lists in its config.
+    /// Same as calling `add_dependency` for each of them, except dependencies
+    /// the task already has are not added again.
+    /// # Arguments
+    /// * `task` - The task to add the dependencies to
+    /// * `dependencies` - The tasks that the task depends on
+    pub fn add_dependencies(&mut self, task: TaskID, dependencies: Vec<TaskID>) {
+        let deps = self.edges.entry(task).or_default();
+        for dependency in dependencies {
+            if !deps.contains(&dependency) {
+                deps.push(dependency);
+            }
+        }
+    }
+
+    /// Add a soft dependency to a task.
+    /// The task waits for the dependency to finish, but runs whether it
+    /// succeeded or not. Useful for best effort steps like optional codegen.
+    /// A hard dependency on the same task takes precedence.
+    /// # Arguments
+    /// * `task` - The task to add a dependency to
+    /// * `dependency` - The task that the task waits for
+    pub fn add_soft_dependency(&mut self, task: TaskID, dependency: TaskID) {
+        self.edges.entry(task.clone()).or_default();
+        self.soft_edges.entry(task).or_default().push(dependency);
+    }
+
+    /// Add a dependency on every task matching a pattern.
+    /// A `*` in the pattern matches any run of characters except `:`, so
+    /// `lib:*:build` matches `lib:utils:build` but not `lib:a:b:build`. The
+    /// pattern is expanded when the graph is built, and never matches the task
+    /// itself.
+    /// # Arguments
+    /// * `task` - The task to add the dependencies to
+    /// * `pattern` - The pattern the dependencies' IDs match
+    pub fn add_dependency_glob(&mut self, task: TaskID, pattern: &str) {
+        self.edges.entry(task.clone()).or_default();
+        self.dependency_patterns
+            .entry(task)
+            .or_default()
+            .push(pattern.to_string());
+    }
+
+    /// Leave the tasks matching a pattern out of the graph, e.g. `e2e:*`.
+    /// Uses the same patterns as `add_dependency_glob`. Soft dependencies on
+    /// the excluded tasks are dropped, but a hard dependency on one fails the
+    /// build. With `build_for`, the tasks only needed by excluded tasks are
+    /// left out too.
+    /// # Arguments
+    /// * `pattern` - The pattern of the tasks to exclude
+    pub fn exclude(&mut self, pattern: &str) {
+        self.exclude_patterns.push(pattern.to_string());
+    }
+
+    /// Removes the excluded tasks, see `exclude`.
+    fn apply_excludes(mut self) -> Result<Self, BuildError> {
+        let patterns = std::mem::take(&mut self.exclude_patterns);
+        if patterns.is_empty() {
+            return Ok(self);
+        }
+        let excluded = |id: &str| patterns.iter().any(|p| glob_match(p, id));
+
+        let mut kept = self
+            .edges
+            .keys()
+            .filter(|t| !excluded(t))
+            .collect::<Vec<&TaskID>>();
+        kept.sort();
+        for task in kept {
+            if let Some(dep) = self.edges[task].iter().find(|d| excluded(d)) {
+                return Err(BuildError::ExcludedDependency {
+                    task: task.clone(),
+                    dependency: dep.clone(),
+                });
+            }
+        }
+
+        self.tasks.retain(|t, _| !excluded(t));
+        self.edges.retain(|t, _| !excluded(t));
+        self.dependency_patterns.retain(|t, _| !excluded(t));
+        self.soft_edges.retain(|t, _| !excluded(t));
+        for deps in self.soft_edges.values_mut() {
+            deps.retain(|d| !excluded(d));
+        }
+
+        return Ok(self);
+    }
+
+    /// Set whether a dependency pattern that matches no tasks fails the build.
+    /// Otherwise it is reported in `TaskGraph::warnings`. Defaults to `false`.
+    pub fn strict_patterns(&mut self, strict: bool) {
+        self.strict_patterns = strict;
+    }
+
+    /// Expand the dependency patterns into dependencies on the matching tasks.
+    /// # Returns
+    /// * `Edges` - The edges, including the expanded patterns
+    /// * `Vec<BuildWarning>` - The patterns that did not match any task
+    fn expand_patterns(&self) -> Result<(Edges, Vec<BuildWarning>), BuildError> {
+        let mut edges = self.edges.clone();
+        let mut warnings: Vec<BuildWarning> = vec![];
+
+        for deps in edges.values_mut() {
+            let mut seen: HashSet<TaskID> = HashSet::new();
+            deps.retain(|d| seen.insert(d.clone()));
+        }
+
+        for (task, patterns) in &self.dependency_patterns {
+            for pattern in patterns {
+                let mut matches = self
+                    .tasks
+                    .keys()
+                    .filter(|id| *id != task && glob_match(pattern, id))
+                    .cloned()
+                    .collect::<Vec<TaskID>>();
+                matches.sort();
+
+                if matches.is_empty() {
+                    if self.strict_patterns {
+                        return Err(BuildError::UnmatchedPattern {
+                            task: task.clone(),
+                            pattern: pattern.clone(),
+                        });
+                    }
+                    warnings.push(BuildWarning::UnmatchedPattern {
+                        task: task.clone(),
+                        pattern: pattern.clone(),
+                    });
+                    continue;
+                }
+
+                let deps = edges.entry(task.clone()).or_default();
+                for dep in matches {
+                    if !deps.contains(&dep) {
+                        deps.push(dep);
+                    }
+                }
+            }
+        }
+
+        return Ok((edges, warnings));
+    }
+
+    /// Add the soft dependencies to the edges. A soft dependency that is also
+    /// hard stays hard, the stronger kind.
+    /// # Returns
+    /// * `HashSet<(TaskID, TaskID)>` - The (task, dependency) pairs that are
+    ///   soft
+    /// * `Vec<BuildWarning>` - The pairs declared both hard and soft, sorted
+    fn merge_soft_edges(
+        &self,
+        edges: &mut Edges,
+    ) -> (HashSet<(TaskID, TaskID)>, Vec<BuildWarning>) {
+        let mut soft: HashSet<(TaskID, TaskID)> = HashSet::new();
+        let mut conflicts: Vec<(TaskID, TaskID)> = vec![];
+
+        for (task, soft_deps) in &self.soft_edges {
+            let deps = edges.entry(task.clone()).or_default();
+            for dep in soft_deps {
+                let pair = (task.clone(), dep.clone());
+                if !deps.contains(dep) {
+                    deps.push(dep.clone());
+                    soft.insert(pair);
+                } else if !soft.contains(&pair) && !conflicts.contains(&pair) {
+                    conflicts.push(pair);
+                }
+            }
+        }
+
+        conflicts.sort();
+        let warnings = conflicts
+            .into_iter()
+            .map(|(task, dependency)| BuildWarning::ConflictingDependencyKinds { task, dependency })
+            .collect();
+        return (soft, warnings);
+    }
+
+    /// Check that the graph can be built, without building it.
+    /// Runs the same checks as `build`: patterns, dangling dependencies,
+    /// excluded dependencies and cycles.
+    pub fn validate(&self) -> Result<(), BuildError> {
+        self.clone().apply_excludes()?.resolve()?;
+        return Ok(());
+    }
+
+    /// Validates the graph and analyzes its shape in a single traversal,
+    /// without building it. See `GraphAnalysis` for what is computed.
+    pub fn analyze(&self) -> Result<GraphAnalysis, BuildError> {
+        let Resolved {
+            edges,
+            ordered_tasks,
+            ..
+        } = self.clone().apply_excludes()?.resolve()?;
+
+        // Dependencies come first in the order, so their level is known
+        let mut levels: HashMap<TaskID, usize> = HashMap::new();
+        for task_id in &ordered_tasks {
+            let level = edges[task_id]
+                .iter()
+                .map(|dep| levels[dep] + 1)
+                .max()
+                .unwrap_or(0);
+            levels.insert(task_id.clone(), level);
+        }
+
+        let needed = edges.values().flatten().collect::<HashSet<&TaskID>>();
+        let mut roots = ordered_tasks
+            .iter()
+            .filter(|t| !needed.contains(t))
+            .cloned()
+            .collect::<Vec<TaskID>>();
+        roots.sort();
+        let mut leaves = ordered_tasks
+            .iter()
+            .filter(|t| edges[*t].is_empty())
+            .cloned()
+            .collect::<Vec<TaskID>>();
+        leaves.sort();
+
+        return Ok(GraphAnalysis {
+            order: ordered_tasks,
+            levels,
+            roots,
+            leaves,
+        });
+    }
+
+    /// Runs the checks of the graph, producing everything `build` needs.
+    fn resolve(&self) -> Result<Resolved, BuildError> {
+        let (mut edges, mut warnings) = self.expand_patterns()?;
+        let (soft, conflicts) = self.merge_soft_edges(&mut edges);
+        warnings.extend(conflicts);
+
+        for (task, deps) in &edges {
+            if !self.tasks.contains_key(task) {
+                return Err(BuildError::UnknownTask(task.clone()));
+            }
+            if let Some(dep) = deps.iter().find(|d| !self.tasks.contains_key(*d)) {
+                return Err(BuildError::MissingDependency {
+                    task: task.clone(),
+                    dependency: dep.clone(),
+                });
+            }
+        }
+
+        // The tasks of a cycle, and those depending on one, never become ready
+        let ordered_tasks = stable_order(&edges);
+        if ordered_tasks.len() < edges.len() {
+            return Err(BuildError::Cycle(cycle_task(&edges, &ordered_tasks)));
+        }
+
+        return Ok(Resolved {
+            edges,
+            soft,
+            warnings,
+            ordered_tasks,
+        });
+    }
+
+    /// Build the task graph
+    /// Also creates the ordered tasks
+    /// # Returns
+    /// * `TaskGraph` - The task graph
+    pub fn build(self) -> Result<TaskGraph, BuildError> {
+        let builder = self.apply_excludes()?;
+        let Resolved {
+            edges,
+            soft,
+            warnings,
+            ordered_tasks,
+        } = builder.resolve()?;
+
+        let ordered_tasks = ordered_tasks
+            .into_iter()
+            .map(TaskId::from)
+            .collect::<Vec<TaskId>>();
+        let status = ordered_tasks
+            .iter()
+            .map(|t| (t.clone(), TaskStatus::Pending))
+            .collect();
+
+        let edges = edges
+            .into_iter()
+            .map(|(t, deps)| (t.into(), deps.into_iter().map(TaskId::from).collect()))
+            .collect::<IdEdges>();
+        let mut dependents: IdEdges = HashMap::new();
+        for (task, deps) in &edges {
+            for dep in deps {
+                dependents
+                    .entry(dep.clone())
+                    .or_default()
+                    .push(task.clone());
+            }
+        }
+        for deps in dependents.values_mut() {
+            deps.sort();
+        }
+        let pending_deps = edges
+            .iter()
+            .map(|(t, deps)| (t.clone(), deps.len()))
+            .collect();
+
+        return Ok(TaskGraph {
+            tasks: builder
+                .tasks
+                .into_iter()
+                .map(|(t, task)| (t.into(), task))
+                .collect(),
+            edges,
+            soft: soft
+                .into_iter()
+                .map(|(t, d)| (t.into(), d.into()))
+                .collect(),
+            dependents,
+            done: HashSet::new(),
+            pending_deps,
+            ordered_tasks,
+            status,
+            warnings,
+            unreachable: vec![],
+            reasons: HashMap::new(),
+        });
+    }
+
+    /// Build a task graph containing only the given tasks and everything they
+    /// depend on, directly or not.
+    /// # Arguments
+    /// * `roots` - The tasks to run
+    /// # Returns
+    /// * `TaskGraph` - The task graph for the roots
+    pub fn build_for(self, roots: &[TaskID]) -> Result<TaskGraph, BuildError> {
+        if let Some(root) = roots.iter().find(|r| !self.tasks.contains_key(*r)) {
+            return Err(BuildError::UnknownRoot(root.clone()));
+        }
+        let builder = self.apply_excludes()?;
+        let roots = roots
+            .iter()
+            .filter(|r| builder.tasks.contains_key(*r))
+            .cloned()
+            .collect::<Vec<TaskID>>();
+
+        let (mut edges, warnings) = builder.expand_patterns()?;
+
+        let mut all_edges = edges.clone();
+        builder.merge_soft_edges(&mut all_edges);
+
+        // Breadth first, so each task is explained by its closest root
+        let mut reasons: HashMap<TaskID, RunReason> = HashMap::new();
+        let mut queue = roots.into_iter().collect::<VecDeque<TaskID>>();
+        for root in &queue {
+            reasons.insert(root.clone(), RunReason::Requested);
+        }
+        while let Some(task_id) = queue.pop_front() {
+            let mut deps = all_edges.get(&task_id).cloned().unwrap_or_default();
+            deps.sort();
+            for dep in deps {
+                if !reasons.contains_key(&dep) {
+                    reasons.insert(dep.clone(), RunReason::DependencyOf(task_id.clone()));
+                    queue.push_back(dep);
+                }
+            }
+        }
+        let reachable = reasons.keys().cloned().collect::<HashSet<TaskID>>();
+
+        let mut unreachable = builder
+            .tasks
+            .keys()
+            .filter(|t| !reachable.contains(*t))
+            .map(|t| {
+                let mut dependents = all_edges
+                    .iter()
+                    .filter(|(_, deps)| deps.contains(t))
+                    .map(|(d, _)| d.clone())
+                    .collect::<Vec<TaskID>>();
+                dependents.sort();
+
+                if dependents.is_empty() {
+                    return (t.clone(), UnreachableReason::NotRequested);
+                }
+                return (t.clone(), UnreachableReason::OnlyNeededBy(dependents));
+            })
+            .collect::<Vec<(TaskID, UnreachableReason)>>();
+        unreachable.sort_by(|a, b| a.0.cmp(&b.0));
+
+        edges.retain(|t, _| reachable.contains(t));
+        let mut soft_edges = builder.soft_edges;
+        soft_edges.retain(|t, _| reachable.contains(t));
+        let pruned = TaskGraphBuilder {
+            tasks: builder
+                .tasks
+                .into_iter()
+                .filter(|(t, _)| reachable.contains(t))
+                .collect(),
+            edges,
+            soft_edges,
+            // Already expanded against the whole graph
+            dependency_patterns: HashMap::new(),
+            strict_patterns: builder.strict_patterns,
+            exclude_patterns: vec![],
+        };
+
+        let mut graph = pruned.build()?;
+        // The conflicts are found again by the build, among the kept tasks
+        let conflicts = std::mem::take(&mut graph.warnings);
+        graph.warnings = warnings
+            .into_iter()
+            .filter(|w| match w {
+                BuildWarning::UnmatchedPattern { task, .. } => reachable.contains(task),
+                BuildWarning::ConflictingDependencyKinds { .. } => false,
+            })
+            .chain(conflicts)
+            .collect();
+        graph.unreachable = unreachable;
+        graph.reasons = reasons;
+
+        return Ok(graph);
+    }
+}
+
+/// The shape of a valid task graph, see `TaskGraphBuilder::analyze`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphAnalysis {
+    /// The tasks in topological order, dependencies first
+    pub order: Vec<TaskID>,
+    /// The level of each task, as in `TaskGraph::levels`
+    pub levels: HashMap<TaskID, usize>,
+    /// The tasks that no task depends on, sorted
+    pub roots: Vec<TaskID>,
+    /// The tasks without dependencies, sorted
+    pub leaves: Vec<TaskID>,
+}
+
+/// The outcome of `TaskGraph::simulate_schedule`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatedRun {
+    /// How long the whole run takes
+    pub makespan: Duration,
+    /// Every task, in the order they end
+    pub tasks: Vec<SimulatedTask>,
+}
+
+/// When a task starts and ends in a simulated run, from the start of the run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatedTask {
+    pub id: TaskID,
+    pub start: Duration,
+    pub end: Duration,
+}
+
+/// Everything `TaskGraphBuilder::resolve` produces.
+struct Resolved {
+    /// The edges, including the expanded patterns and the soft dependencies
+    edges: Edges,
+    /// The (task, dependency) pairs that are soft
+    soft: HashSet<(TaskID, TaskID)>,
+    /// The non-fatal issues found
+    warnings: Vec<BuildWarning>,
+    /// The tasks ordered using topological sort
+    ordered_tasks: Vec<TaskID>,
+}
+
+/// Encodes bytes as lowercase hex.
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Matches a task ID against a pattern where `*` matches any run of
+/// characters except `:`.
+fn glob_match(pattern: &str, id: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == id,
+        Some((prefix, rest)) => {
+            let Some(id) = id.strip_prefix(prefix) else {
+                return false;
+            };
+            // Try every possible length for the `*` within the segment
+            let segment_len = id.find(':').unwrap_or(id.len());
+            return (0..=segment_len).any(|i| glob_match(rest, &id[i..]));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::super::executor::NOOP_EXECUTOR;
+    use super::*;
+
+    fn task(id: &str) -> Task {
+        Task::new(id, id, Action::shell(vec![]))
+    }
+
+    /// Creates an empty directory unique to the test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nx-rs-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_graph_creation() {
+        let n_a = task("a");
+        let n_b = task("b");
+        let n_c = task("c");
+        let n_d = task("d");
+
+        let mut builder = TaskGraphBuilder::new();
+
+        builder.add_task(n_a.clone());
+        builder.add_task(n_b.clone());
+        builder.add_task(n_c.clone());
+        builder.add_task(n_d.clone());
+
+        builder.add_dependency(n_a.id.clone(), n_b.id.clone());
+        builder.add_dependency(n_a.id.clone(), n_d.id.clone());
+        builder.add_dependency(n_b.id.clone(), n_c.id.clone());
+
+        assert!(builder.clone().build().is_ok(), "Graph should be valid");
+
+        builder.add_dependency(n_d.id.clone(), n_c.id.clone());
+        assert!(
+            builder.clone().build().is_ok(),
+            "Graph should be valid with shared dependencies"
+        );
+
+        builder.add_dependency(n_d.id.clone(), n_b.id.clone());
+        assert!(
+            builder.clone().build().is_ok(),
+            "Graph should be valid with multiple shared dependencies"
+        );
+
+        builder.add_dependency(n_b.id.clone(), n_d.id.clone());
+        assert!(
+            builder.clone().build().is_err(),
+            "Graph should be invalid with circular dependencies"
+        );
+    }
+
+    #[test]
+    fn test_get_tasks() {
+        for _ in 0..10 {
+            let n_a = task("a");
+            let n_b = task("b");
+            let n_c = task("c");
+            let n_d = task("d");
+
+            let mut builder = TaskGraphBuilder::new();
+
+            builder.add_task(n_a.clone());
+            builder.add_task(n_b.clone());
+            builder.add_task(n_c.clone());
+            builder.add_task(n_d.clone());
+
+            builder.add_dependency(n_a.id.clone(), n_b.id.clone());
+            builder.add_dependency(n_a.id.clone(), n_d.id.clone());
+            builder.add_dependency(n_a.id.clone(), n_c.id.clone());
+            builder.add_dependency(n_b.id.clone(), n_c.id.clone());
+
+            let mut graph = builder.build().unwrap();
+
+            let first = graph.next().unwrap().unwrap();
+            assert!(
+                [n_c.clone(), n_d.clone()].contains(&first),
+                "First task should be c or d",
+            );
+
+            if first == n_d {
+                // first task is d, so we need to do c first
+                graph.done(&n_d.id);
+                assert_eq!(
+                    graph.next(),
+                    Some(Some(n_c.clone())),
+                    "Next task should be c"
+                );
+            } else {
+                // else first task is c, so we need to do d first
+                assert_eq!(
+                    graph.next(),
+                    Some(Some(n_d.clone())),
+                    "Next task should be d"
+                );
+
+                graph.done(&n_d.id);
+            }
+
+            assert_eq!(
+                graph.next(),
+                Some(None),
+                "There are tasks remaining, but none are ready"
+            );
+
+            graph.done(&n_c.id);
+            assert_eq!(
+                graph.next(),
+                Some(Some(n_b.clone())),
+                "Next task should be b"
+            );
+            graph.done(&n_b.id);
+
+            assert_eq!(
+                graph.next(),
+                Some(Some(n_a.clone())),
+                "Final task should be a"
+            );
+            graph.done(&n_a.id);
+
+            assert_eq!(
+                graph.next(),
+                None,
+                "Should return None if no tasks are ready"
+            );
+
+            // remaining tasks should be 0
+            assert_eq!(graph.remaining(), 0, "Should have no remaining tasks");
+        }
+    }
+
+    #[test]
+    fn test_levels() {
+        let n_a = task("a");
+        let n_b = task("b");
+        let n_c = task("c");
+        let n_d = task("d");
+
+        let mut builder = TaskGraphBuilder::new();
+
+        builder.add_task(n_a.clone());
+        builder.add_task(n_b.clone());
+        builder.add_task(n_c.clone());
+        builder.add_task(n_d.clone());
+
+        builder.add_dependency(n_a.id.clone(), n_b.id.clone());
+        builder.add_dependency(n_a.id.clone(), n_d.id.clone());
+        builder.add_dependency(n_b.id.clone(), n_c.id.clone());
+        builder.add_dependency(n_d.id.clone(), n_c.id.clone());
+
+        let graph = builder.build().unwrap();
+        let levels = graph.levels();
+
+        assert_eq!(levels.get("a"), Some(&2), "a depends on b and d");
+        assert_eq!(levels.get("b"), Some(&1), "b depends on c");
+        assert_eq!(levels.get("d"), Some(&1), "d depends on c");
+        assert_eq!(levels.get("c"), Some(&0), "c has no dependencies");
+    }
+
+    #[test]
+    fn test_enumerate_plan() {
+        let build = || {
+            let mut builder = TaskGraphBuilder::new();
+            for id in ["app", "lib", "core", "docs", "cli"] {
+                builder.add_task(task(id));
+            }
+            builder.add_dependency("app".to_string(), "lib".to_string());
+            builder.add_dependency("lib".to_string(), "core".to_string());
+            builder.add_dependency("cli".to_string(), "core".to_string());
+            return builder.build().unwrap();
+        };
+
+        let mut graph = build();
+        let plan = graph.enumerate_plan();
+        let step = |id: &str| plan.iter().find(|(_, t)| t == id).unwrap().0;
+        assert_eq!(plan.len(), 5);
+        assert_eq!(plan[0].0, 1);
+        assert!(step("core") < step("lib") && step("lib") < step("app"));
+        assert!(step("core") < step("cli"));
+        for _ in 0..10 {
+            assert_eq!(build().enumerate_plan(), plan, "The plan is deterministic");
+        }
+
+        // Not consumed, the plan is what is left to run
+        assert_eq!(graph.remaining(), 5);
+        let first = graph.start(&plan[0].1).unwrap();
+        graph.done(first.id());
+        assert_eq!(graph.enumerate_plan()[0], (1, plan[1].1.clone()));
+    }
+
+    #[test]
+    fn test_progress() {
+        let mut builder = TaskGraphBuilder::new();
+        for id in ["a", "b", "c", "d"] {
+            builder.add_task(task(id));
+        }
+        builder.add_dependency("b".to_string(), "a".to_string());
+        let mut graph = builder.build().unwrap();
+        assert_eq!(graph.progress(), 0.0);
+
+        let c = graph.start(&"c".to_string()).unwrap();
+        assert_eq!(graph.progress(), 0.0, "Running tasks are not finished");
+        graph.done(&c.id);
+        assert_eq!(graph.progress(), 0.25);
+
+        // b is skipped along with a
+        graph.fail(&"a".to_string());
+        assert_eq!(graph.progress(), 0.75);
+        graph.done(&"d".to_string());
+        assert_eq!(graph.progress(), 1.0);
+
+        assert_eq!(TaskGraphBuilder::new().build().unwrap().progress(), 1.0);
+    }
+
+    #[test]
+    fn test_status_summary() {
+        let n_a = task("a");
+        let n_b = task("b");
+        let n_c = task("c");
+        let n_d = task("d");
+
+        let mut builder = TaskGraphBuilder::new();
+
+        builder.add_task(n_a.clone());
+        builder.add_task(n_b.clone());
+        builder.add_task(n_c.clone());
+        builder.add_task(n_d.clone());
+
+        builder.add_dependency(n_a.id.clone(), n_b.id.clone());
+        builder.add_dependency(n_b.id.clone(), n_c.id.clone());
+
+        let mut graph = builder.build().unwrap();
+        assert_eq!(
+            graph.status_summary().get(&TaskStatus::Pending),
+            Some(&4),
+            "All tasks should start pending"
+        );
+
+        graph.fail(&n_c.id);
+        assert_eq!(
+            graph.next(),
+            Some(Some(n_d.clone())),
+            "Only d should be left to run"
+        );
+        graph.done(&n_d.id);
+
+        assert_eq!(graph.status(&n_b.id), Some(TaskStatus::Skipped));
+        assert_eq!(graph.status(&n_a.id), Some(TaskStatus::Skipped));
+
+        let summary = graph.status_summary();
+        assert_eq!(summary.get(&TaskStatus::Succeeded), Some(&1));
+        assert_eq!(summary.get(&TaskStatus::Failed), Some(&1));
+        assert_eq!(summary.get(&TaskStatus::Skipped), Some(&2));
+        assert_eq!(summary.get(&TaskStatus::Pending), None);
+        assert_eq!(graph.next(), None, "Skipped tasks should not be returned");
+    }
+
+    #[test]
+    fn test_dependency_glob() {
+        let mut builder = TaskGraphBuilder::new();
+
+        builder.add_task(task("app:test"));
+        builder.add_task(task("lib:a:build"));
+        builder.add_task(task("lib:b:build"));
+        builder.add_task(task("lib:b:test"));
+        builder.add_task(task("lib:c:d:build"));
+
+        builder.add_dependency_glob("app:test".to_string(), "lib:*:build");
+        builder.add_dependency_glob("app:test".to_string(), "missing:*");
+
+        let graph = builder.clone().build().unwrap();
+        assert_eq!(
+            graph.edges.get("app:test").unwrap(),
+            &vec!["lib:a:build".to_string(), "lib:b:build".to_string()],
+            "Pattern should only match within a segment"
+        );
+        assert_eq!(
+            graph.warnings(),
+            &[BuildWarning::UnmatchedPattern {
+                task: "app:test".to_string(),
+                pattern: "missing:*".to_string(),
+            }]
+        );
+
+        builder.strict_patterns(true);
+        assert_eq!(
+            builder.build().err(),
+            Some(BuildError::UnmatchedPattern {
+                task: "app:test".to_string(),
+                pattern: "missing:*".to_string(),
+            }),
+            "Strict builder should fail on unmatched patterns"
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut builder = TaskGraphBuilder::new();
+
+        builder.add_task(task("a"));
+        builder.add_task(task("b"));
+        builder.add_dependency("a".to_string(), "b".to_string());
+        assert_eq!(builder.validate(), Ok(()), "Graph should be valid");
+
+        let mut dangling = builder.clone();
+        dangling.add_dependency("a".to_string(), "c".to_string());
+        assert_eq!(
+            dangling.validate(),
+            Err(BuildError::MissingDependency {
+                task: "a".to_string(),
+                dependency: "c".to_string(),
+            })
+        );
+
+        let mut unknown = builder.clone();
+        unknown.add_dependency("c".to_string(), "a".to_string());
+        assert_eq!(
+            unknown.validate(),
+            Err(BuildError::UnknownTask("c".to_string()))
+        );
+
+        // A cycle without any root
+        builder.add_dependency("b".to_string(), "a".to_string());
+        assert_eq!(builder.validate(), Err(BuildError::Cycle("a".to_string())));
+        assert!(
+            builder.clone().build().is_err(),
+            "build should run the same checks"
+        );
+
+        // Reported from within the cycle, not from a task depending on it
+        let mut downstream = builder.clone();
+        downstream.add_task(task("0-first"));
+        downstream.add_dependency("0-first".to_string(), "b".to_string());
+        assert_eq!(
+            downstream.validate(),
+            Err(BuildError::Cycle("b".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_build_for() {
+        let mut builder = TaskGraphBuilder::new();
+
+        builder.add_task(task("a"));
+        builder.add_task(task("b"));
+        builder.add_task(task("c"));
+        builder.add_task(task("d"));
+
+        builder.add_dependency("a".to_string(), "b".to_string());
+        builder.add_dependency("b".to_string(), "c".to_string());
+        builder.add_dependency("d".to_string(), "c".to_string());
+
+        let mut graph = builder.clone().build_for(&["b".to_string()]).unwrap();
+        assert_eq!(graph.remaining(), 2, "Only b and c should be in the graph");
+        assert_eq!(graph.next(), Some(Some(task("c"))));
+        graph.done(&"c".to_string());
+        assert_eq!(graph.next(), Some(Some(task("b"))));
+        graph.done(&"b".to_string());
+        assert_eq!(graph.next(), None);
+
+        assert_eq!(
+            graph.unreachable_tasks(),
+            &[
+                ("a".to_string(), UnreachableReason::NotRequested),
+                ("d".to_string(), UnreachableReason::NotRequested),
+            ]
+        );
+
+        let graph = builder.clone().build_for(&["d".to_string()]).unwrap();
+        assert_eq!(
+            graph.unreachable_tasks(),
+            &[
+                ("a".to_string(), UnreachableReason::NotRequested),
+                (
+                    "b".to_string(),
+                    UnreachableReason::OnlyNeededBy(vec!["a".to_string()])
+                ),
+            ]
+        );
+
+        assert_eq!(
+            builder.build_for(&["e".to_string()]).err(),
+            Some(BuildError::UnknownRoot("e".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_reason() {
+        let mut builder = TaskGraphBuilder::new();
+        for id in ["app:build", "app:test", "lib:build", "core:build", "docs"] {
+            builder.add_task(task(id));
+        }
+        builder.add_dependency("app:test".to_string(), "app:build".to_string());
+        builder.add_dependency("app:build".to_string(), "lib:build".to_string());
+        builder.add_dependency("app:test".to_string(), "core:build".to_string());
+        builder.add_dependency("lib:build".to_string(), "core:build".to_string());
+
+        let graph = builder
+            .clone()
+            .build_for(&["app:test".to_string(), "app:build".to_string()])
+            .unwrap();
+        let reason = |id: &str| graph.reason(&id.to_string());
+        assert_eq!(reason("app:test"), Some(RunReason::Requested));
+        assert_eq!(reason("app:build"), Some(RunReason::Requested));
+        assert_eq!(
+            reason("lib:build"),
+            Some(RunReason::DependencyOf("app:build".to_string()))
+        );
+        // Pulled in by both, closest to `app:test`
+        assert_eq!(
+            reason("core:build"),
+            Some(RunReason::DependencyOf("app:test".to_string()))
+        );
+        assert_eq!(reason("docs"), None);
+        assert_eq!(
+            reason("lib:build").unwrap().to_string(),
+            "dependency of app:build"
+        );
+
+        let graph = builder.build().unwrap();
+        assert_eq!(
+            graph.reason(&"core:build".to_string()),
+            Some(RunReason::Requested)
+        );
+    }
+
+    #[test]
+    fn test_resolve_inputs() {
+        let root = temp_dir("resolve-inputs");
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::create_dir_all(root.join("target")).unwrap();
+        std::fs::write(root.join(".gitignore"), "target/\n").unwrap();
+        std::fs::write(root.join("src/main.rs"), "").unwrap();
+        std::fs::write(root.join("src/notes.md"), "").unwrap();
+        std::fs::write(root.join("target/out.rs"), "").unwrap();
+
+        let t = task("a").with_inputs(vec!["**/*.rs".to_string()]);
+        assert_eq!(
+            t.resolve_inputs(&root).unwrap(),
+            vec![root.join("src/main.rs")],
+            "Ignored files should not be inputs"
+        );
+
+        let t = t.include_ignored_inputs(true);
+        assert_eq!(
+            t.resolve_inputs(&root).unwrap(),
+            vec![root.join("src/main.rs"), root.join("target/out.rs")],
+            "Ignored files should be inputs when asked for"
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_outputs_up_to_date() {
+        let root = temp_dir("up-to-date");
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        let now = SystemTime::now();
+        let touch = |path: &str, age: u64| {
+            let file = std::fs::File::create(root.join(path)).unwrap();
+            file.set_modified(now - std::time::Duration::from_secs(age))
+                .unwrap();
+        };
+        touch("src/main.rs", 20);
+        touch("src/lib.rs", 10);
+
+        let t = task("a")
+            .with_inputs(vec!["src/*.rs".to_string()])
+            .with_outputs(vec![PathBuf::from("app"), PathBuf::from("app.d")]);
+        assert!(!t.outputs_up_to_date(&root).unwrap(), "Outputs are missing");
+
+        touch("app", 5);
+        touch("app.d", 15);
+        assert!(
+            !t.outputs_up_to_date(&root).unwrap(),
+            "`app.d` is older than `src/lib.rs`"
+        );
+        touch("app.d", 5);
+        assert!(t.outputs_up_to_date(&root).unwrap());
+
+        touch("src/lib.rs", 0);
+        assert!(!t.outputs_up_to_date(&root).unwrap());
+
+        let no_inputs = task("b").with_outputs(vec![PathBuf::from("app")]);
+        assert!(no_inputs.outputs_up_to_date(&root).unwrap());
+        assert!(!task("c").outputs_up_to_date(&root).unwrap());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_blocked_by() {
+        let mut builder = TaskGraphBuilder::new();
+        for id in ["lib", "app", "e2e", "docs", "site", "other"] {
+            builder.add_task(task(id));
+        }
+        builder.add_dependency("app".to_string(), "lib".to_string());
+        builder.add_dependency("e2e".to_string(), "app".to_string());
+        builder.add_soft_dependency("docs".to_string(), "lib".to_string());
+        builder.add_dependency("site".to_string(), "docs".to_string());
+        let mut graph = builder.build().unwrap();
+
+        assert_eq!(graph.blocked_by(&"lib".to_string()), vec!["app", "e2e"]);
+        assert_eq!(graph.blocked_by(&"docs".to_string()), vec!["site"]);
+        assert!(graph.blocked_by(&"other".to_string()).is_empty());
+
+        // Exactly the tasks a failure skips
+        graph.fail(&"lib".to_string());
+        let mut skipped = graph
+            .statuses()
+            .iter()
+            .filter(|(_, status)| **status == TaskStatus::Skipped)
+            .map(|(id, _)| id.to_string())
+            .collect::<Vec<TaskID>>();
+        skipped.sort();
+        assert_eq!(skipped, vec!["app", "e2e"]);
+    }
+
+    #[test]
+    fn test_run_one() {
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(task("lib"));
+        builder.add_task(Task::new(
+            "app",
+            "app",
+            Action::shell(vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "exit 3".to_string(),
+            ]),
+        ));
+        builder.add_dependency("app".to_string(), "lib".to_string());
+        let graph = builder.build().unwrap();
+
+        let status = graph.run_one(&"app".to_string()).unwrap();
+        assert_eq!(status.code(), Some(3), "Runs even though `lib` is not done");
+        assert_eq!(graph.status(&"app".to_string()), Some(TaskStatus::Pending));
+
+        // The task's environment reaches the process
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(
+            Task::new(
+                "env",
+                "env",
+                Action::shell(vec![
+                    "sh".to_string(),
+                    "-c".to_string(),
+                    "exit $NX_RS_RUN_ONE".to_string(),
+                ]),
+            )
+            .with_env(HashMap::from([(
+                "NX_RS_RUN_ONE".to_string(),
+                "4".to_string(),
+            )])),
+        );
+        builder.add_task(task("noop").with_executor(NOOP_EXECUTOR));
+        let graph = builder.build().unwrap();
+        let status = graph.run_one(&"env".to_string()).unwrap();
+        assert_eq!(status.code(), Some(4));
+
+        let err = graph.run_one(&"noop".to_string()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`noop` runs with the executor `noop`, use an `Executor` to run it"
+        );
+
+        let err = graph.run_one(&"docs".to_string()).unwrap_err();
+        assert_eq!(err.to_string(), "unknown task `docs`");
+    }
+
+    #[test]
+    fn test_prune_done() {
+        let mut builder = TaskGraphBuilder::new();
+        for id in ["lib", "app", "test", "docs"] {
+            builder.add_task(task(id));
+        }
+        builder.add_dependency("app".to_string(), "lib".to_string());
+        builder.add_dependency("test".to_string(), "app".to_string());
+        builder.add_soft_dependency("docs".to_string(), "lib".to_string());
+        let mut graph = builder.build().unwrap();
+
+        graph.start(&"lib".to_string());
+        assert_eq!(graph.prune_done(), 0, "Running tasks are kept");
+        graph.done(&"lib".to_string());
+        assert_eq!(graph.prune_done(), 0, "`app` and `docs` still need `lib`");
+
+        graph.start(&"app".to_string());
+        graph.done(&"app".to_string());
+        graph.start(&"docs".to_string());
+        graph.fail(&"docs".to_string());
+        assert_eq!(graph.prune_done(), 2, "`lib` and `docs` are not needed");
+        assert!(!graph.tasks.contains_key("lib"));
+        assert!(!graph.tasks.contains_key("docs"));
+        assert!(graph.dependencies(&"lib".to_string()).is_empty());
+        assert_eq!(
+            graph.status(&"lib".to_string()),
+            Some(TaskStatus::Succeeded)
+        );
+
+        // The remaining tasks run as before
+        assert_eq!(graph.next().unwrap().unwrap().id(), "test");
+        graph.done(&"test".to_string());
+        assert!(graph.next().is_none());
+        assert_eq!(graph.critical_path(), vec!["app", "test"]);
+        assert_eq!(graph.prune_done(), 2);
+        assert!(graph.tasks.is_empty());
+
+        // Done without being started
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(task("lib"));
+        builder.add_task(task("app"));
+        builder.add_dependency("app".to_string(), "lib".to_string());
+        let mut graph = builder.build().unwrap();
+        graph.done(&"lib".to_string());
+        assert_eq!(graph.prune_done(), 0, "`app` still needs `lib`");
+        graph.done(&"app".to_string());
+        assert_eq!(graph.prune_done(), 2);
+        assert!(graph.ready_tasks().is_empty());
+        assert!(graph.next().is_none());
+        assert_eq!(graph.remaining(), 0);
+    }
+
+    #[test]
+    fn test_patch() {
+        let mut builder = TaskGraphBuilder::new();
+        for id in [
+            "lib:build",
+            "app:build",
+            "app:test",
+            "docs:build",
+            "tools:build",
+        ] {
+            builder.add_task(task(id));
+        }
+        builder.add_dependency("app:build".to_string(), "lib:build".to_string());
+        builder.add_dependency("app:test".to_string(), "app:build".to_string());
+        builder.add_dependency("docs:build".to_string(), "app:build".to_string());
+        let mut graph = builder.build().unwrap();
+        let edge = |t: &str, d: &str| (t.to_string(), d.to_string());
+
+        // `app` loses its test, and now also depends on `tools`
+        let outcome = graph
+            .patch(
+                "app",
+                vec![task("app:build")],
+                vec![
+                    edge("app:build", "lib:build"),
+                    edge("app:build", "tools:build"),
+                    edge("docs:build", "app:build"),
+                ],
+            )
+            .unwrap();
+        assert_eq!(outcome, PatchOutcome::Patched);
+        assert_eq!(graph.status(&"app:test".to_string()), None);
+        assert_eq!(
+            graph.dependencies(&"app:build".to_string()),
+            ["lib:build", "tools:build"]
+        );
+        assert_eq!(graph.dependents(&"app:build".to_string()), ["docs:build"]);
+
+        let mut order: Vec<TaskID> = vec![];
+        while let Some(task) = graph.next() {
+            let task = task.unwrap();
+            graph.done(task.id());
+            order.push(task.id().clone());
+        }
+        let position = |id: &str| order.iter().position(|t| t == id).unwrap();
+        assert_eq!(order.len(), 4);
+        assert!(position("tools:build") < position("app:build"));
+        assert!(position("app:build") < position("docs:build"));
+    }
+
+    #[test]
+    fn test_patch_errors() {
+        let mut builder = TaskGraphBuilder::new();
+        for id in [
+            "lib:build",
+            "app:build",
+            "docs:build",
+            "tools:build",
+            "site:build",
+        ] {
+            builder.add_task(task(id));
+        }
+        builder.add_dependency("app:build".to_string(), "lib:build".to_string());
+        let mut graph = builder.build().unwrap();
+        let edge = |t: &str, d: &str| (t.to_string(), d.to_string());
+
+        let cycle = graph.patch(
+            "lib",
+            vec![task("lib:build")],
+            vec![
+                edge("app:build", "lib:build"),
+                edge("lib:build", "app:build"),
+            ],
+        );
+        assert!(matches!(cycle, Err(BuildError::Cycle(_))), "{cycle:?}");
+        let missing = graph.patch(
+            "lib",
+            vec![task("lib:build")],
+            vec![edge("lib:build", "lib:codegen")],
+        );
+        assert!(matches!(missing, Err(BuildError::MissingDependency { .. })));
+        assert_eq!(
+            graph.dependents(&"lib:build".to_string()),
+            ["app:build"],
+            "A failed patch changes nothing"
+        );
+
+        let new_tasks = ["lib:a", "lib:b", "lib:c"].map(task).to_vec();
+        assert_eq!(
+            graph.patch("lib", new_tasks, vec![]).unwrap(),
+            PatchOutcome::RebuildNeeded
+        );
+        assert_eq!(graph.remaining(), 5);
+    }
+
+    #[test]
+    fn test_done_many() {
+        let mut builder = TaskGraphBuilder::new();
+
+        builder.add_task(task("a"));
+        builder.add_task(task("b"));
+        builder.add_task(task("c"));
+        builder.add_dependency("a".to_string(), "b".to_string());
+        builder.add_dependency("a".to_string(), "c".to_string());
+        // Duplicates should not need to be done twice
+        builder.add_dependency("a".to_string(), "c".to_string());
+
+        let mut graph = builder.build().unwrap();
+        let b = graph.next().unwrap().unwrap();
+        let c = graph.next().unwrap().unwrap();
+        assert_eq!(graph.next(), Some(None), "a should wait for b and c");
+
+        graph.done_many(&[b.id.clone(), c.id.clone(), c.id.clone()]);
+        assert_eq!(graph.next(), Some(Some(task("a"))));
+    }
+
+    #[test]
+    fn test_analyze() {
+        let mut builder = TaskGraphBuilder::new();
+
+        builder.add_task(task("a"));
+        builder.add_task(task("b"));
+        builder.add_task(task("c"));
+        builder.add_task(task("d"));
+        builder.add_dependency("a".to_string(), "b".to_string());
+        builder.add_dependency("b".to_string(), "c".to_string());
+        builder.add_dependency("d".to_string(), "c".to_string());
+
+        let analysis = builder.analyze().unwrap();
+        assert_eq!(analysis.order.len(), 4);
+        assert_eq!(analysis.order[0], "c");
+        assert_eq!(analysis.roots, vec!["a".to_string(), "d".to_string()]);
+        assert_eq!(analysis.leaves, vec!["c".to_string()]);
+
+        let graph = builder.clone().build().unwrap();
+        let levels = graph.levels();
+        assert_eq!(analysis.levels.len(), levels.len());
+        for (task_id, level) in &analysis.levels {
+            assert_eq!(levels.get(task_id.as_str()), Some(level));
+        }
+
+        builder.add_dependency("c".to_string(), "a".to_string());
+        assert!(matches!(builder.analyze(), Err(BuildError::Cycle(_))));
+    }
+
+    #[test]
+    fn test_add_dependencies() {
+        let mut builder = TaskGraphBuilder::new();
+
+        builder.add_task(task("a"));
+        builder.add_task(task("b"));
+        builder.add_task(task("c"));
+        builder.add_dependency("a".to_string(), "b".to_string());
+        builder.add_dependencies(
+            "a".to_string(),
+            vec!["b".to_string(), "c".to_string(), "c".to_string()],
+        );
+        assert_eq!(builder.edges["a"], vec!["b".to_string(), "c".to_string()]);
+
+        let mut graph = builder.build().unwrap();
+        assert_eq!(graph.dependencies(&"a".to_string()).len(), 2);
+        let b = graph.next().unwrap().unwrap();
+        let c = graph.next().unwrap().unwrap();
+        graph.done_many(&[b.id, c.id]);
+        assert_eq!(graph.ready_tasks(), vec![task("a")]);
+    }
+
+    #[test]
+    fn test_dependencies() {
+        let mut builder = TaskGraphBuilder::new();
+
+        builder.add_task(task("a"));
+        builder.add_task(task("b"));
+        builder.add_task(task("c"));
+        builder.add_dependency("a".to_string(), "c".to_string());
+        builder.add_dependency("b".to_string(), "c".to_string());
+
+        let graph = builder.build().unwrap();
+        assert_eq!(graph.dependencies(&"a".to_string()), ["c"]);
+        assert!(graph.dependencies(&"c".to_string()).is_empty());
+        assert!(graph.dependencies(&"unknown".to_string()).is_empty());
+        assert_eq!(graph.dependents(&"c".to_string()), ["a", "b"]);
+        assert!(graph.dependents(&"a".to_string()).is_empty());
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let root = temp_dir("cache-key");
+        std::fs::write(root.join("input.txt"), "one").unwrap();
+
+        let t = task("a").with_inputs(vec!["*.txt".to_string()]);
+        let key = t.cache_key(&root).unwrap();
+        assert_eq!(key, t.cache_key(&root).unwrap(), "Keys should be stable");
+        assert_eq!(key.len(), 64);
+
+        assert_ne!(
+            key,
+            t.clone().with_profile("release").cache_key(&root).unwrap(),
+            "The profile should change the key"
+        );
+
+        std::fs::write(root.join("input.txt"), "two").unwrap();
+        assert_ne!(
+            key,
+            t.cache_key(&root).unwrap(),
+            "The inputs should change the key"
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_conflicting_dependency_kinds() {
+        let mut builder = TaskGraphBuilder::new();
+        builder.add_task(task("codegen"));
+        builder.add_task(task("build"));
+        builder.add_soft_dependency("build".to_string(), "codegen".to_string());
+        builder.add_dependency("build".to_string(), "codegen".to_string());
+        builder.add_soft_dependency("build".to_string(), "codegen".to_string());
+
+        let mut graph = builder.build().unwrap();
+        assert_eq!(
+            graph.dependency_kind(&"build".to_string(), &"codegen".to_string()),
+            Some(DependencyKind::Hard)
+        );
+        assert_eq!(graph.dependencies(&"build".to_string()).len(), 1);
+        assert_eq!(
+            graph.warnings(),
+            &[BuildWarning::ConflictingDependencyKinds {
+                task: "build".to_string(),
+                dependency: "codegen".to_string(),
+            }]
+        );
+
+        // A failed hard dependency skips the task
+        graph.next();
+        graph.fail(&"codegen".to_string());
+        assert_eq!(
+            graph.status(&"build".to_string()),
+            Some(TaskStatus::Skipped)
+        );
+    }
+
+    #[test]
+    fn test_soft_dependencies() {
+        let mut builder = TaskGraphBuilder::new();
+
+        builder.add_task(task("codegen"));
+        builder.add_task(task("build"));
+        builder.add_task(task("test"));
+        builder.add_soft_dependency("build".to_string(), "codegen".to_string());
+        builder.add_dependency("test".to_string(), "codegen".to_string());
+
+        let mut graph = builder.build().unwrap();
+        assert_eq!(
+            graph.dependency_kind(&"build".to_string(), &"codegen".to_string()),
+            Some(DependencyKind::Soft)
+        );
+        assert_eq!(
+            graph.dependency_kind(&"test".to_string(), &"codegen".to_string()),
+            Some(DependencyKind::Hard)
+        );
+
+        assert!(graph.warnings().is_empty());
+
+        assert_eq!(graph.next(), Some(Some(task("codegen"))));
+        assert_eq!(graph.next(), Some(None), "build should wait for codegen");
+
+        graph.fail(&"codegen".to_string());
+        assert_eq!(
+            graph.next(),
+            Some(Some(task("build"))),
+            "build should run even though codegen failed"
+        );
+        assert_eq!(graph.status(&"test".to_string()), Some(TaskStatus::Skipped));
+    }
+
+    #[test]
+    fn test_soft_dependency_released_once() {
+        let mut builder = TaskGraphBuilder::new();
+        for id in ["codegen", "lint", "build"] {
+            builder.add_task(task(id));
+        }
+        builder.add_soft_dependency("build".to_string(), "codegen".to_string());
+        builder.add_soft_dependency("build".to_string(), "lint".to_string());
+        let mut graph = builder.build().unwrap();
+
+        // `build` still waits for `lint` however often `codegen` finishes
+        graph.fail(&"codegen".to_string());
+        graph.fail(&"codegen".to_string());
+        graph.done(&"codegen".to_string());
+        assert_eq!(graph.next(), Some(Some(task("lint"))));
+        assert_eq!(graph.next(), Some(None), "build should wait for lint");
+
+        graph.done(&"lint".to_string());
+        assert_eq!(graph.next(), Some(Some(task("build"))));
+    }
+
+    #[test]
+    fn test_same_id() {
+        let a = task("a");
+        let other_a = Task::new("a", "other", Action::shell(vec!["true".to_string()]));
+
+        assert_ne!(a, other_a);
+        assert!(a.same_id(&other_a));
+        assert!(!a.same_id(&task("b")));
+
+        let b = task("b");
+        let unique: HashSet<ById> = [&a, &other_a, &b].into_iter().map(ById).collect();
+        assert_eq!(unique.len(), 2);
+        assert!(unique.contains(&ById(&task("a"))));
+
+        let ids: HashSet<TaskId> = HashSet::from(["a".into(), "b".into()]);
+        assert!(ids.contains("a"), "TaskId should be looked up by str");
+        assert_eq!(TaskId::from("a").to_string(), "a");
+    }
+
+    #[test]
+    fn test_exclude() {
+        let mut builder = TaskGraphBuilder::new();
+
+        builder.add_task(task("app:build"));
+        builder.add_task(task("app:lint"));
+        builder.add_task(task("fixtures:build"));
+        builder.add_task(task("e2e:run"));
+        builder.add_dependency("e2e:run".to_string(), "fixtures:build".to_string());
+        builder.add_soft_dependency("app:build".to_string(), "e2e:run".to_string());
+        builder.exclude("e2e:*");
+
+        let graph = builder.clone().build().unwrap();
+        assert_eq!(graph.remaining(), 3);
+        assert!(graph.status(&"e2e:run".to_string()).is_none());
+        assert!(graph.dependencies(&"app:build".to_string()).is_empty());
+
+        let roots = vec![
+            "app:build".to_string(),
+            "app:lint".to_string(),
+            "e2e:run".to_string(),
+        ];
+        let graph = builder.clone().build_for(&roots).unwrap();
+        assert_eq!(
+            graph.remaining(),
+            2,
+            "fixtures:build is only needed by e2e:run"
+        );
+
+        builder.add_dependency("app:lint".to_string(), "e2e:run".to_string());
+        assert_eq!(
+            builder.validate(),
+            Err(BuildError::ExcludedDependency {
+                task: "app:lint".to_string(),
+                dependency: "e2e:run".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_label() {
+        let cmd = vec![
+            "webpack".to_string(),
+            "--config".to_string(),
+            "app.js".to_string(),
+        ];
+        let plain = Action::shell(cmd.clone());
+        let labeled = Action::shell(cmd).with_label("bundle app");
+
+        assert_eq!(plain.display_name(), "webpack --config app.js");
+        assert_eq!(labeled.display_name(), "bundle app");
+
+        let json = serde_json::to_value(&labeled).unwrap();
+        assert_eq!(json["Shell"]["label"], "bundle app");
+        let json = serde_json::to_value(&plain).unwrap();
+        assert!(json["Shell"].get("label").is_none());
+
+        let root = Path::new(".");
+        assert_eq!(
+            Task::new("a", "a", plain).cache_key(root).unwrap(),
+            Task::new("a", "a", labeled).cache_key(root).unwrap(),
+            "The label doesn't change what the task runs"
+        );
+    }
+
+    #[test]
+    fn test_waves() {
+        let mut builder = TaskGraphBuilder::new();
+
+        builder.add_task(task("a"));
+        builder.add_task(task("b"));
+        builder.add_task(task("c"));
+        builder.add_task(task("d"));
+        builder.add_task(task("e"));
+        builder.add_dependency("c".to_string(), "a".to_string());
+        builder.add_dependency("c".to_string(), "b".to_string());
+        builder.add_dependency("d".to_string(), "b".to_string());
+        builder.add_dependency("e".to_string(), "d".to_string());
+
+        let mut graph = builder.clone().build().unwrap();
+        let waves = graph
+            .waves()
+            .map(|wave| {
+                let mut ids = wave.iter().map(|t| t.id().clone()).collect::<Vec<TaskID>>();
+                ids.sort();
+                ids
+            })
+            .collect::<Vec<Vec<TaskID>>>();
+        assert_eq!(waves, vec![vec!["a", "b"], vec!["c", "d"], vec!["e"]]);
+        assert_eq!(graph.remaining(), 0);
+
+        let mut graph = builder.build().unwrap();
+        let mut waves = graph.waves();
+        assert_eq!(waves.next().unwrap().len(), 2);
+        waves.fail(&"b".to_string());
+        assert_eq!(waves.next(), None, "c and d need b");
+        assert_eq!(graph.status(&"a".to_string()), Some(TaskStatus::Succeeded));
+        assert_eq!(graph.status(&"e".to_string()), Some(TaskStatus::Skipped));
+    }
+
+    #[test]
+    fn test_critical_path() {
+        let mut builder = TaskGraphBuilder::new();
+
+        // a(1) -> b(5) -> d(1) is longer than a(1) -> c(2) -> d(1), even
+        // though both have as many tasks
+        builder.add_task(task("a"));
+        builder.add_task(task("b").with_cost(5));
+        builder.add_task(task("c").with_cost(2));
+        builder.add_task(task("d"));
+        builder.add_task(task("lone").with_cost(6));
+        builder.add_dependency("b".to_string(), "a".to_string());
+        builder.add_dependency("c".to_string(), "a".to_string());
+        builder.add_dependency("d".to_string(), "b".to_string());
+        builder.add_dependency("d".to_string(), "c".to_string());
+
+        let graph = builder.clone().build().unwrap();
+        assert_eq!(graph.critical_path(), vec!["a", "b", "d"]);
+
+        builder.add_task(task("lone").with_cost(8));
+        let graph = builder.build().unwrap();
+        assert_eq!(graph.critical_path(), vec!["lone"]);
+
+        assert!(TaskGraphBuilder::new()
+            .build()
+            .unwrap()
+            .critical_path()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_roots_and_leaves() {
+        // A diamond: app depends on both libs, which depend on core
+        let mut builder = TaskGraphBuilder::new();
+        for id in ["app", "lib-a", "lib-b", "core", "docs"] {
+            builder.add_task(task(id));
+        }
+        builder.add_dependency("app".to_string(), "lib-a".to_string());
+        builder.add_dependency("app".to_string(), "lib-b".to_string());
+        builder.add_dependency("lib-a".to_string(), "core".to_string());
+        builder.add_dependency("lib-b".to_string(), "core".to_string());
+        let analysis = builder.analyze().unwrap();
+        let graph = builder.build().unwrap();
+
+        // `core` runs first and `app` last
+        assert_eq!(graph.roots(), vec!["core", "docs"]);
+        assert_eq!(graph.leaves(), vec!["app", "docs"]);
+        // The analysis names them after the edges, from `app` down to `core`
+        assert_eq!(graph.roots(), analysis.leaves.iter().collect::<Vec<_>>());
+        assert_eq!(graph.leaves(), analysis.roots.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_simulate() {
+        let mut builder = TaskGraphBuilder::new();
+        for id in ["a", "b", "c"] {
+            builder.add_task(task(id).with_cost(100));
+        }
+        builder.add_task(task("d").with_cost(50));
+        builder.add_dependency("d".to_string(), "a".to_string());
+        let graph = builder.build().unwrap();
+        let ms = Duration::from_millis;
+
+        assert_eq!(graph.simulate(1), ms(350));
+        assert_eq!(graph.simulate(4), ms(150));
+
+        // `c` takes the slot freed by `a`, `d` the one freed by `b`
+        let run = graph.simulate_schedule(2);
+        assert_eq!(run.makespan, ms(200));
+        let times = run
+            .tasks
+            .iter()
+            .map(|t| (t.id.as_str(), t.start, t.end))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            times,
+            vec![
+                ("a", ms(0), ms(100)),
+                ("b", ms(0), ms(100)),
+                ("d", ms(100), ms(150)),
+                ("c", ms(100), ms(200)),
+            ]
+        );
+        assert_eq!(graph.remaining(), 4, "The graph itself is left as it was");
+    }
+
+    #[test]
+    fn test_tasks_with_tag() {
+        let tags = |tags: &[&str]| tags.iter().map(|t| t.to_string()).collect();
+        let mut builder = TaskGraphBuilder::new();
+
+        builder.add_task(task("e2e").with_tags(tags(&["slow", "flaky"])));
+        builder.add_task(task("bench").with_tags(tags(&["slow"])));
+        builder.add_task(task("unit"));
+
+        let graph = builder.build().unwrap();
+        assert_eq!(graph.tasks_with_tag("slow"), vec!["bench", "e2e"]);
+        assert_eq!(graph.tasks_with_tag("flaky"), vec!["e2e"]);
+        assert!(graph.tasks_with_tag("fast").is_empty());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_niceness() {
+        let output = Action::shell(vec!["nice".to_string()])
+            .with_niceness(10)
+            .command()
+            .unwrap()
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap().trim(), "10");
+    }
+
+    #[test]
+    fn test_platform_shell() {
+        let strings = |s: &[&str]| s.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        let action = Action::platform_shell(strings(&["./build.sh"]), strings(&["build.ps1"]));
+        assert_eq!(action.cmd_for(false).unwrap(), ["./build.sh"]);
+        assert_eq!(action.cmd_for(true).unwrap(), ["build.ps1"]);
+
+        let unix_only = Action::platform_shell(strings(&["./build.sh"]), vec![]);
+        assert!(unix_only.cmd_for(false).is_ok());
+        assert_eq!(
+            unix_only.cmd_for(true).unwrap_err().to_string(),
+            "the action has no command for Windows"
+        );
+        assert_eq!(unix_only.cmd().is_ok(), cfg!(unix));
+
+        let cleanup = unix_only.with_cleanup(Action::shell(strings(&["rm", "tmp"])));
+        assert!(cleanup.cmd_for(true).is_err());
+    }
+
+    #[test]
+    fn test_empty_command() {
+        let action = Action::shell(vec![]);
+        assert_eq!(
+            action.command().unwrap_err().to_string(),
+            "the action has no command"
+        );
+        assert!(task("a").command().is_err());
+        assert!(action
+            .with_cleanup(Action::shell(vec!["true".to_string()]))
+            .command()
+            .is_err());
+    }
+
+    #[test]
+    fn test_container() {
+        let action = Action::container(
+            "rust:1",
+            vec!["cargo".to_string(), "build".to_string()],
+            vec![(PathBuf::from("/src/app"), PathBuf::from("/app"))],
+        );
+        assert_eq!(action.display_name(), "cargo build");
+
+        let Action::Container {
+            image, cmd, mounts, ..
+        } = &action
+        else {
+            unreachable!()
+        };
+        assert_eq!(
+            container_args(image, cmd, mounts),
+            [
+                "run",
+                "--rm",
+                "--volume",
+                "/src/app:/app",
+                "rust:1",
+                "cargo",
+                "build"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_no_container_runtime() {
+        // The test runs again in its own process, the PATH of this one is
+        // shared with the other tests
+        if std::env::var_os("NX_RS_NO_CONTAINER_RUNTIME").is_none() {
+            let empty = temp_dir("no-container-runtime");
+            let output = Command::new(std::env::current_exe().unwrap())
+                .args([
+                    "--exact",
+                    "graphing::types::test::test_no_container_runtime",
+                ])
+                .env("NX_RS_NO_CONTAINER_RUNTIME", "1")
+                .env("PATH", &empty)
+                .output()
+                .unwrap();
+            std::fs::remove_dir_all(&empty).unwrap();
+            assert!(
+                output.status.success(),
+                "{}",
+                String::from_utf8_lossy(&output.stdout)
+            );
+            assert!(String::from_utf8_lossy(&output.stdout).contains("1 passed"));
+            return;
+        }
+
+        let action = Action::container("rust:1", vec!["cargo".to_string()], vec![]);
+        let err = action.command().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no container runtime found, install docker or podman to run container actions"
+        );
+
+        let runner = crate::graphing::runner::MockRunner::new();
+        assert!(action.run_with(&runner).is_err());
+        assert!(runner.commands().is_empty(), "nothing should be spawned");
+    }
+
+    #[test]
+    fn test_run_with() {
+        let strings = |s: &[&str]| s.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        let runner = crate::graphing::runner::MockRunner::new().with_exit_code("make", 2);
+
+        let action = Action::shell(strings(&["make", "all"]))
+            .with_success_codes(vec![0, 2])
+            .with_cleanup(Action::shell(strings(&["rm", "-r", "tmp"])));
+        let status = action.run_with(&runner).unwrap();
+
+        assert_eq!(status.code(), Some(2));
+        assert!(action.is_success(&status));
+        assert_eq!(
+            runner.commands(),
+            [strings(&["make", "all"]), strings(&["rm", "-r", "tmp"])]
+        );
     }
 }
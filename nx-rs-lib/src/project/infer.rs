@@ -0,0 +1,156 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::Result;
+
+use super::types::Target;
+
+/// Infers targets of a project from the files in its directory, so that
+/// common targets don't have to be written in every project file, e.g. `build`
+/// for a Cargo crate. See `Workspace::register_inferrer`.
+pub trait TargetInferrer: Send + Sync {
+    /// Returns the targets inferred for a project, by name. The targets the
+    /// project defines itself override them.
+    /// # Arguments
+    /// * `dir` - The directory of the project
+    fn infer(&self, dir: &Path) -> Result<HashMap<String, Target>>;
+}
+
+/// Infers `build` and `test` targets for the projects with a `Cargo.toml`,
+/// running `cargo build` and `cargo test` on their manifest.
+pub struct CargoInferrer;
+
+impl TargetInferrer for CargoInferrer {
+    fn infer(&self, dir: &Path) -> Result<HashMap<String, Target>> {
+        let manifest = dir.join("Cargo.toml");
+        if !manifest.is_file() {
+            return Ok(HashMap::new());
+        }
+
+        let inputs = vec![
+            "{projectRoot}/Cargo.toml".to_string(),
+            "{projectRoot}/src/**".to_string(),
+        ];
+        let cargo = |command: &str| {
+            let command = vec![
+                "cargo".to_string(),
+                command.to_string(),
+                "--manifest-path".to_string(),
+                manifest.to_string_lossy().to_string(),
+            ];
+            Target::new(command).with_inputs(inputs.clone())
+        };
+
+        return Ok(HashMap::from([
+            ("build".to_string(), cargo("build")),
+            ("test".to_string(), cargo("test")),
+        ]));
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::project::types::{ValidateProjectsError, Workspace};
+
+    #[test]
+    fn test_cargo_inferrer() {
+        let root = std::env::temp_dir().join(format!("nx-rs-infer-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let crate_dir = root.join("app");
+        std::fs::create_dir_all(crate_dir.join("src")).unwrap();
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::write(
+            crate_dir.join("Cargo.toml"),
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+        std::fs::write(crate_dir.join("src/main.rs"), "fn main() {}\n").unwrap();
+
+        let targets = CargoInferrer.infer(&crate_dir).unwrap();
+        let manifest = crate_dir.join("Cargo.toml").to_string_lossy().to_string();
+        assert_eq!(
+            targets["build"].command(),
+            ["cargo", "build", "--manifest-path", &manifest]
+        );
+        assert!(targets.contains_key("test"));
+        assert!(CargoInferrer.infer(&root.join("docs")).unwrap().is_empty());
+
+        // `app` overrides the inferred `test`
+        let project = |name: &str, targets: &str| {
+            format!(
+                r#"{{
+                    "name": "{name}",
+                    "description": "",
+                    "owners": [],
+                    "affects_tags": [],
+                    "affected_by_tags": [],
+                    "targets": {targets}
+                }}"#
+            )
+        };
+        std::fs::write(
+            crate_dir.join("project.json"),
+            project(
+                "app",
+                r#"{ "test": { "command": ["cargo", "nextest", "run"] } }"#,
+            ),
+        )
+        .unwrap();
+        std::fs::write(root.join("docs/project.json"), project("docs", "{}")).unwrap();
+        let ws = format!(
+            r#"{{
+                "name": "ws",
+                "app_version": "0.1.0",
+                "projects": {{
+                    "app": "{}",
+                    "docs": "{}"
+                }},
+                "tags": [],
+                "maintainers": [],
+                "repository": "",
+                "required_targets": ["build"]
+            }}"#,
+            crate_dir.join("project.json").display(),
+            root.join("docs/project.json").display()
+        );
+        let ws_json = ws;
+        let mut ws = Workspace::read_reader(ws_json.as_bytes()).unwrap();
+        ws.register_inferrer(CargoInferrer);
+
+        let command = |target: &str| {
+            let mut graph = ws.build_task_graph(target, None).unwrap();
+            assert_eq!(graph.remaining(), 1, "`docs` is not a crate");
+            let task = graph.next().unwrap().unwrap();
+            assert_eq!(task.id(), &format!("app:{target}"));
+            return task.action().cmd().unwrap().to_vec();
+        };
+        assert_eq!(
+            command("build"),
+            ["cargo", "build", "--manifest-path", &manifest]
+        );
+        assert_eq!(command("test"), ["cargo", "nextest", "run"]);
+
+        // Checked with the inferred targets too
+        std::fs::write(root.join("workspace.json"), &ws_json).unwrap();
+        let missing = |report: crate::project::types::HealthReport| {
+            report
+                .errors
+                .into_iter()
+                .filter_map(|e| match e {
+                    ValidateProjectsError::MissingTargets(name, _) => Some(name),
+                    _ => None,
+                })
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(
+            missing(Workspace::health_check_with(&root, ws.inferrers())),
+            vec!["docs"]
+        );
+        let mut all = missing(Workspace::health_check(&root));
+        all.sort();
+        assert_eq!(all, vec!["app", "docs"], "`app` only has `test` itself");
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,6 +1,21 @@
-use anyhow::{Error, Result};
-use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs::read_to_string, path::Path};
+use anyhow::{bail, Error, Result};
+use ignore::{gitignore::Gitignore, WalkBuilder};
+use notify::{RecursiveMode, Watcher};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fmt,
+    fs::File,
+    io::{BufReader, Read},
+    path::{Component, Path, PathBuf},
+    sync::{mpsc, Arc},
+    time::{Duration, SystemTime},
+};
+
+use super::infer::TargetInferrer;
+use crate::graphing::executor::RunEnv;
+use crate::graphing::types::{Action, MissingEnvFile, Task, TaskGraph, TaskGraphBuilder, TaskID};
 
 /// The list of possible errors that can occur when validating the projects in
 /// the workspace.
@@ -18,6 +33,13 @@ pub enum ValidateProjectsError {
     /// * `Vec<String>` - The list of unknown tags
     UnknownTags(String, Vec<String>),
 
+    /// A target of the project has one or more unknown tags
+    /// # Arguments
+    /// * `String` - The name of the project
+    /// * `String` - The name of the target
+    /// * `Vec<String>` - The list of unknown tags
+    UnknownTargetTags(String, String, Vec<String>),
+
     /// The project could not be deserialized
     /// # Arguments
     /// * `String` - The name of the project
@@ -26,16 +48,344 @@ pub enum ValidateProjectsError {
 
     /// The workspace could not be deserialized
     /// # Arguments
-    /// * `Error` - The error that occurred
-    WorkspaceSerialization(Error),
+    /// * `ReadError` - The error that occurred
+    WorkspaceSerialization(ReadError),
+
+    /// The workspace or project file does not exist
+    /// # Arguments
+    /// * `PathBuf` - The path of the missing file
+    MissingFile(PathBuf),
+
+    /// The name in the project file is not the one the workspace uses for it
+    /// # Arguments
+    /// * `String` - The name of the project in the workspace
+    /// * `String` - The name in the project file
+    NameMismatch(String, String),
+
+    /// The projects affect each other in a cycle through their tags
+    /// # Arguments
+    /// * `Vec<String>` - The projects in the cycle, each affecting the next
+    TagCycle(Vec<String>),
+
+    /// The project both affects and is affected by a tag, so it affects itself
+    /// # Arguments
+    /// * `String` - The name of the project
+    /// * `String` - The tag
+    SelfAffectingTag(String, String),
+
+    /// The workspace has no required targets, so the projects' targets are
+    /// not checked. Usually a misconfiguration rather than intent
+    NoRequiredTargets,
+
+    /// The workspace has no project with the name
+    /// # Arguments
+    /// * `String` - The name of the project
+    UnknownProject(String),
+
+    /// The project has no targets, so nothing can run for it. Only checked
+    /// when the workspace's `check_empty_projects` is set
+    /// # Arguments
+    /// * `String` - The name of the project
+    NoTargets(String),
+}
+
+/// The ways reading a config file can fail.
+#[derive(Debug, thiserror::Error)]
+pub enum ReadError {
+    /// The file could not be read, e.g. it does not exist
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    /// The content is not a valid config
+    /// # Arguments
+    /// * `line` - The one-based line of the error, 0 if unknown
+    /// * `col` - The one-based column of the error, 0 if unknown
+    /// * `message` - What is wrong
+    #[error("invalid config at line {line} column {col}: {message}")]
+    Parse {
+        line: usize,
+        col: usize,
+        message: String,
+    },
+}
+
+impl From<serde_json::Error> for ReadError {
+    fn from(e: serde_json::Error) -> Self {
+        if e.is_io() {
+            return ReadError::Io(e.into());
+        }
+
+        let (line, col) = (e.line(), e.column());
+        let message = e.to_string();
+        // The location is kept apart, not repeated in the message
+        let message = message
+            .strip_suffix(&format!(" at line {line} column {col}"))
+            .unwrap_or(&message)
+            .to_string();
+        return ReadError::Parse { line, col, message };
+    }
+}
+
+impl From<json5::Error> for ReadError {
+    fn from(e: json5::Error) -> Self {
+        let json5::Error::Message { msg, location } = e;
+        let (line, col) = location.map(|l| (l.line, l.column)).unwrap_or_default();
+        return ReadError::Parse {
+            line,
+            col,
+            message: msg,
+        };
+    }
+}
+
+/// The formats the config files can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ConfigFormat {
+    /// Strict JSON
+    #[default]
+    Json,
+    /// JSON5, which allows comments, trailing commas and unquoted keys
+    Json5,
+}
+
+impl ConfigFormat {
+    /// Picks the format from the file extension: `.json5` files are JSON5,
+    /// anything else is strict JSON.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension() {
+            Some(ext) if ext == "json5" => ConfigFormat::Json5,
+            _ => ConfigFormat::Json,
+        }
+    }
+
+    /// Reads and deserializes the whole content of the reader.
+    /// JSON is streamed from the reader, so large files are never held in
+    /// memory as a whole. JSON5 is read at once, its parser needs a string.
+    fn parse<T: DeserializeOwned>(self, mut r: impl Read) -> Result<T, ReadError> {
+        match self {
+            ConfigFormat::Json => return Ok(serde_json::from_reader(BufReader::new(r))?),
+            ConfigFormat::Json5 => {
+                let mut data = String::new();
+                r.read_to_string(&mut data)?;
+                return Ok(json5::from_str(&data)?);
+            }
+        }
+    }
 }
 
 // NOTE: should I use the same one from the algorithms module, or create a new
 // one?
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Target {}
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Target {
+    /// The command to run, as the program followed by its arguments
+    #[serde(default)]
+    command: Vec<String>,
+    /// The paths the target produces, relative to the workspace root.
+    /// `{projectRoot}` is replaced by the directory of the project and
+    /// `{profile}` by the build profile, e.g. `{projectRoot}/dist/{profile}`
+    #[serde(default)]
+    outputs: Vec<String>,
+    /// Globs of the files the target reads, relative to the workspace root,
+    /// or the names of the workspace's named inputs prefixed with `@`, e.g.
+    /// `@production`, see `Workspace::named_inputs`. `{projectRoot}` is
+    /// replaced by the directory of the project
+    #[serde(default)]
+    inputs: Vec<String>,
+    /// Whether the outputs may be outside of the project's directory
+    #[serde(default)]
+    allow_outputs_outside_project: bool,
+    /// The name of the custom executor running the target instead of its
+    /// command being spawned, see `Executor::register_executor`
+    #[serde(default)]
+    executor: Option<String>,
+    /// Tags of the target's tasks, e.g. `slow`. Must be workspace tags
+    #[serde(default)]
+    tags: Vec<String>,
+    /// The targets the target depends on. `^build` is the `build` target of
+    /// the projects affecting this one, `codegen` the `codegen` target of the
+    /// same project
+    #[serde(default)]
+    depends_on: Vec<String>,
+    /// The shell the command runs through. Defaults to the workspace's
+    /// `default_shell`
+    #[serde(default)]
+    shell: Option<TargetShell>,
+}
 
-#[derive(Serialize, Deserialize, Debug)]
+/// A shell commands run through, as the program and the arguments the
+/// command is appended to, e.g. `("bash", ["-lc"])`.
+pub type Shell = (String, Vec<String>);
+
+/// The shell of a target, see `Workspace::default_shell`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum TargetShell {
+    /// `true` for the workspace's default shell, `false` to run the command
+    /// directly
+    Default(bool),
+    /// Another shell than the default, e.g. `["zsh", ["-c"]]`
+    Custom(String, Vec<String>),
+}
+
+impl Target {
+    /// A target running a command, with nothing else set.
+    pub fn new(command: Vec<String>) -> Self {
+        return Target {
+            command,
+            ..Default::default()
+        };
+    }
+
+    /// Set the files the target reads, see `inputs`.
+    pub fn with_inputs(mut self, inputs: Vec<String>) -> Self {
+        self.inputs = inputs;
+        return self;
+    }
+
+    /// Returns the command of the target, before `{profile}` is replaced and
+    /// the default shell applied.
+    pub fn command(&self) -> &[String] {
+        return &self.command;
+    }
+
+    /// Fills the fields the target leaves unset with the ones of the
+    /// defaults. Lists are unset when empty, and the defaults of flags only
+    /// turn them on.
+    /// # Arguments
+    /// * `defaults` - The target defaults, see `Workspace::target_defaults`
+    pub fn inherit(mut self, defaults: &Target) -> Target {
+        let fill = |own: &mut Vec<String>, default: &Vec<String>| {
+            if own.is_empty() {
+                own.clone_from(default);
+            }
+        };
+        fill(&mut self.command, &defaults.command);
+        fill(&mut self.outputs, &defaults.outputs);
+        fill(&mut self.inputs, &defaults.inputs);
+        fill(&mut self.tags, &defaults.tags);
+        fill(&mut self.depends_on, &defaults.depends_on);
+        self.allow_outputs_outside_project |= defaults.allow_outputs_outside_project;
+        if self.executor.is_none() {
+            self.executor.clone_from(&defaults.executor);
+        }
+        if self.shell.is_none() {
+            self.shell.clone_from(&defaults.shell);
+        }
+        return self;
+    }
+
+    /// Resolves the outputs of the target for a project.
+    /// # Arguments
+    /// * `root` - The directory of the project, relative to the workspace root
+    /// * `profile` - The build profile
+    ///
+    /// # Returns
+    /// * `Vec<PathBuf>` - The outputs, relative to the workspace root
+    pub fn resolve_outputs(&self, root: &Path, profile: &str) -> Result<Vec<PathBuf>> {
+        let root = normalize(root);
+        let mut outputs: Vec<PathBuf> = vec![];
+
+        for output in &self.outputs {
+            let path = output
+                .replace("{projectRoot}", &root.to_string_lossy())
+                .replace("{profile}", profile);
+            let path = normalize(Path::new(&path));
+
+            if !self.allow_outputs_outside_project
+                && (path.is_absolute() || path.starts_with("..") || !path.starts_with(&root))
+            {
+                bail!(
+                    "output `{output}` resolves to `{}`, outside of `{}`",
+                    path.display(),
+                    root.display()
+                );
+            }
+            outputs.push(path);
+        }
+
+        return Ok(outputs);
+    }
+
+    /// Resolves the input globs of the target for a project, expanding the
+    /// named inputs.
+    /// # Arguments
+    /// * `root` - The directory of the project, relative to the workspace root
+    /// * `named_inputs` - See `Workspace::named_inputs`
+    ///
+    /// # Returns
+    /// * `Vec<String>` - The globs, relative to the workspace root
+    pub fn resolve_inputs(
+        &self,
+        root: &Path,
+        named_inputs: &HashMap<String, Vec<String>>,
+    ) -> Result<Vec<String>> {
+        let root = normalize(root);
+        let mut inputs: Vec<String> = vec![];
+        for input in &self.inputs {
+            expand_input(input, named_inputs, &mut vec![], &mut inputs)?;
+        }
+
+        return Ok(inputs
+            .into_iter()
+            .map(|input| input.replace("{projectRoot}", &root.to_string_lossy()))
+            .collect());
+    }
+}
+
+/// Adds the globs of an input to `inputs`, expanding named inputs.
+/// # Arguments
+/// * `path` - The named inputs being expanded, to detect cycles
+fn expand_input(
+    input: &str,
+    named_inputs: &HashMap<String, Vec<String>>,
+    path: &mut Vec<String>,
+    inputs: &mut Vec<String>,
+) -> Result<()> {
+    // Globs, including ones looking like a name, e.g. `Makefile`
+    let Some(name) = input.strip_prefix('@') else {
+        if !inputs.iter().any(|i| i == input) {
+            inputs.push(input.to_string());
+        }
+        return Ok(());
+    };
+
+    let Some(members) = named_inputs.get(name) else {
+        bail!("unknown named input `{name}`");
+    };
+    if path.iter().any(|i| i == name) {
+        bail!("named input cycle: {} -> {name}", path.join(" -> "));
+    }
+
+    path.push(name.to_string());
+    for member in members {
+        expand_input(member, named_inputs, path, inputs)?;
+    }
+    path.pop();
+
+    return Ok(());
+}
+
+/// Removes the `.` and `..` components of a path without touching the
+/// filesystem. Leading `..` that can't be removed are kept.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push("..");
+                }
+            }
+            c => normalized.push(c),
+        }
+    }
+
+    return normalized;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Project {
     name: String,
     version: Option<String>,
@@ -44,14 +394,74 @@ pub struct Project {
     affects_tags: Vec<String>,
     affected_by_tags: Vec<String>,
     targets: HashMap<String, Target>,
+    /// The kind of project, e.g. `lib` or `app`, picking its required targets
+    #[serde(default, rename = "type")]
+    project_type: Option<String>,
+    /// The directory of the project file, relative to the workspace root
+    #[serde(skip)]
+    root: PathBuf,
 }
 
 impl Project {
-    pub fn read(path: &Path) -> Result<Project> {
-        let data = read_to_string(path)?;
+    /// Reads a project file. The format is picked from the extension, see
+    /// `ConfigFormat::from_path`.
+    pub fn read(path: &Path) -> Result<Project, ReadError> {
+        return Project::read_with_format(path, ConfigFormat::from_path(path));
+    }
 
-        let proj = serde_json::from_str(&data)?;
-        return Ok(proj);
+    /// Reads a project file in the given format, whatever its extension.
+    pub fn read_with_format(path: &Path, format: ConfigFormat) -> Result<Project, ReadError> {
+        return Project::read_reader_with_format(File::open(path)?, format);
+    }
+
+    /// Reads a project from any reader, e.g. stdin.
+    /// # Arguments
+    /// * `r` - The reader containing the project JSON
+    pub fn read_reader(r: impl Read) -> Result<Project, ReadError> {
+        return Project::read_reader_with_format(r, ConfigFormat::Json);
+    }
+
+    /// Reads a project from any reader, in the given format.
+    pub fn read_reader_with_format(
+        r: impl Read,
+        format: ConfigFormat,
+    ) -> Result<Project, ReadError> {
+        return format.parse(r);
+    }
+
+    /// Returns the names of the project's targets, sorted.
+    pub fn target_names(&self) -> Vec<&String> {
+        let mut names = self.targets.keys().collect::<Vec<&String>>();
+        names.sort();
+        return names;
+    }
+
+    /// Returns the target with the given name, if the project has it.
+    pub fn target(&self, name: &str) -> Option<&Target> {
+        self.targets.get(name)
+    }
+
+    /// Compares the structure of two versions of a project: their tags and
+    /// targets.
+    /// # Arguments
+    /// * `other` - The newer version of the project
+    pub fn diff(&self, other: &Project) -> ProjectDiff {
+        let (added_affects_tags, removed_affects_tags) =
+            added_removed(&self.affects_tags, &other.affects_tags);
+        let (added_affected_by_tags, removed_affected_by_tags) =
+            added_removed(&self.affected_by_tags, &other.affected_by_tags);
+        let (added_targets, removed_targets) =
+            added_removed(self.targets.keys(), other.targets.keys());
+
+        ProjectDiff {
+            name: other.name.clone(),
+            added_affects_tags,
+            removed_affects_tags,
+            added_affected_by_tags,
+            removed_affected_by_tags,
+            added_targets,
+            removed_targets,
+        }
     }
 }
 
@@ -59,19 +469,252 @@ impl Project {
 pub struct Workspace {
     name: String,
     app_version: String,
-    projects: HashMap<String, String>,
+    projects: HashMap<String, ProjectEntry>,
     tags: Vec<String>,
     maintainers: Vec<String>,
     repository: String,
-    required_targets: Vec<String>,
+    /// The targets the projects must have
+    #[serde(default)]
+    required_targets: RequiredTargets,
+    /// The build profile used when none is given
+    #[serde(default = "default_profile")]
+    default_profile: String,
+    /// Named lists of targets run together, e.g. `ci = [lint, test, build]`
+    #[serde(default)]
+    pipelines: HashMap<String, Vec<String>>,
+    /// Names standing for several targets, e.g. `check = [lint, test]`.
+    /// Aliases may refer to other aliases
+    #[serde(default)]
+    aliases: HashMap<String, Vec<String>>,
+    /// The shell the targets' commands run through, unless they opt out. The
+    /// words of a command are quoted for the shell and joined with spaces into
+    /// a single argument, e.g. `["bash", ["-lc"]]` runs `cargo build` as
+    /// `bash -lc "cargo build"`. A command of a single word is passed as is,
+    /// so it can be a script, e.g. `["cargo build && cargo test"]`
+    #[serde(default)]
+    default_shell: Option<Shell>,
+    /// Reusable sets of input globs the targets refer to by name, e.g.
+    /// `production = ["{projectRoot}/src/**"]`, referred to as `@production`.
+    /// Named inputs may refer to other named inputs
+    #[serde(default)]
+    named_inputs: HashMap<String, Vec<String>>,
+    /// The default variables of every task, overriding the ones of the run
+    /// and overridden by the task's, see `RunEnv`
+    #[serde(default)]
+    env: HashMap<String, String>,
+    /// The default config of the targets, by name. Every project gets these
+    /// targets, with the fields its own target sets overriding the defaults,
+    /// see `Target::inherit`
+    #[serde(default)]
+    target_defaults: HashMap<String, Target>,
+    /// Whether projects without any target are reported, see
+    /// `ValidateProjectsError::NoTargets`. Off by default, since some
+    /// projects are empty on purpose
+    #[serde(default)]
+    check_empty_projects: bool,
+    /// See `register_inferrer`
+    #[serde(skip)]
+    inferrers: Inferrers,
+}
+
+/// The target inferrers of a workspace, in registration order.
+#[derive(Default)]
+struct Inferrers(Vec<Arc<dyn TargetInferrer>>);
+
+impl fmt::Debug for Inferrers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Inferrers({})", self.0.len())
+    }
+}
+
+/// The workspace settings the tasks of the targets are made with.
+#[derive(Debug, Default, Clone, Copy)]
+struct TaskOptions<'a> {
+    /// The build profile
+    profile: &'a str,
+    /// See `Workspace::default_shell`
+    default_shell: Option<&'a Shell>,
+    /// See `Workspace::named_inputs`
+    named_inputs: Option<&'a HashMap<String, Vec<String>>>,
+}
+
+/// The targets the projects of a workspace must have.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum RequiredTargets {
+    /// The same targets for every project, e.g. `["build", "test"]`
+    All(Vec<String>),
+    /// The targets of each project type, e.g.
+    /// `{"lib": ["build"], "app": ["build", "serve"]}`. Projects without a
+    /// type, or of a type without a rule, use the `default` rule, if any
+    ByType(HashMap<String, Vec<String>>),
+}
+
+impl Default for RequiredTargets {
+    fn default() -> Self {
+        RequiredTargets::All(vec![])
+    }
+}
+
+/// The rule of `RequiredTargets::ByType` for untyped projects.
+const DEFAULT_PROJECT_TYPE: &str = "default";
+
+impl RequiredTargets {
+    /// Returns the targets a project of the given type must have.
+    pub fn for_type(&self, project_type: Option<&str>) -> &[String] {
+        match self {
+            RequiredTargets::All(targets) => return targets,
+            RequiredTargets::ByType(rules) => {
+                return project_type
+                    .and_then(|t| rules.get(t))
+                    .or_else(|| rules.get(DEFAULT_PROJECT_TYPE))
+                    .map(Vec::as_slice)
+                    .unwrap_or(&[]);
+            }
+        }
+    }
+
+    /// Whether no project has any required target.
+    pub fn is_empty(&self) -> bool {
+        match self {
+            RequiredTargets::All(targets) => return targets.is_empty(),
+            RequiredTargets::ByType(rules) => return rules.values().all(Vec::is_empty),
+        }
+    }
+
+    /// Lists the rules as strings, to compare them: the targets, prefixed by
+    /// their type and a colon for `ByType`, e.g. `lib:build`.
+    fn entries(&self) -> Vec<String> {
+        match self {
+            RequiredTargets::All(targets) => return targets.clone(),
+            RequiredTargets::ByType(rules) => {
+                let mut entries = rules
+                    .iter()
+                    .flat_map(|(t, targets)| {
+                        targets.iter().map(move |target| format!("{t}:{target}"))
+                    })
+                    .collect::<Vec<String>>();
+                entries.sort();
+                return entries;
+            }
+        }
+    }
+}
+
+/// A project of the workspace, either the path of its file or its definition.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum ProjectEntry {
+    /// The path of the project file, relative to the workspace root
+    Path(String),
+    /// The project, defined in the workspace file itself
+    Inline(Box<Project>),
+}
+
+impl ProjectEntry {
+    /// Reads the project, or copies it if it is inline.
+    /// # Arguments
+    /// * `root` - The workspace root, which the path is relative to
+    fn load(&self, root: &Path) -> Result<Project> {
+        return self.load_with(root, &mut |path| Ok(Project::read(path)?));
+    }
+
+    /// See `load`.
+    /// # Arguments
+    /// * `read` - Reads the project file at a path
+    fn load_with(
+        &self,
+        root: &Path,
+        read: &mut dyn FnMut(&Path) -> Result<Project>,
+    ) -> Result<Project> {
+        match self {
+            ProjectEntry::Path(path) => {
+                let mut proj = read(&root.join(path))?;
+                proj.root = Path::new(path)
+                    .parent()
+                    .map(Path::to_path_buf)
+                    .unwrap_or_default();
+                return Ok(proj);
+            }
+            ProjectEntry::Inline(proj) => return Ok((**proj).clone()),
+        }
+    }
+
+    fn path(&self) -> Option<&str> {
+        match self {
+            ProjectEntry::Path(path) => Some(path),
+            ProjectEntry::Inline(_) => None,
+        }
+    }
+}
+
+/// Keeps the projects read from their files, so that a file is only read
+/// again once its modification time changed, e.g. between the changes seen by
+/// `Workspace::watch`.
+#[derive(Debug, Default)]
+pub struct ProjectCache {
+    /// The modification time of each file when it was read, and its project
+    entries: HashMap<PathBuf, (SystemTime, Project)>,
+}
+
+impl ProjectCache {
+    pub fn new() -> Self {
+        ProjectCache::default()
+    }
+
+    /// Reads a project file, unless it did not change since it was last read.
+    pub fn read(&mut self, path: &Path) -> Result<Project> {
+        let modified = std::fs::metadata(path)?.modified()?;
+        if let Some((read_at, proj)) = self.entries.get(path) {
+            if *read_at == modified {
+                return Ok(proj.clone());
+            }
+        }
+
+        let proj = Project::read(path)?;
+        self.entries
+            .insert(path.to_path_buf(), (modified, proj.clone()));
+        return Ok(proj);
+    }
+
+    /// Forgets every project, so the files are all read again.
+    pub fn invalidate(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// How long `Workspace::watch` waits for more changes before building a graph.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(200);
+
+fn default_profile() -> String {
+    "debug".to_string()
 }
 
 impl Workspace {
-    pub fn read(path: &Path) -> Result<Workspace> {
-        let data = read_to_string(path)?;
+    /// Reads a workspace file. The format is picked from the extension, see
+    /// `ConfigFormat::from_path`.
+    pub fn read(path: &Path) -> Result<Workspace, ReadError> {
+        return Workspace::read_with_format(path, ConfigFormat::from_path(path));
+    }
 
-        let ws = serde_json::from_str(&data)?;
-        return Ok(ws);
+    /// Reads a workspace file in the given format, whatever its extension.
+    pub fn read_with_format(path: &Path, format: ConfigFormat) -> Result<Workspace, ReadError> {
+        return Workspace::read_reader_with_format(File::open(path)?, format);
+    }
+
+    /// Reads a workspace from any reader, e.g. stdin.
+    /// # Arguments
+    /// * `r` - The reader containing the workspace JSON
+    pub fn read_reader(r: impl Read) -> Result<Workspace, ReadError> {
+        return Workspace::read_reader_with_format(r, ConfigFormat::Json);
+    }
+
+    /// Reads a workspace from any reader, in the given format.
+    pub fn read_reader_with_format(
+        r: impl Read,
+        format: ConfigFormat,
+    ) -> Result<Workspace, ReadError> {
+        return format.parse(r);
     }
 
     /// Returns the list of projects that are affected by the given project
@@ -80,7 +723,9 @@ impl Workspace {
     /// * `proj_name` - The name of the project to check
     ///
     /// # Returns
-    /// * `Vec<String>` - The list of projects affected by the given project
+    /// * `Vec<String>` - The list of projects affected by the given project,
+    ///   sorted and without duplicates, so the same workspace always gives
+    ///   the same list
     pub fn affected_projects(&self, proj_name: &str) -> Result<Vec<String>> {
         return Ok(Workspace::affected_util(
             proj_name,
@@ -89,102 +734,3072 @@ impl Workspace {
     }
 
     /// Returns the list of projects that are affected by the given project
-    /// based on the tags set on the project. Does so recursively.
+    /// based on the tags set on the project, directly or not.
+    /// Walks the projects with an explicit stack, each project at most once,
+    /// so neither cycles nor long chains of tags can exhaust the call stack.
     /// # Arguments
     /// * `proj_name` - The name of the project to check
     /// * `projects` - A hashmap of all the projects in the workspace
     ///
     /// # Returns
-    /// * `Vec<String>` - The list of projects affected by the given project
+    /// * `Vec<String>` - The list of projects affected by the given project,
+    ///   sorted and without duplicates. Only includes the project itself if it
+    ///   affects itself through a cycle
     fn affected_util(proj_name: &str, projects: &HashMap<String, Project>) -> Vec<String> {
         let mut affected: Vec<String> = vec![];
+        let mut visited: HashSet<&str> = HashSet::new();
 
-        let tags = projects.get(proj_name).unwrap().affects_tags.clone();
+        let mut stack: Vec<&str> = vec![proj_name];
+        while let Some(current) = stack.pop() {
+            let tags = &projects[current].affects_tags;
 
-        for (name, proj) in projects {
-            if proj.affected_by_tags.iter().any(|t| tags.contains(t)) {
-                affected.push(name.clone());
-                affected.extend(Workspace::affected_util(name, projects));
+            for (name, proj) in projects {
+                if proj.affected_by_tags.iter().any(|t| tags.contains(t))
+                    && visited.insert(name.as_str())
+                {
+                    affected.push(name.clone());
+                    stack.push(name);
+                }
             }
         }
 
+        affected.sort();
         return affected;
     }
 
-    fn get_projects_map(&self) -> Result<HashMap<String, Project>> {
-        let mut projects: HashMap<String, Project> = HashMap::new();
+    /// Returns the list of projects that are affected by any of the given
+    /// projects, sorted and without duplicates.
+    /// # Arguments
+    /// * `changed` - The names of the projects to check
+    ///
+    /// # Returns
+    /// * `Vec<String>` - The list of projects affected by the given projects
+    pub fn affected_by_many(&self, changed: &[String]) -> Result<Vec<String>> {
+        return Workspace::affected_by_many_util(changed, &self.get_projects_map()?);
+    }
+
+    fn affected_by_many_util(
+        changed: &[String],
+        projects: &HashMap<String, Project>,
+    ) -> Result<Vec<String>> {
+        let mut affected: Vec<String> = vec![];
 
-        for (name, path) in &self.projects {
-            let proj = Project::read(Path::new(path))?;
-            projects.insert(name.clone(), proj);
+        for name in changed {
+            if !projects.contains_key(name) {
+                bail!("unknown project `{name}`");
+            }
+            affected.extend(Workspace::affected_util(name, projects));
         }
 
-        return Ok(projects);
+        affected.sort();
+        affected.dedup();
+        return Ok(affected);
     }
 
-    /// Returns a list of validation errors for the workspace.
-    /// See `ValidateProjectsError` for the list of possible errors.
+    /// Explains why a project is affected by another, with the shortest chain
+    /// of tags linking them.
+    /// # Arguments
+    /// * `source` - The project that changed
+    /// * `target` - The project that may be affected
+    ///
     /// # Returns
-    /// * `Vec<ValidateProjectsError>` - The list of validation errors
-    pub fn validate_projects() -> Vec<ValidateProjectsError> {
-        let ws_res = Workspace::read(Path::new("workspace.json"));
+    /// * `Vec<String>` - The chain, alternating projects and the tags linking
+    ///   them, e.g. `["core", "lib", "app"]` when `core` affects the `lib`
+    ///   tag that `app` is affected by. Empty if `target` is not affected by
+    ///   `source`
+    pub fn explain_affected(&self, source: &str, target: &str) -> Result<Vec<String>> {
+        return Workspace::explain_affected_util(source, target, &self.get_projects_map()?);
+    }
 
-        let ws: Workspace;
-        if let Ok(w) = ws_res {
-            ws = w;
-        } else {
-            return vec![ValidateProjectsError::WorkspaceSerialization(
-                ws_res.err().unwrap(),
-            )];
+    fn explain_affected_util(
+        source: &str,
+        target: &str,
+        projects: &HashMap<String, Project>,
+    ) -> Result<Vec<String>> {
+        for name in [source, target] {
+            if !projects.contains_key(name) {
+                bail!("unknown project `{name}`");
+            }
         }
 
-        let mut errors: Vec<ValidateProjectsError> = vec![];
+        let mut names = projects.keys().collect::<Vec<&String>>();
+        names.sort();
 
-        for (name, path) in ws.projects {
-            let proj_res = Project::read(Path::new(&path));
+        // Breadth first, so the chain is the shortest. The source isn't
+        // visited yet, a project only affects itself through a cycle
+        let mut visited: HashSet<&str> = HashSet::new();
+        let mut queue: VecDeque<Vec<String>> = VecDeque::from([vec![source.to_string()]]);
+        while let Some(chain) = queue.pop_front() {
+            let proj = &projects[chain.last().unwrap()];
 
-            let proj: Project;
-            if let Ok(p) = proj_res {
-                proj = p;
-            } else {
-                errors.push(ValidateProjectsError::ProjectSerialization(
-                    name.clone(),
-                    proj_res.err().unwrap(),
-                ));
-                continue;
-            }
+            for name in &names {
+                let Some(tag) = proj
+                    .affects_tags
+                    .iter()
+                    .find(|t| projects[*name].affected_by_tags.contains(t))
+                else {
+                    continue;
+                };
+                if !visited.insert(name.as_str()) {
+                    continue;
+                }
 
-            // check targets
-            for target in ws.required_targets.clone() {
-                if !proj.targets.contains_key(&target) {
-                    errors.push(ValidateProjectsError::MissingTargets(name.clone(), target));
+                let mut next = chain.clone();
+                next.push(tag.clone());
+                next.push(name.to_string());
+                if *name == target {
+                    return Ok(next);
                 }
+                queue.push_back(next);
             }
+        }
 
-            // check tags
-            //// check affects tags
-            let mut unknown_tags: Vec<String> = vec![];
-            for tag in proj.affects_tags.clone() {
-                if !ws.tags.contains(&tag) {
-                    unknown_tags.push(tag);
-                }
+        return Ok(vec![]);
+    }
+
+    /// Builds the graph of the given target across all the projects that
+    /// have it. A project's task depends on the same target of every project
+    /// that affects it.
+    /// `{profile}` in the targets' commands is replaced by the profile.
+    /// The tasks of a project with a `.env` file next to its project file load
+    /// its variables, overriding the workspace's `env`, see `RunEnv`.
+    /// Every project is read, since any of them may have the target, use
+    /// `build_task_graph_for` to only read the projects some tasks need.
+    /// # Arguments
+    /// * `target` - The name of the target, e.g. `build`, or of an alias
+    ///   whose targets are all built, see `expand_target`
+    /// * `profile` - The build profile, e.g. `release`. Defaults to the
+    ///   workspace's `default_profile`
+    ///
+    /// # Returns
+    /// * `TaskGraph` - The graph of `<project>:<target>` tasks
+    pub fn build_task_graph(&self, target: &str, profile: Option<&str>) -> Result<TaskGraph> {
+        let projects = self.get_projects_map()?;
+        let targets = Workspace::expand_target_util(target, &self.aliases, &projects)?;
+        let options = self.task_options(profile.unwrap_or(&self.default_profile));
+        return Ok(Workspace::pipeline_builder(&targets, &projects, options, false)?.build()?);
+    }
+
+    /// Builds the graph of the given tasks and the tasks they depend on, like
+    /// `build_task_graph`, reading the project files lazily: a project is
+    /// only read once the traversal from the tasks reaches it, e.g. to run a
+    /// single project's target in a huge workspace.
+    ///
+    /// Finding the projects affecting a project takes the tags of all of
+    /// them, so every project is read as soon as a reached project is
+    /// affected by a tag, or depends on an upstream target.
+    /// # Arguments
+    /// * `tasks` - The `<project>:<target>` tasks to build
+    /// * `profile` - The build profile, defaults to the workspace's
+    ///   `default_profile`
+    ///
+    /// # Returns
+    /// * `TaskGraph` - The graph of the tasks and their dependencies
+    pub fn build_task_graph_for(
+        &self,
+        tasks: &[TaskID],
+        profile: Option<&str>,
+    ) -> Result<TaskGraph> {
+        // Split by the workspace file, without reading any project
+        let mut roots: Vec<(&str, &str)> = vec![];
+        for id in tasks {
+            let split = self.projects.keys().find_map(|name| {
+                let target = id.strip_prefix(name.as_str())?.strip_prefix(':')?;
+                Some((name.as_str(), target))
+            });
+            let Some(root) = split else {
+                bail!("task `{id}` is not of a project of the workspace");
+            };
+            roots.push(root);
+        }
+
+        let projects = self.reached_projects(&roots)?;
+        let mut builder = TaskGraphBuilder::new();
+        let options = self.task_options(profile.unwrap_or(&self.default_profile));
+        for (name, target) in roots {
+            if !projects[name].targets.contains_key(target) {
+                bail!("project `{name}` has no target `{target}`");
             }
+            Workspace::add_project_task(&mut builder, name, target, &projects, options)?;
+        }
+        return Ok(builder.build()?);
+    }
 
-            //// check affected by tags
-            for tag in proj.affected_by_tags.clone() {
-                if !ws.tags.contains(&tag) {
-                    unknown_tags.push(tag);
-                }
+    /// Reads the projects the tasks of the given targets may depend on, see
+    /// `build_task_graph_for`.
+    /// # Arguments
+    /// * `roots` - The (project, target) pairs the traversal starts from
+    ///
+    /// # Returns
+    /// * `HashMap<String, Project>` - The projects read, every project of the
+    ///   workspace once the tags are needed
+    fn reached_projects(&self, roots: &[(&str, &str)]) -> Result<HashMap<String, Project>> {
+        let mut projects: HashMap<String, Project> = HashMap::new();
+        let mut seen: HashSet<(String, String)> = HashSet::new();
+        let mut queue = roots
+            .iter()
+            .map(|(name, target)| (name.to_string(), target.to_string()))
+            .collect::<VecDeque<(String, String)>>();
+
+        while let Some((name, target)) = queue.pop_front() {
+            if !seen.insert((name.clone(), target.clone())) {
+                continue;
+            }
+            if !projects.contains_key(&name) {
+                let proj = self.load_project(&name)?;
+                projects.insert(name.clone(), proj);
             }
 
-            if unknown_tags.is_empty() {
-                errors.push(ValidateProjectsError::UnknownTags(
-                    name.clone(),
-                    unknown_tags,
-                ));
+            let proj = &projects[&name];
+            let Some(t) = proj.targets.get(&target) else {
+                // Reported when the task is added
+                continue;
+            };
+            let upstream = t.depends_on.iter().any(|d| d.starts_with('^'));
+            if upstream || !proj.affected_by_tags.is_empty() {
+                return self.get_projects_map();
             }
+            queue.extend(t.depends_on.iter().map(|d| (name.clone(), d.clone())));
         }
 
-        return errors;
+        return Ok(projects);
+    }
+
+    /// Expands a target alias into the targets it stands for, recursively.
+    /// Names that are not aliases are targets, and expand to themselves.
+    /// Fails on a cycle of aliases, or an alias referring to a name that is
+    /// neither an alias nor the target of any project.
+    /// # Returns
+    /// * `Vec<String>` - The targets, in the order the aliases list them,
+    ///   without duplicates
+    pub fn expand_target(&self, target: &str) -> Result<Vec<String>> {
+        return Workspace::expand_target_util(target, &self.aliases, &self.get_projects_map()?);
+    }
+
+    fn expand_target_util(
+        target: &str,
+        aliases: &HashMap<String, Vec<String>>,
+        projects: &HashMap<String, Project>,
+    ) -> Result<Vec<String>> {
+        let mut targets: Vec<String> = vec![];
+        Workspace::expand_alias(target, aliases, projects, &mut vec![], &mut targets)?;
+        return Ok(targets);
+    }
+
+    /// Adds the targets of an alias to `targets`.
+    /// # Arguments
+    /// * `path` - The aliases being expanded, to detect cycles
+    fn expand_alias(
+        name: &str,
+        aliases: &HashMap<String, Vec<String>>,
+        projects: &HashMap<String, Project>,
+        path: &mut Vec<String>,
+        targets: &mut Vec<String>,
+    ) -> Result<()> {
+        let Some(members) = aliases.get(name) else {
+            if !targets.iter().any(|t| t == name) {
+                targets.push(name.to_string());
+            }
+            return Ok(());
+        };
+        if path.iter().any(|a| a == name) {
+            bail!("alias cycle: {} -> {name}", path.join(" -> "));
+        }
+
+        path.push(name.to_string());
+        for member in members {
+            if !aliases.contains_key(member)
+                && !projects.values().any(|p| p.targets.contains_key(member))
+            {
+                bail!("alias `{name}` refers to unknown target `{member}`");
+            }
+            Workspace::expand_alias(member, aliases, projects, path, targets)?;
+        }
+        path.pop();
+
+        return Ok(());
+    }
+
+    /// Builds a single graph running every target of a pipeline across all
+    /// the projects. The targets run in any order, use
+    /// `build_ordered_pipeline` to run them one after the other.
+    /// # Arguments
+    /// * `name` - The name of the pipeline, e.g. `ci`
+    ///
+    /// # Returns
+    /// * `TaskGraph` - The graph of the `<project>:<target>` tasks of every
+    ///   target in the pipeline
+    pub fn build_pipeline(&self, name: &str) -> Result<TaskGraph> {
+        return self.build_pipeline_util(name, false);
+    }
+
+    /// Same as `build_pipeline`, except every task of a target also depends
+    /// on all the tasks of the target before it in the pipeline. A phase only
+    /// starts once the previous one is done.
+    pub fn build_ordered_pipeline(&self, name: &str) -> Result<TaskGraph> {
+        return self.build_pipeline_util(name, true);
+    }
+
+    /// The settings of the tasks made for the workspace's targets.
+    /// # Arguments
+    /// * `profile` - The build profile
+    fn task_options<'a>(&'a self, profile: &'a str) -> TaskOptions<'a> {
+        return TaskOptions {
+            profile,
+            default_shell: self.default_shell.as_ref(),
+            named_inputs: Some(&self.named_inputs),
+        };
+    }
+
+    fn build_pipeline_util(&self, name: &str, ordered: bool) -> Result<TaskGraph> {
+        let Some(names) = self.pipelines.get(name) else {
+            bail!("unknown pipeline `{name}`");
+        };
+        let projects = self.get_projects_map()?;
+        let mut targets: Vec<String> = vec![];
+        for name in names {
+            for target in Workspace::expand_target_util(name, &self.aliases, &projects)? {
+                if !targets.contains(&target) {
+                    targets.push(target);
+                }
+            }
+        }
+        let builder = Workspace::pipeline_builder(
+            &targets,
+            &projects,
+            self.task_options(&self.default_profile),
+            ordered,
+        )?;
+        return Ok(builder.build()?);
+    }
+
+    /// Creates the builder for the graph of several targets across all the
+    /// projects that have them.
+    /// # Arguments
+    /// * `ordered` - Whether each target depends on the one before it
+    fn pipeline_builder(
+        targets: &[String],
+        projects: &HashMap<String, Project>,
+        options: TaskOptions,
+        ordered: bool,
+    ) -> Result<TaskGraphBuilder> {
+        let mut builder = TaskGraphBuilder::new();
+        let mut previous: Vec<TaskID> = vec![];
+
+        for target in targets {
+            let ids = Workspace::add_target_tasks(&mut builder, target, projects, options)?;
+            if ordered {
+                for id in &ids {
+                    builder.add_dependencies(id.clone(), previous.clone());
+                }
+                // A target without tasks does not break the chain
+                if !ids.is_empty() {
+                    previous = ids;
+                }
+            }
+        }
+
+        return Ok(builder);
+    }
+
+    /// Builds the graph of the given target for the changed projects, the
+    /// projects they affect, and the tasks those depend on.
+    /// # Arguments
+    /// * `target` - The name of the target, e.g. `test`, or of an alias
+    /// * `changed` - The names of the projects that changed
+    ///
+    /// # Returns
+    /// * `TaskGraph` - The graph of the affected `<project>:<target>` tasks
+    pub fn affected_task_graph(&self, target: &str, changed: &[String]) -> Result<TaskGraph> {
+        let projects = self.get_projects_map()?;
+        return Workspace::affected_task_graph_util(
+            &Workspace::expand_target_util(target, &self.aliases, &projects)?,
+            changed,
+            &projects,
+            self.task_options(&self.default_profile),
+        );
+    }
+
+    fn affected_task_graph_util(
+        targets: &[String],
+        changed: &[String],
+        projects: &HashMap<String, Project>,
+        options: TaskOptions,
+    ) -> Result<TaskGraph> {
+        let mut affected = Workspace::affected_by_many_util(changed, projects)?;
+        affected.extend(changed.iter().cloned());
+        affected.sort();
+        affected.dedup();
+
+        let roots = targets
+            .iter()
+            .flat_map(|target| {
+                affected
+                    .iter()
+                    .filter(|p| projects[*p].targets.contains_key(target))
+                    .map(|p| task_id(p, target))
+            })
+            .collect::<Vec<TaskID>>();
+
+        let builder = Workspace::pipeline_builder(targets, projects, options, false)?;
+        return Ok(builder.build_for(&roots)?);
+    }
+
+    /// Watches the files of the projects, and builds the graph of the given
+    /// target for the affected projects whenever some change, see
+    /// `affected_task_graph`. Changes are debounced, so saving many files at
+    /// once builds a single graph. Files ignored by the workspace's
+    /// `.gitignore`, e.g. build outputs, are not watched.
+    /// The projects are read again on every change, so changes to their files
+    /// are picked up, but only the files that changed are actually read.
+    /// Runs until the watcher fails.
+    /// # Arguments
+    /// * `target` - The name of the target, e.g. `test`, or of an alias
+    /// * `on_change` - Called with the graph of every change, e.g. to run it
+    pub fn watch(&self, target: &str, mut on_change: impl FnMut(TaskGraph)) -> Result<()> {
+        let mut cache = ProjectCache::new();
+        let projects = self.cached_projects_map(&mut cache)?;
+        let targets = Workspace::expand_target_util(target, &self.aliases, &projects)?;
+
+        let cwd = std::env::current_dir()?;
+        let ignored = Gitignore::new(cwd.join(".gitignore")).0;
+        let mut roots = projects
+            .iter()
+            .map(|(name, proj)| (name.clone(), cwd.join(&proj.root)))
+            .collect::<Vec<(String, PathBuf)>>();
+        roots.sort_by(|a, b| a.1.cmp(&b.1));
+
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+        let mut watched: Vec<&Path> = vec![];
+        for (_, root) in &roots {
+            // Nested roots are already watched with their parent
+            if !watched.iter().any(|w| root.starts_with(w)) {
+                watcher.watch(root, RecursiveMode::Recursive)?;
+                watched.push(root);
+            }
+        }
+
+        while let Some(paths) = Workspace::debounce(&rx, WATCH_DEBOUNCE)? {
+            let paths = paths
+                .into_iter()
+                .filter(|p| !p.components().any(|c| c.as_os_str() == ".git"))
+                .filter(|p| {
+                    let relative = p.strip_prefix(&cwd).unwrap_or(p);
+                    !ignored
+                        .matched_path_or_any_parents(relative, p.is_dir())
+                        .is_ignore()
+                })
+                .collect::<Vec<PathBuf>>();
+
+            let changed = Workspace::changed_projects(&paths, &roots);
+            if changed.is_empty() {
+                continue;
+            }
+            let projects = self.cached_projects_map(&mut cache)?;
+            on_change(Workspace::affected_task_graph_util(
+                &targets,
+                &changed,
+                &projects,
+                self.task_options(&self.default_profile),
+            )?);
+        }
+
+        return Ok(());
+    }
+
+    /// Waits for a change, then for the changes that follow it closely.
+    /// # Arguments
+    /// * `window` - How long to wait for another change before returning
+    ///
+    /// # Returns
+    /// * `Option<Vec<PathBuf>>` - The changed paths, `None` once the watcher
+    ///   stopped
+    fn debounce(
+        rx: &mpsc::Receiver<notify::Result<notify::Event>>,
+        window: Duration,
+    ) -> Result<Option<Vec<PathBuf>>> {
+        let Ok(event) = rx.recv() else {
+            return Ok(None);
+        };
+        let mut paths = event?.paths;
+
+        while let Ok(event) = rx.recv_timeout(window) {
+            paths.extend(event?.paths);
+        }
+
+        paths.sort();
+        paths.dedup();
+        return Ok(Some(paths));
+    }
+
+    /// Finds the projects the paths belong to: the project with the deepest
+    /// root containing each path.
+    /// # Arguments
+    /// * `roots` - The name and root of every project
+    ///
+    /// # Returns
+    /// * `Vec<String>` - The projects, sorted and without duplicates
+    fn changed_projects(paths: &[PathBuf], roots: &[(String, PathBuf)]) -> Vec<String> {
+        let mut changed = paths
+            .iter()
+            .filter_map(|path| {
+                roots
+                    .iter()
+                    .filter(|(_, root)| path.starts_with(root))
+                    .max_by_key(|(_, root)| root.components().count())
+                    .map(|(name, _)| name.clone())
+            })
+            .collect::<Vec<String>>();
+
+        changed.sort();
+        changed.dedup();
+        return changed;
+    }
+
+    /// Adds the tasks of the given target, and the dependencies between them,
+    /// to a builder. The targets the tasks depend on are added too, see
+    /// `Target::depends_on`. Fails if the outputs of a target can't be
+    /// resolved.
+    /// # Returns
+    /// * `Vec<TaskID>` - The added tasks of the target, sorted
+    fn add_target_tasks(
+        builder: &mut TaskGraphBuilder,
+        target: &str,
+        projects: &HashMap<String, Project>,
+        options: TaskOptions,
+    ) -> Result<Vec<TaskID>> {
+        let mut ids: Vec<TaskID> = vec![];
+
+        for (name, proj) in projects {
+            if proj.targets.contains_key(target) {
+                ids.push(Workspace::add_project_task(
+                    builder, name, target, projects, options,
+                )?);
+            }
+        }
+
+        ids.sort();
+        return Ok(ids);
+    }
+
+    /// Adds the task of a project's target, unless it was already added, and
+    /// the tasks it depends on.
+    /// # Arguments
+    /// * `name` - The name of the project, which must have the target
+    ///
+    /// # Returns
+    /// * `TaskID` - The ID of the task
+    fn add_project_task(
+        builder: &mut TaskGraphBuilder,
+        name: &str,
+        target: &str,
+        projects: &HashMap<String, Project>,
+        options: TaskOptions,
+    ) -> Result<TaskID> {
+        let profile = options.profile;
+        let proj = &projects[name];
+        let t = &proj.targets[target];
+        let id = task_id(name, target);
+        if builder.has_task(&id) {
+            return Ok(id);
+        }
+
+        let cmd = t
+            .command
+            .iter()
+            .map(|arg| arg.replace("{profile}", profile))
+            .collect::<Vec<String>>();
+        let shell = match &t.shell {
+            None | Some(TargetShell::Default(true)) => options.default_shell.cloned(),
+            Some(TargetShell::Default(false)) => None,
+            Some(TargetShell::Custom(program, args)) => Some((program.clone(), args.clone())),
+        };
+        let cmd = match shell {
+            Some((program, mut args)) if !cmd.is_empty() => {
+                args.insert(0, program);
+                let script = match cmd.as_slice() {
+                    [script] => script.clone(),
+                    words => words
+                        .iter()
+                        .map(|w| shell_quote(w))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                };
+                args.push(script);
+                args
+            }
+            _ => cmd,
+        };
+        let outputs = t
+            .resolve_outputs(&proj.root, profile)
+            .map_err(|e| e.context(format!("invalid outputs for `{id}`")))?;
+        let inputs = t
+            .resolve_inputs(&proj.root, options.named_inputs.unwrap_or(&HashMap::new()))
+            .map_err(|e| e.context(format!("invalid inputs for `{id}`")))?;
+        let mut task = Task::new(&id, &id, Action::shell(cmd))
+            .with_profile(profile)
+            .with_inputs(inputs)
+            .with_outputs(outputs)
+            .with_tags(t.tags.clone());
+        if let Some(executor) = &t.executor {
+            task = task.with_executor(executor);
+        }
+        // The project's own variables, read when the task runs
+        let env_file = proj.root.join(".env");
+        if env_file.is_file() {
+            task = task.with_env_file(env_file, MissingEnvFile::Ignore);
+        }
+        // Added before its dependencies, so that a cycle fails the build
+        // instead of recursing forever
+        builder.add_task(task);
+
+        // Depend on the projects affecting this one
+        for dep_name in Workspace::upstream_projects(name, target, projects) {
+            let dep_id = Workspace::add_project_task(builder, dep_name, target, projects, options)?;
+            builder.add_dependency(id.clone(), dep_id);
+        }
+
+        for dependency in &t.depends_on {
+            let (dep_names, dep_target) = match dependency.strip_prefix('^') {
+                Some(upstream) => (
+                    Workspace::upstream_projects(name, upstream, projects),
+                    upstream,
+                ),
+                None => {
+                    if !proj.targets.contains_key(dependency) {
+                        bail!("`{id}` depends on unknown target `{dependency}`");
+                    }
+                    (vec![name], dependency.as_str())
+                }
+            };
+            for dep_name in dep_names {
+                let dep_id =
+                    Workspace::add_project_task(builder, dep_name, dep_target, projects, options)?;
+                builder.add_dependency(id.clone(), dep_id);
+            }
+        }
+
+        return Ok(id);
+    }
+
+    /// Returns the other projects with the target that affect a project.
+    fn upstream_projects<'a>(
+        name: &str,
+        target: &str,
+        projects: &'a HashMap<String, Project>,
+    ) -> Vec<&'a str> {
+        let proj = &projects[name];
+        return projects
+            .iter()
+            .filter(|(dep_name, dep)| {
+                *dep_name != name
+                    && dep.targets.contains_key(target)
+                    && dep
+                        .affects_tags
+                        .iter()
+                        .any(|t| proj.affected_by_tags.contains(t))
+            })
+            .map(|(dep_name, _)| dep_name.as_str())
+            .collect();
+    }
+
+    /// Returns the `affects_tags` entries that no project in the workspace is
+    /// affected by. Such tags do not affect anything and are likely a mistake.
+    ///
+    /// # Returns
+    /// * `Vec<(String, String)>` - The (project, tag) pairs, sorted
+    pub fn dangling_affect_tags(&self) -> Result<Vec<(String, String)>> {
+        return Ok(Workspace::dangling_affect_tags_util(
+            &self.get_projects_map()?,
+        ));
+    }
+
+    fn dangling_affect_tags_util(projects: &HashMap<String, Project>) -> Vec<(String, String)> {
+        let mut dangling: Vec<(String, String)> = vec![];
+
+        for (name, proj) in projects {
+            for tag in &proj.affects_tags {
+                if !projects.values().any(|p| p.affected_by_tags.contains(tag)) {
+                    dangling.push((name.clone(), tag.clone()));
+                }
+            }
+        }
+
+        dangling.sort();
+        return dangling;
+    }
+
+    /// Compares the structure of two versions of a workspace: their projects
+    /// and tags. Use `Project::diff` to compare the projects themselves.
+    /// # Arguments
+    /// * `other` - The newer version of the workspace
+    pub fn diff(&self, other: &Workspace) -> WorkspaceDiff {
+        let (added_projects, removed_projects) =
+            added_removed(self.projects.keys(), other.projects.keys());
+        let mut moved_projects = self
+            .projects
+            .iter()
+            .filter_map(|(name, entry)| {
+                let path = entry.path()?;
+                let new_path = other.projects.get(name)?.path()?;
+                (new_path != path).then(|| (name.clone(), path.to_string(), new_path.to_string()))
+            })
+            .collect::<Vec<(String, String, String)>>();
+        moved_projects.sort();
+        let (added_tags, removed_tags) = added_removed(&self.tags, &other.tags);
+        let (added_required_targets, removed_required_targets) = added_removed(
+            &self.required_targets.entries(),
+            &other.required_targets.entries(),
+        );
+
+        WorkspaceDiff {
+            added_projects,
+            removed_projects,
+            moved_projects,
+            added_tags,
+            removed_tags,
+            added_required_targets,
+            removed_required_targets,
+        }
+    }
+
+    /// Returns the environment of a run of the workspace's tasks, with the
+    /// workspace's default variables on top of the given base.
+    /// # Arguments
+    /// * `base` - The base variables of the run, e.g. a prepared snapshot
+    pub fn run_env(&self, base: HashMap<String, String>) -> RunEnv {
+        return RunEnv::new(base).with_defaults(self.env.clone());
+    }
+
+    /// Records the version of every project in a lockfile, see `Lockfile`.
+    /// # Arguments
+    /// * `path` - Where to write the lockfile, e.g. `workspace.lock`
+    pub fn write_lockfile(&self, path: &Path) -> Result<()> {
+        let lockfile = Lockfile::from_projects(&self.get_projects_map()?);
+        std::fs::write(path, lockfile.to_json()?)?;
+        return Ok(());
+    }
+
+    /// Checks that the projects' versions still match a lockfile written by
+    /// `write_lockfile`.
+    /// Fails listing every project whose version changed, and every project
+    /// that was added or removed since.
+    pub fn verify_lockfile(&self, path: &Path) -> Result<()> {
+        let locked: Lockfile = serde_json::from_reader(File::open(path)?)?;
+        return locked.verify(&Lockfile::from_projects(&self.get_projects_map()?));
+    }
+
+    /// Returns the projects affected by the changes since the last successful
+    /// run recorded by `record_success`, instead of since a git ref, so moving
+    /// the base branch doesn't affect everything again.
+    /// # Arguments
+    /// * `state_path` - The state written by `record_success`. If it doesn't
+    ///   exist, every project is affected
+    ///
+    /// # Returns
+    /// * `Vec<String>` - The changed projects and the projects they affect,
+    ///   sorted and without duplicates
+    pub fn affected_since_last_success(&self, state_path: &Path) -> Result<Vec<String>> {
+        return Workspace::affected_since_util(state_path, &self.get_projects_map()?);
+    }
+
+    fn affected_since_util(
+        state_path: &Path,
+        projects: &HashMap<String, Project>,
+    ) -> Result<Vec<String>> {
+        let current = RunState::from_projects(projects, Path::new(""))?;
+        let last: RunState = match File::open(state_path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => RunState::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let changed = current.changed_since(&last);
+        let mut affected = Workspace::affected_by_many_util(&changed, projects)?;
+        affected.extend(changed);
+        affected.sort();
+        affected.dedup();
+        return Ok(affected);
+    }
+
+    /// Records the current state of the projects as the last successful run,
+    /// see `affected_since_last_success`. Call it once a run succeeded.
+    /// # Arguments
+    /// * `state_path` - Where to write the state, e.g. `.nx/last-success.json`
+    pub fn record_success(&self, state_path: &Path) -> Result<()> {
+        return RunState::from_projects(&self.get_projects_map()?, Path::new(""))?
+            .write(state_path);
+    }
+
+    /// Hashes the config of the workspace at `root`: its workspace file,
+    /// every project file and the projects' `.env` files, e.g. as a cache key
+    /// component shared by all the tasks, since a config change may affect
+    /// any of them. Line endings are normalized, so the hash is the same on
+    /// every machine.
+    /// # Arguments
+    /// * `root` - The directory containing `workspace.json`. Project paths are
+    ///   relative to it
+    ///
+    /// # Returns
+    /// * `String` - The hash, as a hex encoded SHA-256
+    pub fn config_hash(root: &Path) -> Result<String> {
+        let ws_path = root.join("workspace.json");
+        let ws = Workspace::read(&ws_path)?;
+
+        // Inline projects are hashed with the workspace file
+        let mut files = vec![(String::new(), ws_path)];
+        let mut names = ws.projects.keys().collect::<Vec<&String>>();
+        names.sort();
+        for name in names {
+            let entry = &ws.projects[name];
+            let dir = match entry.path() {
+                Some(path) => {
+                    files.push((name.clone(), root.join(path)));
+                    Path::new(path)
+                        .parent()
+                        .map(Path::to_path_buf)
+                        .unwrap_or_default()
+                }
+                None => entry.load(root)?.root,
+            };
+            // Read by the project's tasks, see `build_task_graph`
+            let env_file = root.join(dir).join(".env");
+            if env_file.is_file() {
+                files.push((format!("{name}/.env"), env_file));
+            }
+        }
+
+        let mut hasher = Sha256::new();
+        for (name, path) in files {
+            let contents = match std::fs::read(&path) {
+                Ok(contents) => contents,
+                Err(e) => bail!("failed to read `{}`: {e}", path.display()),
+            };
+            // On the raw bytes, the files need not be UTF-8
+            let contents = normalize_line_endings(&contents);
+            // Length prefixed so that a name can't run into the contents
+            hasher.update((name.len() as u64).to_le_bytes());
+            hasher.update(name.as_bytes());
+            hasher.update((contents.len() as u64).to_le_bytes());
+            hasher.update(&contents);
+        }
+
+        return Ok(format!("{:x}", hasher.finalize()));
+    }
+
+    fn get_projects_map(&self) -> Result<HashMap<String, Project>> {
+        let mut projects: HashMap<String, Project> = HashMap::new();
+
+        for name in self.projects.keys() {
+            projects.insert(name.clone(), self.load_project(name)?);
+        }
+
+        return Ok(projects);
+    }
+
+    /// Reads a project of the workspace, with its inferred and default
+    /// targets, see `complete_targets`.
+    fn load_project(&self, name: &str) -> Result<Project> {
+        let entry = &self.projects[name];
+        return self.complete_targets(entry.load(Path::new(""))?, Path::new(""));
+    }
+
+    /// Adds the targets a project doesn't define itself: the targets inferred
+    /// from its files, see `register_inferrer`, then the default targets, see
+    /// `target_defaults`.
+    /// # Arguments
+    /// * `root` - The workspace root, which the project's directory is
+    ///   relative to
+    fn complete_targets(&self, mut proj: Project, root: &Path) -> Result<Project> {
+        let dir = root.join(&proj.root);
+        for inferrer in &self.inferrers.0 {
+            for (name, target) in inferrer.infer(&dir)? {
+                proj.targets.entry(name).or_insert(target);
+            }
+        }
+
+        for (name, defaults) in &self.target_defaults {
+            let target = proj.targets.remove(name).unwrap_or_default();
+            proj.targets.insert(name.clone(), target.inherit(defaults));
+        }
+        return Ok(proj);
+    }
+
+    /// Infer targets of the projects from their files, e.g. `build` for a
+    /// project with a `Cargo.toml`, see `CargoInferrer`. The targets a project
+    /// defines override the inferred ones, and inferrers registered first
+    /// override the later ones.
+    pub fn register_inferrer(&mut self, inferrer: impl TargetInferrer + 'static) {
+        self.inferrers.0.push(Arc::new(inferrer));
+    }
+
+    /// Returns the registered inferrers, e.g. for `health_check_with`.
+    pub fn inferrers(&self) -> &[Arc<dyn TargetInferrer>] {
+        return &self.inferrers.0;
+    }
+
+    /// Like `get_projects_map`, with the project files read through a cache.
+    fn cached_projects_map(&self, cache: &mut ProjectCache) -> Result<HashMap<String, Project>> {
+        let mut projects: HashMap<String, Project> = HashMap::new();
+
+        for (name, entry) in &self.projects {
+            let proj = entry.load_with(Path::new(""), &mut |path| cache.read(path))?;
+            projects.insert(name.clone(), self.complete_targets(proj, Path::new(""))?);
+        }
+
+        return Ok(projects);
+    }
+
+    /// Returns a list of validation errors for the workspace.
+    /// See `ValidateProjectsError` for the list of possible errors.
+    /// # Returns
+    /// * `Vec<ValidateProjectsError>` - The list of validation errors
+    pub fn validate_projects() -> Vec<ValidateProjectsError> {
+        return Workspace::validate_projects_with(&[]);
+    }
+
+    /// Same as `validate_projects`, with targets inferred for the projects,
+    /// so the projects are checked with the targets `build_task_graph` sees.
+    /// # Arguments
+    /// * `inferrers` - The inferrers registered on the workspace, see
+    ///   `register_inferrer`
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub fn validate_projects_with(
+        inferrers: &[Arc<dyn TargetInferrer>],
+    ) -> Vec<ValidateProjectsError> {
+        let ws_res = Workspace::read(Path::new("workspace.json"));
+
+        let mut ws: Workspace;
+        if let Ok(w) = ws_res {
+            ws = w;
+        } else {
+            return vec![ValidateProjectsError::WorkspaceSerialization(
+                ws_res.err().unwrap(),
+            )];
+        }
+
+        ws.inferrers.0.extend(inferrers.iter().cloned());
+        let errors = ws.validation_errors();
+
+        #[cfg(feature = "tracing")]
+        tracing::info!(errors = errors.len(), "validated the projects");
+        return errors;
+    }
+
+    /// Runs the checks of `validate_projects` on a read workspace.
+    fn validation_errors(&self) -> Vec<ValidateProjectsError> {
+        let mut errors = self.workspace_errors();
+
+        // Sorted so the errors are in the same order on every run
+        let mut names = self.projects.keys().cloned().collect::<Vec<String>>();
+        names.sort();
+
+        for name in names {
+            let proj_res = self.projects[&name]
+                .load(Path::new(""))
+                .and_then(|p| self.complete_targets(p, Path::new("")));
+
+            let proj: Project;
+            if let Ok(p) = proj_res {
+                proj = p;
+            } else {
+                errors.push(ValidateProjectsError::ProjectSerialization(
+                    name.clone(),
+                    proj_res.err().unwrap(),
+                ));
+                continue;
+            }
+
+            errors.extend(self.project_errors(&name, &proj));
+        }
+
+        return errors;
+    }
+
+    /// Validates a single project against the workspace rules, without
+    /// reading the other projects, e.g. to lint a project file on save. Runs
+    /// the checks of `validate_projects`, and checks that the name in the
+    /// project file is the one the workspace uses for it.
+    /// # Arguments
+    /// * `name` - The name of the project in the workspace
+    ///
+    /// # Returns
+    /// * `Vec<ValidateProjectsError>` - The validation errors of the project
+    pub fn validate_project(&self, name: &str) -> Vec<ValidateProjectsError> {
+        let Some(entry) = self.projects.get(name) else {
+            return vec![ValidateProjectsError::UnknownProject(name.to_string())];
+        };
+        let proj = entry
+            .load(Path::new(""))
+            .and_then(|p| self.complete_targets(p, Path::new("")));
+        let proj = match proj {
+            Ok(proj) => proj,
+            Err(e) => {
+                return vec![ValidateProjectsError::ProjectSerialization(
+                    name.to_string(),
+                    e,
+                )]
+            }
+        };
+
+        let mut errors: Vec<ValidateProjectsError> = vec![];
+        if proj.name != name {
+            errors.push(ValidateProjectsError::NameMismatch(
+                name.to_string(),
+                proj.name.clone(),
+            ));
+        }
+        errors.extend(self.project_errors(name, &proj));
+        return errors;
+    }
+
+    /// Runs the checks of the workspace itself.
+    fn workspace_errors(&self) -> Vec<ValidateProjectsError> {
+        let mut errors: Vec<ValidateProjectsError> = vec![];
+
+        if self.required_targets.is_empty() {
+            errors.push(ValidateProjectsError::NoRequiredTargets);
+        }
+
+        return errors;
+    }
+
+    /// Runs the checks of a single project against the workspace rules: the
+    /// required targets and the known tags.
+    fn project_errors(&self, name: &str, proj: &Project) -> Vec<ValidateProjectsError> {
+        let mut errors: Vec<ValidateProjectsError> = vec![];
+
+        // check targets
+        if self.check_empty_projects && proj.targets.is_empty() {
+            errors.push(ValidateProjectsError::NoTargets(name.to_string()));
+        }
+        let required = self.required_targets.for_type(proj.project_type.as_deref());
+        for target in required {
+            if !proj.targets.contains_key(target) {
+                errors.push(ValidateProjectsError::MissingTargets(
+                    name.to_string(),
+                    target.clone(),
+                ));
+            }
+        }
+
+        // check tags
+        //// check affects tags
+        let mut unknown_tags: Vec<String> = vec![];
+        for tag in proj.affects_tags.clone() {
+            if !self.tags.contains(&tag) {
+                unknown_tags.push(tag);
+            }
+        }
+
+        //// check affected by tags
+        for tag in proj.affected_by_tags.clone() {
+            if !self.tags.contains(&tag) {
+                unknown_tags.push(tag);
+            }
+        }
+
+        if !unknown_tags.is_empty() {
+            errors.push(ValidateProjectsError::UnknownTags(
+                name.to_string(),
+                unknown_tags,
+            ));
+        }
+
+        //// check that the project doesn't affect itself
+        for tag in &proj.affects_tags {
+            if proj.affected_by_tags.contains(tag) {
+                errors.push(ValidateProjectsError::SelfAffectingTag(
+                    name.to_string(),
+                    tag.clone(),
+                ));
+            }
+        }
+
+        //// check the targets' tags
+        for target in proj.target_names() {
+            let unknown_tags = proj.targets[target]
+                .tags
+                .iter()
+                .filter(|t| !self.tags.contains(t))
+                .cloned()
+                .collect::<Vec<String>>();
+            if !unknown_tags.is_empty() {
+                errors.push(ValidateProjectsError::UnknownTargetTags(
+                    name.to_string(),
+                    target.clone(),
+                    unknown_tags,
+                ));
+            }
+        }
+
+        return errors;
+    }
+
+    /// Runs every validation of the workspace at `root`, and its projects.
+    /// Unlike `validate_projects`, it doesn't stop at the first issue of a
+    /// project, and also checks that the files exist, that project names match
+    /// the workspace, and that the tags don't form cycles.
+    /// # Arguments
+    /// * `root` - The directory containing `workspace.json`. Project paths are
+    ///   relative to it
+    ///
+    /// # Returns
+    /// * `HealthReport` - Every issue found
+    pub fn health_check(root: &Path) -> HealthReport {
+        return Workspace::health_check_with(root, &[]);
+    }
+
+    /// Same as `health_check`, with targets inferred for the projects, so
+    /// the projects are checked with the targets `build_task_graph` sees.
+    /// # Arguments
+    /// * `root` - The directory containing `workspace.json`
+    /// * `inferrers` - The inferrers registered on the workspace, see
+    ///   `register_inferrer`
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(root = %root.display())))]
+    pub fn health_check_with(root: &Path, inferrers: &[Arc<dyn TargetInferrer>]) -> HealthReport {
+        let mut report = HealthReport::default();
+
+        let ws_path = root.join("workspace.json");
+        if !ws_path.is_file() {
+            report
+                .errors
+                .push(ValidateProjectsError::MissingFile(ws_path));
+            return report;
+        }
+        let mut ws = match Workspace::read(&ws_path) {
+            Ok(ws) => ws,
+            Err(e) => {
+                report
+                    .errors
+                    .push(ValidateProjectsError::WorkspaceSerialization(e));
+                return report;
+            }
+        };
+        ws.inferrers.0.extend(inferrers.iter().cloned());
+        report.errors.extend(ws.workspace_errors());
+
+        let mut names = ws.projects.keys().cloned().collect::<Vec<String>>();
+        names.sort();
+
+        let mut projects: HashMap<String, Project> = HashMap::new();
+        for name in names {
+            let entry = &ws.projects[&name];
+            if let Some(path) = entry.path().map(|p| root.join(p)) {
+                if !path.is_file() {
+                    report.errors.push(ValidateProjectsError::MissingFile(path));
+                    continue;
+                }
+            }
+
+            let proj = match entry.load(root).and_then(|p| ws.complete_targets(p, root)) {
+                Ok(proj) => proj,
+                Err(e) => {
+                    report
+                        .errors
+                        .push(ValidateProjectsError::ProjectSerialization(name, e));
+                    continue;
+                }
+            };
+
+            if proj.name != name {
+                report.errors.push(ValidateProjectsError::NameMismatch(
+                    name.clone(),
+                    proj.name.clone(),
+                ));
+            }
+            report.errors.extend(ws.project_errors(&name, &proj));
+            projects.insert(name, proj);
+        }
+
+        for cycle in Workspace::tag_cycles(&projects) {
+            report.errors.push(ValidateProjectsError::TagCycle(cycle));
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::info!(errors = report.errors.len(), "checked the workspace health");
+        return report;
+    }
+
+    /// Finds the cycles in the affected relationships of the projects.
+    /// Projects affecting themselves are left to `SelfAffectingTag`.
+    /// Every cycle is found once, starting from its first project by name.
+    /// # Returns
+    /// * `Vec<Vec<String>>` - The projects of each cycle, in affected order
+    fn tag_cycles(projects: &HashMap<String, Project>) -> Vec<Vec<String>> {
+        let mut names = projects.keys().collect::<Vec<&String>>();
+        names.sort();
+
+        // The projects each project directly affects, in reverse so they are
+        // popped by name
+        let affects = |name: &String| -> Vec<&String> {
+            let tags = &projects[name].affects_tags;
+            names
+                .iter()
+                .rev()
+                .filter(|n| {
+                    **n != name
+                        && projects[**n]
+                            .affected_by_tags
+                            .iter()
+                            .any(|t| tags.contains(t))
+                })
+                .copied()
+                .collect()
+        };
+
+        let mut cycles: Vec<Vec<String>> = vec![];
+        for start in &names {
+            // Depth first search of the paths back to the start, through the
+            // projects after it only, so no cycle is found twice. The
+            // projects on the current path are tracked per search, a project
+            // left by one path may still close a cycle through another
+            let mut path: Vec<&String> = vec![start];
+            let mut stack: Vec<Vec<&String>> = vec![affects(start)];
+
+            while let Some(next) = stack.last_mut() {
+                let Some(child) = next.pop() else {
+                    stack.pop();
+                    path.pop();
+                    continue;
+                };
+
+                if child == *start {
+                    cycles.push(path.iter().map(|p| p.to_string()).collect());
+                } else if child > *start && !path.contains(&child) {
+                    path.push(child);
+                    stack.push(affects(child));
+                }
+            }
+        }
+
+        return cycles;
+    }
+}
+
+/// How serious a validation issue is, or a lint of a graph, see
+/// `TaskGraph::lint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Severity {
+    /// Worth a look, but often intended
+    Info,
+    /// Probably a mistake, but the workspace still works
+    Warning,
+    /// The workspace is broken
+    Error,
+}
+
+impl ValidateProjectsError {
+    pub fn severity(&self) -> Severity {
+        match self {
+            ValidateProjectsError::MissingTargets(..)
+            | ValidateProjectsError::UnknownTags(..)
+            | ValidateProjectsError::UnknownTargetTags(..)
+            | ValidateProjectsError::ProjectSerialization(..)
+            | ValidateProjectsError::WorkspaceSerialization(..)
+            | ValidateProjectsError::MissingFile(..)
+            | ValidateProjectsError::NameMismatch(..)
+            | ValidateProjectsError::TagCycle(..)
+            | ValidateProjectsError::SelfAffectingTag(..)
+            | ValidateProjectsError::UnknownProject(..) => Severity::Error,
+            ValidateProjectsError::NoRequiredTargets | ValidateProjectsError::NoTargets(..) => {
+                Severity::Warning
+            }
+        }
+    }
+}
+
+/// The result of `Workspace::health_check`.
+#[derive(Debug, Default)]
+pub struct HealthReport {
+    /// Every issue found, in the order the checks ran
+    pub errors: Vec<ValidateProjectsError>,
+}
+
+impl HealthReport {
+    /// Whether the workspace has no error. Warnings are fine.
+    pub fn is_healthy(&self) -> bool {
+        !self.errors.iter().any(|e| e.severity() == Severity::Error)
+    }
+
+    /// Counts the issues of each severity.
+    pub fn counts(&self) -> HashMap<Severity, usize> {
+        let mut counts: HashMap<Severity, usize> = HashMap::new();
+
+        for error in &self.errors {
+            *counts.entry(error.severity()).or_default() += 1;
+        }
+
+        return counts;
+    }
+}
+
+/// The structural differences between two versions of a workspace, see
+/// `Workspace::diff`. Every list is sorted.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct WorkspaceDiff {
+    pub added_projects: Vec<String>,
+    pub removed_projects: Vec<String>,
+    /// The projects whose file moved, as (name, old path, new path)
+    pub moved_projects: Vec<(String, String, String)>,
+    pub added_tags: Vec<String>,
+    pub removed_tags: Vec<String>,
+    /// The required targets, prefixed by their project type and a colon if
+    /// they are per type, e.g. `lib:build`
+    pub added_required_targets: Vec<String>,
+    pub removed_required_targets: Vec<String>,
+}
+
+impl WorkspaceDiff {
+    /// Whether the workspaces have the same structure.
+    pub fn is_empty(&self) -> bool {
+        *self == WorkspaceDiff::default()
+    }
+}
+
+/// The structural differences between two versions of a project, see
+/// `Project::diff`. Every list is sorted.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct ProjectDiff {
+    /// The name of the newer version of the project
+    pub name: String,
+    pub added_affects_tags: Vec<String>,
+    pub removed_affects_tags: Vec<String>,
+    pub added_affected_by_tags: Vec<String>,
+    pub removed_affected_by_tags: Vec<String>,
+    pub added_targets: Vec<String>,
+    pub removed_targets: Vec<String>,
+}
+
+impl ProjectDiff {
+    /// Whether the projects have the same structure.
+    pub fn is_empty(&self) -> bool {
+        *self
+            == ProjectDiff {
+                name: self.name.clone(),
+                ..Default::default()
+            }
+    }
+}
+
+/// Returns the items only in `new` and the items only in `old`, both sorted.
+fn added_removed<'a>(
+    old: impl IntoIterator<Item = &'a String>,
+    new: impl IntoIterator<Item = &'a String>,
+) -> (Vec<String>, Vec<String>) {
+    let old = old.into_iter().collect::<HashSet<&String>>();
+    let new = new.into_iter().collect::<HashSet<&String>>();
+
+    let mut added = new
+        .difference(&old)
+        .map(|s| s.to_string())
+        .collect::<Vec<String>>();
+    added.sort();
+    let mut removed = old
+        .difference(&new)
+        .map(|s| s.to_string())
+        .collect::<Vec<String>>();
+    removed.sort();
+
+    return (added, removed);
+}
+
+/// The versions of the projects in the workspace, pinned for reproducibility.
+/// Serialized as JSON sorted by project name, so it diffs cleanly.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Lockfile {
+    /// The version of each project, `None` for unversioned projects
+    projects: BTreeMap<String, Option<String>>,
+}
+
+impl Lockfile {
+    fn from_projects(projects: &HashMap<String, Project>) -> Self {
+        Lockfile {
+            projects: projects
+                .iter()
+                .map(|(name, proj)| (name.clone(), proj.version.clone()))
+                .collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        let mut json = serde_json::to_string_pretty(self)?;
+        json.push('\n');
+        return Ok(json);
+    }
+
+    /// Fails if the current versions drifted from the locked ones.
+    /// # Arguments
+    /// * `current` - The versions the projects have now
+    fn verify(&self, current: &Lockfile) -> Result<()> {
+        let show = |v: &Option<String>| v.clone().unwrap_or_else(|| "no version".to_string());
+        let mut drift: Vec<String> = vec![];
+
+        for (name, locked) in &self.projects {
+            match current.projects.get(name) {
+                None => drift.push(format!("`{name}` was removed")),
+                Some(version) if version != locked => drift.push(format!(
+                    "`{name}` is locked at {} but is {}",
+                    show(locked),
+                    show(version)
+                )),
+                Some(_) => {}
+            }
+        }
+        for name in current.projects.keys() {
+            if !self.projects.contains_key(name) {
+                drift.push(format!("`{name}` is not in the lockfile"));
+            }
+        }
+
+        if !drift.is_empty() {
+            bail!("lockfile is out of date: {}", drift.join(", "));
+        }
+        return Ok(());
+    }
+}
+
+/// The state of the projects at a successful run, see
+/// `Workspace::record_success`.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct RunState {
+    /// The hash of the files of each project. Files in the root of a nested
+    /// project only count for the nested project
+    projects: BTreeMap<String, String>,
+}
+
+impl RunState {
+    /// Hashes the files of the projects.
+    /// # Arguments
+    /// * `base` - The workspace root, which the projects' directories are
+    ///   relative to
+    fn from_projects(projects: &HashMap<String, Project>, base: &Path) -> Result<Self> {
+        let roots = projects
+            .iter()
+            .map(|(name, proj)| (name.clone(), proj.root.clone()))
+            .collect::<Vec<(String, PathBuf)>>();
+
+        let mut state = RunState::default();
+        for (name, root) in &roots {
+            // Inline projects, and projects at the root of the workspace, have
+            // an empty directory, which can't be walked
+            let dir = match base.join(root) {
+                dir if dir.as_os_str().is_empty() => PathBuf::from("."),
+                dir => dir,
+            };
+            let walker = WalkBuilder::new(&dir)
+                .hidden(false)
+                .git_global(false)
+                .ignore(false)
+                .parents(false)
+                .require_git(false)
+                .filter_entry(|e| e.file_name() != ".git")
+                .build();
+
+            let mut files: Vec<PathBuf> = vec![];
+            for entry in walker {
+                let entry = entry?;
+                if entry.file_type().is_some_and(|t| t.is_file()) {
+                    let file = entry.into_path();
+                    files.push(file.strip_prefix(&dir).unwrap_or(&file).to_path_buf());
+                }
+            }
+            files.sort();
+
+            let mut hasher = Sha256::new();
+            for file in files {
+                if Workspace::changed_projects(&[root.join(&file)], &roots) != [name.clone()] {
+                    continue;
+                }
+                // Length prefixed so that a path can't run into the contents
+                let path = file.to_string_lossy();
+                let contents = std::fs::read(dir.join(&file))?;
+                hasher.update((path.len() as u64).to_le_bytes());
+                hasher.update(path.as_bytes());
+                hasher.update((contents.len() as u64).to_le_bytes());
+                hasher.update(&contents);
+            }
+            state
+                .projects
+                .insert(name.clone(), format!("{:x}", hasher.finalize()));
+        }
+
+        return Ok(state);
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        let mut json = serde_json::to_string_pretty(self)?;
+        json.push('\n');
+        return Ok(json);
+    }
+
+    fn write(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, self.to_json()?)?;
+        return Ok(());
+    }
+
+    /// Returns the projects that are new or whose files changed since the
+    /// last state, sorted.
+    fn changed_since(&self, last: &RunState) -> Vec<String> {
+        return self
+            .projects
+            .iter()
+            .filter(|(name, hash)| last.projects.get(*name) != Some(hash))
+            .map(|(name, _)| name.clone())
+            .collect();
+    }
+}
+
+/// Quotes a word for a POSIX shell, unless it has no special characters.
+fn shell_quote(word: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-./=:,+@%^".contains(c);
+    if !word.is_empty() && word.chars().all(plain) {
+        return word.to_string();
+    }
+    return format!("'{}'", word.replace('\'', r"'\''"));
+}
+
+/// Replaces the `\r\n` line endings by `\n`.
+fn normalize_line_endings(contents: &[u8]) -> Vec<u8> {
+    let mut normalized = Vec::with_capacity(contents.len());
+    for (i, byte) in contents.iter().enumerate() {
+        if *byte == b'\r' && contents.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+        normalized.push(*byte);
+    }
+    return normalized;
+}
+
+/// The ID of the task running a project's target.
+fn task_id(project: &str, target: &str) -> TaskID {
+    format!("{project}:{target}")
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    const PROJECT: &str = r#"{
+        "name": "app",
+        "version": "1.0.0",
+        "description": "An app",
+        "owners": ["me"],
+        "affects_tags": [],
+        "affected_by_tags": ["lib"],
+        "targets": { "build": {} }
+    }"#;
+
+    const WORKSPACE: &str = r#"{
+        "name": "ws",
+        "app_version": "0.1.0",
+        "projects": { "app": "app/project.json" },
+        "tags": ["lib"],
+        "maintainers": [],
+        "repository": "",
+        "required_targets": ["build"]
+    }"#;
+
+    fn project(name: &str, affects: &[&str], affected_by: &[&str]) -> Project {
+        Project {
+            name: name.to_string(),
+            version: None,
+            description: String::new(),
+            owners: vec![],
+            affects_tags: affects.iter().map(|t| t.to_string()).collect(),
+            affected_by_tags: affected_by.iter().map(|t| t.to_string()).collect(),
+            targets: HashMap::new(),
+            project_type: None,
+            root: PathBuf::from(name),
+        }
+    }
+
+    fn with_target(mut proj: Project, target: &str) -> Project {
+        proj.targets.insert(target.to_string(), Target::default());
+        proj
+    }
+
+    fn projects(projs: Vec<Project>) -> HashMap<String, Project> {
+        projs.into_iter().map(|p| (p.name.clone(), p)).collect()
+    }
+
+    fn options(profile: &str) -> TaskOptions<'_> {
+        TaskOptions {
+            profile,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_read_reader() {
+        let proj = Project::read_reader(PROJECT.as_bytes()).unwrap();
+        assert_eq!(proj.name, "app");
+        assert_eq!(proj.target_names(), vec!["build"]);
+        assert!(proj.target("build").is_some());
+        assert!(proj.target("test").is_none());
+
+        let ws = Workspace::read_reader(WORKSPACE.as_bytes()).unwrap();
+        assert_eq!(ws.name, "ws");
+        assert_eq!(ws.projects["app"].path(), Some("app/project.json"));
+
+        assert!(
+            Project::read_reader("{".as_bytes()).is_err(),
+            "Invalid JSON should not parse"
+        );
+    }
+
+    #[test]
+    fn test_dangling_affect_tags() {
+        let projs = projects(vec![
+            project("lib", &["lib", "nothing"], &[]),
+            project("app", &["unused"], &["lib"]),
+        ]);
+
+        assert_eq!(
+            Workspace::dangling_affect_tags_util(&projs),
+            vec![
+                ("app".to_string(), "unused".to_string()),
+                ("lib".to_string(), "nothing".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_affected_task_graph() {
+        // core -> lib -> app, tool is unrelated
+        let projs = projects(vec![
+            with_target(project("core", &["core"], &[]), "test"),
+            with_target(project("lib", &["lib"], &["core"]), "test"),
+            with_target(project("app", &[], &["lib"]), "test"),
+            with_target(project("tool", &[], &[]), "test"),
+        ]);
+
+        assert_eq!(
+            Workspace::affected_by_many_util(&["core".to_string(), "lib".to_string()], &projs)
+                .unwrap(),
+            vec!["app".to_string(), "lib".to_string()]
+        );
+        assert!(Workspace::affected_by_many_util(&["nope".to_string()], &projs).is_err());
+
+        let mut graph = Workspace::affected_task_graph_util(
+            &["test".to_string()],
+            &["lib".to_string()],
+            &projs,
+            options("debug"),
+        )
+        .unwrap();
+
+        // lib:test needs core:test, which is pulled in as a dependency
+        let mut order: Vec<TaskID> = vec![];
+        while let Some(Some(task)) = graph.next() {
+            graph.done(task.id());
+            order.push(task.id().clone());
+        }
+        assert_eq!(order, vec!["core:test", "lib:test", "app:test"]);
+    }
+
+    #[test]
+    fn test_read_json5() {
+        let data = r#"{
+            // The main app
+            name: "app",
+            description: "An app",
+            owners: ["me"],
+            affects_tags: [],
+            affected_by_tags: ["lib",], // trailing commas are fine
+            targets: {},
+        }"#;
+
+        assert!(
+            Project::read_reader(data.as_bytes()).is_err(),
+            "Strict JSON should be the default"
+        );
+
+        let proj = Project::read_reader_with_format(data.as_bytes(), ConfigFormat::Json5).unwrap();
+        assert_eq!(proj.name, "app");
+        assert_eq!(proj.affected_by_tags, vec!["lib".to_string()]);
+
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("app/project.json5")),
+            ConfigFormat::Json5
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("app/project.json")),
+            ConfigFormat::Json
+        );
+    }
+
+    #[test]
+    fn test_tag_cycles() {
+        let projs = projects(vec![
+            project("a", &["a"], &["c"]),
+            project("b", &["b"], &["a"]),
+            project("c", &["c"], &["b"]),
+            project("d", &[], &["a"]),
+        ]);
+
+        assert_eq!(
+            Workspace::tag_cycles(&projs),
+            vec![vec!["a".to_string(), "b".to_string(), "c".to_string()]]
+        );
+        // `a` affects `b` both directly and through `c`, and `b` affects `a`
+        let projs = projects(vec![
+            project("a", &["a"], &["b"]),
+            project("b", &["b"], &["a", "c"]),
+            project("c", &["c"], &["a"]),
+        ]);
+        let cycles = Workspace::tag_cycles(&projs);
+        let words = |words: &[&str]| words.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+        assert_eq!(cycles, vec![words(&["a", "b"]), words(&["a", "c", "b"])]);
+    }
+
+    #[test]
+    fn test_validation_order() {
+        let project = |name: &str| {
+            format!(
+                r#""{name}": {{
+                    "name": "{name}",
+                    "description": "",
+                    "owners": [],
+                    "affects_tags": ["unknown-{name}"],
+                    "affected_by_tags": [],
+                    "targets": {{}}
+                }}"#
+            )
+        };
+        let errors = |names: &[&str]| {
+            let projects = names.iter().map(|n| project(n)).collect::<Vec<_>>();
+            let json = format!(
+                r#"{{
+                    "name": "ws",
+                    "app_version": "0.1.0",
+                    "projects": {{ {} }},
+                    "tags": [],
+                    "maintainers": [],
+                    "repository": "",
+                    "required_targets": ["build"]
+                }}"#,
+                projects.join(",")
+            );
+            let ws = Workspace::read_reader(json.as_bytes()).unwrap();
+            return format!("{:?}", ws.validation_errors());
+        };
+
+        // The same errors whatever the order the projects were added in
+        let sorted = errors(&["a", "b", "c", "d", "e"]);
+        for shuffled in [
+            ["e", "d", "c", "b", "a"],
+            ["c", "a", "e", "b", "d"],
+            ["b", "e", "a", "d", "c"],
+        ] {
+            assert_eq!(errors(&shuffled), sorted);
+        }
+        assert!(sorted.find(r#""a""#) < sorted.find(r#""e""#), "{sorted}");
+    }
+
+    #[test]
+    fn test_unknown_tags() {
+        let ws = Workspace::read_reader(WORKSPACE.as_bytes()).unwrap();
+        let unknown = |proj: &Project| {
+            ws.project_errors(&proj.name, proj)
+                .into_iter()
+                .filter(|e| matches!(e, ValidateProjectsError::UnknownTags(..)))
+                .collect::<Vec<_>>()
+        };
+
+        // Known tags are no error
+        assert!(unknown(&project("app", &["lib"], &[])).is_empty());
+        assert!(unknown(&project("app", &[], &["lib"])).is_empty());
+
+        let errors = unknown(&project("app", &["lib", "ui"], &["db"]));
+        assert!(matches!(
+            &errors[..],
+            [ValidateProjectsError::UnknownTags(name, tags)] if name == "app" && tags == &["ui", "db"]
+        ));
+    }
+
+    #[test]
+    fn test_self_affecting_tag() {
+        let ws = Workspace::read_reader(WORKSPACE.as_bytes()).unwrap();
+        let app = project("app", &["lib"], &["lib"]);
+
+        let errors = ws.project_errors("app", &app);
+        assert!(errors.iter().any(|e| matches!(
+            e,
+            ValidateProjectsError::SelfAffectingTag(p, t) if p == "app" && t == "lib"
+        )));
+        assert!(Workspace::tag_cycles(&projects(vec![app])).is_empty());
+    }
+
+    #[test]
+    fn test_explain_affected() {
+        let projs = projects(vec![
+            project("core", &["core"], &[]),
+            project("lib", &["lib"], &["core"]),
+            project("app", &[], &["lib", "core"]),
+            project("ui", &["ui"], &["lib"]),
+            project("docs", &[], &[]),
+        ]);
+
+        let explain = |s, t| Workspace::explain_affected_util(s, t, &projs).unwrap();
+        assert_eq!(explain("core", "lib"), ["core", "core", "lib"]);
+        assert_eq!(explain("lib", "ui"), ["lib", "lib", "ui"]);
+        assert_eq!(explain("core", "ui"), ["core", "core", "lib", "lib", "ui"]);
+        // Directly affected, not through lib
+        assert_eq!(explain("core", "app"), ["core", "core", "app"]);
+        assert!(explain("core", "docs").is_empty());
+        assert!(explain("app", "core").is_empty());
+        assert!(Workspace::explain_affected_util("core", "nope", &projs).is_err());
+    }
+
+    #[test]
+    fn test_read_large_workspace() {
+        let path = std::env::temp_dir().join(format!("nx-rs-large-{}.json", std::process::id()));
+        let count = 20_000;
+        let projects = (0..count)
+            .map(|i| format!(r#""proj-{i}": "projects/proj-{i}/project.json""#))
+            .collect::<Vec<String>>()
+            .join(",\n");
+        std::fs::write(
+            &path,
+            WORKSPACE.replace(
+                r#"{ "app": "app/project.json" }"#,
+                &format!("{{{projects}}}"),
+            ),
+        )
+        .unwrap();
+
+        let ws = Workspace::read(&path).unwrap();
+        assert_eq!(ws.projects.len(), count);
+        assert_eq!(
+            ws.projects["proj-12345"].path(),
+            Some("projects/proj-12345/project.json")
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_changed_projects() {
+        let roots = vec![
+            ("ws".to_string(), PathBuf::from("/ws")),
+            ("app".to_string(), PathBuf::from("/ws/app")),
+            ("lib".to_string(), PathBuf::from("/ws/libs/lib")),
+        ];
+        let changed = |paths: &[&str]| {
+            let paths = paths.iter().map(PathBuf::from).collect::<Vec<PathBuf>>();
+            Workspace::changed_projects(&paths, &roots)
+        };
+
+        assert_eq!(
+            changed(&["/ws/app/src/main.rs", "/ws/app/Cargo.toml"]),
+            ["app"]
+        );
+        assert_eq!(
+            changed(&["/ws/libs/lib/a.rs", "/ws/README.md"]),
+            ["lib", "ws"]
+        );
+        // Not a path component prefix
+        assert_eq!(changed(&["/ws/application/a.rs"]), ["ws"]);
+        assert!(changed(&["/elsewhere/a.rs"]).is_empty());
+    }
+
+    #[test]
+    fn test_debounce() {
+        let (tx, rx) = mpsc::channel();
+        let event = |path: &str| {
+            Ok(notify::Event::new(notify::EventKind::Any).add_path(PathBuf::from(path)))
+        };
+
+        tx.send(event("/a")).unwrap();
+        tx.send(event("/b")).unwrap();
+        tx.send(event("/a")).unwrap();
+        let window = Duration::from_millis(20);
+        assert_eq!(
+            Workspace::debounce(&rx, window).unwrap(),
+            Some(vec![PathBuf::from("/a"), PathBuf::from("/b")])
+        );
+
+        tx.send(event("/c")).unwrap();
+        drop(tx);
+        assert_eq!(
+            Workspace::debounce(&rx, window).unwrap(),
+            Some(vec![PathBuf::from("/c")])
+        );
+        assert_eq!(Workspace::debounce(&rx, window).unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_error() {
+        let missing = Project::read(Path::new("does/not/exist.json")).unwrap_err();
+        assert!(matches!(missing, ReadError::Io(e) if e.kind() == std::io::ErrorKind::NotFound));
+
+        let invalid = Project::read_reader("{\n  \"name\": ,\n}".as_bytes()).unwrap_err();
+        assert!(
+            matches!(&invalid, ReadError::Parse { line: 2, col: 11, message } if message == "expected value"),
+            "{invalid:?}"
+        );
+
+        let invalid =
+            Project::read_reader_with_format("{\n  name: }".as_bytes(), ConfigFormat::Json5)
+                .unwrap_err();
+        assert!(
+            matches!(invalid, ReadError::Parse { line: 2, .. }),
+            "{invalid:?}"
+        );
+    }
+
+    #[test]
+    fn test_affected_util() {
+        let mut projs = projects(vec![
+            project("a", &["a"], &["c"]),
+            project("b", &["b"], &["a"]),
+            project("c", &["c"], &["b"]),
+        ]);
+        let affected = Workspace::affected_util("a", &projs);
+        assert_eq!(affected, ["a", "b", "c"], "A cycle terminates");
+
+        let fan_out = projects(vec![
+            project("core", &["core"], &[]),
+            project("zeta", &[], &["core"]),
+            project("alpha", &[], &["core"]),
+            project("mid", &[], &["core"]),
+        ]);
+        assert_eq!(
+            Workspace::affected_util("core", &fan_out),
+            ["alpha", "mid", "zeta"],
+            "The affected projects are sorted"
+        );
+
+        // A long chain, walked without recursing
+        for i in 0..2_000 {
+            let (tag, next) = (format!("t{i}"), format!("t{}", i + 1));
+            projs.insert(tag.clone(), project(&tag, &[&next], &[&tag]));
+        }
+        let affected = Workspace::affected_util("t0", &projs);
+        assert_eq!(affected.len(), 1_999);
+    }
+
+    #[test]
+    fn test_project_cache() {
+        let path =
+            std::env::temp_dir().join(format!("nx-rs-project-cache-{}.json", std::process::id()));
+        std::fs::write(&path, PROJECT).unwrap();
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+
+        let mut cache = ProjectCache::new();
+        assert_eq!(cache.read(&path).unwrap().name, "app");
+
+        // Same modification time, the file is not read again
+        let renamed = PROJECT.replace(r#""name": "app""#, r#""name": "renamed""#);
+        std::fs::write(&path, &renamed).unwrap();
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_modified(modified).unwrap();
+        assert_eq!(cache.read(&path).unwrap().name, "app");
+
+        file.set_modified(modified + Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(cache.read(&path).unwrap().name, "renamed");
+
+        std::fs::write(&path, PROJECT).unwrap();
+        file.set_modified(modified + Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(cache.read(&path).unwrap().name, "renamed");
+        cache.invalidate();
+        assert_eq!(cache.read(&path).unwrap().name, "app");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_health_check() {
+        let root = std::env::temp_dir().join(format!("nx-rs-health-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+
+        std::fs::write(
+            root.join("workspace.json"),
+            r#"{
+                "name": "ws",
+                "app_version": "0.1.0",
+                "projects": {
+                    "app": "app.json",
+                    "broken": "broken.json",
+                    "missing": "missing.json"
+                },
+                "tags": ["lib"],
+                "maintainers": [],
+                "repository": "",
+                "required_targets": ["build"]
+            }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("app.json"),
+            r#"{
+                "name": "application",
+                "description": "",
+                "owners": [],
+                "affects_tags": ["unknown"],
+                "affected_by_tags": [],
+                "targets": {}
+            }"#,
+        )
+        .unwrap();
+        std::fs::write(root.join("broken.json"), "{").unwrap();
+
+        let report = Workspace::health_check(&root);
+        let errors = report
+            .errors
+            .iter()
+            .map(|e| match e {
+                ValidateProjectsError::NameMismatch(..) => "name",
+                ValidateProjectsError::MissingTargets(..) => "targets",
+                ValidateProjectsError::UnknownTags(..) => "tags",
+                ValidateProjectsError::ProjectSerialization(..) => "serialization",
+                ValidateProjectsError::MissingFile(..) => "file",
+                _ => "other",
+            })
+            .collect::<Vec<&str>>();
+
+        assert_eq!(
+            errors,
+            vec!["name", "targets", "tags", "serialization", "file"],
+            "Every issue should be reported"
+        );
+        assert!(!report.is_healthy());
+        assert_eq!(report.counts().get(&Severity::Error), Some(&5));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_default_shell() {
+        let target = |shell: &str| Target {
+            command: vec!["cargo".to_string(), "build".to_string()],
+            shell: serde_json::from_str(shell).unwrap(),
+            ..Default::default()
+        };
+        let mut app = project("app", &[], &[]);
+        app.targets.insert("build".to_string(), target("null"));
+        let mut lib = project("lib", &[], &[]);
+        lib.targets.insert("build".to_string(), target("false"));
+        let mut docs = project("docs", &[], &[]);
+        docs.targets
+            .insert("build".to_string(), target(r#"["zsh", ["-c"]]"#));
+        let projs = projects(vec![app, lib, docs]);
+
+        let shell = ("bash".to_string(), vec!["-lc".to_string()]);
+        let options = TaskOptions {
+            default_shell: Some(&shell),
+            ..options("debug")
+        };
+        let mut graph = Workspace::pipeline_builder(&["build".to_string()], &projs, options, false)
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut actions = HashMap::new();
+        while let Some(task) = graph.next() {
+            let task = task.unwrap();
+            actions.insert(task.id().clone(), task.action().clone());
+            graph.done(task.id());
+        }
+
+        let words = |words: &[&str]| words.iter().map(|w| w.to_string()).collect();
+        assert_eq!(
+            actions["app:build"],
+            Action::shell(words(&["bash", "-lc", "cargo build"]))
+        );
+        assert_eq!(
+            actions["lib:build"],
+            Action::shell(words(&["cargo", "build"]))
+        );
+        assert_eq!(
+            actions["docs:build"],
+            Action::shell(words(&["zsh", "-c", "cargo build"]))
+        );
+    }
+
+    #[test]
+    fn test_default_shell_quoting() {
+        let target = |command: &[&str]| Target {
+            command: command.iter().map(|w| w.to_string()).collect(),
+            ..Default::default()
+        };
+        let mut app = project("app", &[], &[]);
+        app.targets.insert(
+            "build".to_string(),
+            target(&["echo", "a b", "it's", "$HOME", ""]),
+        );
+        let mut lib = project("lib", &[], &[]);
+        lib.targets
+            .insert("build".to_string(), target(&["cargo build && cargo test"]));
+        let projs = projects(vec![app, lib]);
+
+        let shell = ("sh".to_string(), vec!["-c".to_string()]);
+        let options = TaskOptions {
+            default_shell: Some(&shell),
+            ..options("debug")
+        };
+        let mut graph = Workspace::pipeline_builder(&["build".to_string()], &projs, options, false)
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut scripts = HashMap::new();
+        while let Some(task) = graph.next() {
+            let task = task.unwrap();
+            scripts.insert(
+                task.id().clone(),
+                task.action().cmd().unwrap().last().cloned(),
+            );
+            graph.done(task.id());
+        }
+
+        assert_eq!(
+            scripts["app:build"].as_deref(),
+            Some(r#"echo 'a b' 'it'\''s' '$HOME' ''"#)
+        );
+        assert_eq!(
+            scripts["lib:build"].as_deref(),
+            Some("cargo build && cargo test")
+        );
+    }
+
+    #[test]
+    fn test_target_defaults() {
+        let json = r#"{
+                "name": "ws",
+                "app_version": "0.1.0",
+                "projects": {
+                    "app": {
+                        "name": "app",
+                        "description": "",
+                        "owners": [],
+                        "affects_tags": [],
+                        "affected_by_tags": [],
+                        "targets": {
+                            "test": { "command": ["cargo", "nextest", "run"] }
+                        }
+                    },
+                    "lib": {
+                        "name": "lib",
+                        "description": "",
+                        "owners": [],
+                        "affects_tags": [],
+                        "affected_by_tags": [],
+                        "targets": {}
+                    }
+                },
+                "tags": ["slow"],
+                "maintainers": [],
+                "repository": "",
+                "required_targets": ["test"],
+                "target_defaults": {
+                    "test": { "command": ["cargo", "test"], "tags": ["slow"] }
+                }
+            }"#;
+        let ws = Workspace::read_reader(json.as_bytes()).unwrap();
+
+        let mut graph = ws.build_task_graph("test", None).unwrap();
+        let mut tasks = HashMap::new();
+        while let Some(task) = graph.next() {
+            let task = task.unwrap();
+            graph.done(task.id());
+            tasks.insert(task.id().clone(), task);
+        }
+
+        let words = |words: &[&str]| words.iter().map(|w| w.to_string()).collect();
+        assert_eq!(tasks.len(), 2, "`lib` gets the target from the defaults");
+        assert_eq!(
+            tasks["lib:test"].action(),
+            &Action::shell(words(&["cargo", "test"]))
+        );
+        assert_eq!(
+            tasks["app:test"].action(),
+            &Action::shell(words(&["cargo", "nextest", "run"]))
+        );
+        assert_eq!(tasks["app:test"].tags(), &["slow".to_string()]);
+
+        // The validations see the default targets too
+        let errors = ws.validate_project("lib");
+        assert!(errors.is_empty(), "{errors:?}");
+        let root = std::env::temp_dir().join(format!("nx-rs-defaults-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("workspace.json"), json).unwrap();
+        let report = Workspace::health_check(&root);
+        std::fs::remove_dir_all(&root).unwrap();
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+    }
+
+    #[test]
+    fn test_validate_project() {
+        let ws = Workspace::read_reader(
+            r#"{
+                "name": "ws",
+                "app_version": "0.1.0",
+                "projects": {
+                    "app": {
+                        "name": "application",
+                        "description": "",
+                        "owners": [],
+                        "affects_tags": ["unknown"],
+                        "affected_by_tags": [],
+                        "targets": {}
+                    },
+                    "broken": "broken/project.json",
+                    "lib": {
+                        "name": "lib",
+                        "description": "",
+                        "owners": [],
+                        "affects_tags": [],
+                        "affected_by_tags": [],
+                        "targets": {}
+                    }
+                },
+                "tags": [],
+                "maintainers": [],
+                "repository": "",
+                "required_targets": ["build", "test"],
+                "target_defaults": { "test": {} }
+            }"#
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let errors = ws.validate_project("app");
+        assert_eq!(errors.len(), 3, "{errors:?}");
+        assert!(matches!(
+            &errors[0],
+            ValidateProjectsError::NameMismatch(name, file_name)
+                if name == "app" && file_name == "application"
+        ));
+        // `test` comes from the target defaults
+        assert!(matches!(
+            &errors[1],
+            ValidateProjectsError::MissingTargets(name, target) if name == "app" && target == "build"
+        ));
+        assert!(matches!(
+            &errors[2],
+            ValidateProjectsError::UnknownTags(name, tags) if name == "app" && tags == &["unknown"]
+        ));
+
+        assert!(matches!(
+            ws.validate_project("broken")[..],
+            [ValidateProjectsError::ProjectSerialization(..)]
+        ));
+        assert!(matches!(
+            &ws.validate_project("docs")[..],
+            [ValidateProjectsError::UnknownProject(name)] if name == "docs"
+        ));
+    }
+
+    #[test]
+    fn test_no_targets() {
+        let ws = |check: bool| -> Workspace {
+            let json = format!(
+                r#"{{
+                    "name": "ws",
+                    "app_version": "0.1.0",
+                    "projects": {{
+                        "app": {{
+                            "name": "app",
+                            "description": "",
+                            "owners": [],
+                            "affects_tags": [],
+                            "affected_by_tags": [],
+                            "targets": {{}}
+                        }}
+                    }},
+                    "tags": [],
+                    "maintainers": [],
+                    "repository": "",
+                    "check_empty_projects": {check}
+                }}"#
+            );
+            Workspace::read_reader(json.as_bytes()).unwrap()
+        };
+
+        assert!(ws(false).validate_project("app").is_empty());
+        let errors = ws(true).validate_project("app");
+        assert!(matches!(
+            &errors[..],
+            [ValidateProjectsError::NoTargets(name)] if name == "app"
+        ));
+        assert_eq!(errors[0].severity(), Severity::Warning);
+    }
+
+    #[test]
+    fn test_config_hash() {
+        let root = std::env::temp_dir().join(format!("nx-rs-config-hash-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("app")).unwrap();
+        let write = |path: &str, contents: &str| std::fs::write(root.join(path), contents).unwrap();
+
+        write("workspace.json", WORKSPACE);
+        write("app/project.json", PROJECT);
+        let hash = Workspace::config_hash(&root).unwrap();
+        assert_eq!(hash.len(), 64);
+        assert_eq!(Workspace::config_hash(&root).unwrap(), hash);
+
+        // Checked out with Windows line endings
+        write("app/project.json", &PROJECT.replace('\n', "\r\n"));
+        assert_eq!(Workspace::config_hash(&root).unwrap(), hash);
+
+        write("app/project.json", &PROJECT.replace("An app", "The app"));
+        let changed = Workspace::config_hash(&root).unwrap();
+        assert_ne!(changed, hash);
+
+        // The raw bytes are hashed, a stray `\r` or invalid UTF-8 counts
+        write("app/.env", "LEVEL=app\n");
+        let with_env = Workspace::config_hash(&root).unwrap();
+        assert_ne!(with_env, changed);
+        write("app/.env", "LEVEL=app\r\n");
+        assert_eq!(Workspace::config_hash(&root).unwrap(), with_env);
+        write("app/.env", "LEVEL=app\r");
+        assert_ne!(Workspace::config_hash(&root).unwrap(), with_env);
+        std::fs::write(root.join("app/.env"), b"LEVEL=\xff\n").unwrap();
+        let invalid = Workspace::config_hash(&root).unwrap();
+        std::fs::write(root.join("app/.env"), b"LEVEL=\xfe\n").unwrap();
+        assert_ne!(Workspace::config_hash(&root).unwrap(), invalid);
+
+        std::fs::remove_file(root.join("app/project.json")).unwrap();
+        assert!(Workspace::config_hash(&root).is_err());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_project_env_file() {
+        let root = std::env::temp_dir().join(format!("nx-rs-project-env-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for dir in ["app", "lib"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+            std::fs::write(
+                root.join(dir).join("project.json"),
+                PROJECT.replace("\"app\"", &format!("\"{dir}\"")),
+            )
+            .unwrap();
+        }
+        std::fs::write(root.join("app/.env"), "LEVEL=project\nPROJECT=app\n").unwrap();
+        let ws = format!(
+            r#"{{
+                "name": "ws",
+                "app_version": "0.1.0",
+                "projects": {{
+                    "app": "{}",
+                    "lib": "{}"
+                }},
+                "tags": [],
+                "maintainers": [],
+                "repository": "",
+                "env": {{ "LEVEL": "workspace", "WORKSPACE": "ws" }}
+            }}"#,
+            root.join("app/project.json").display(),
+            root.join("lib/project.json").display()
+        );
+        let ws = Workspace::read_reader(ws.as_bytes()).unwrap();
+
+        let mut graph = ws.build_task_graph("build", None).unwrap();
+        let mut tasks = vec![];
+        while let Some(task) = graph.next() {
+            let task = task.unwrap();
+            graph.done(task.id());
+            tasks.push(task);
+        }
+        tasks.sort_by(|a, b| a.id().cmp(b.id()));
+        let env = ws.run_env(HashMap::new());
+        let var = |task: &Task, key: &str| env.resolve(task).unwrap().get(key).cloned();
+
+        let (app, lib) = (&tasks[0], &tasks[1]);
+        assert_eq!(app.id(), "app:build");
+        assert_eq!(var(app, "LEVEL").as_deref(), Some("project"));
+        assert_eq!(var(app, "PROJECT").as_deref(), Some("app"));
+        assert_eq!(var(app, "WORKSPACE").as_deref(), Some("ws"));
+        // `lib` has no `.env`
+        assert_eq!(var(lib, "LEVEL").as_deref(), Some("workspace"));
+        assert_eq!(var(lib, "PROJECT"), None);
+
+        // The task's own variables win
+        let app = app
+            .clone()
+            .with_env(HashMap::from([("LEVEL".to_string(), "task".to_string())]));
+        assert_eq!(var(&app, "LEVEL").as_deref(), Some("task"));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_depends_on() {
+        let target = |depends_on: &[&str]| Target {
+            depends_on: depends_on.iter().map(|d| d.to_string()).collect(),
+            ..Default::default()
+        };
+        let mut app = project("app", &[], &["lib"]);
+        app.targets
+            .insert("test".to_string(), target(&["codegen", "^build"]));
+        app.targets.insert("codegen".to_string(), target(&[]));
+        app.targets.insert("build".to_string(), target(&[]));
+        let mut lib = project("lib", &["lib"], &[]);
+        lib.targets.insert("build".to_string(), target(&[]));
+        let projs = projects(vec![app, lib]);
+
+        let graph =
+            Workspace::pipeline_builder(&["test".to_string()], &projs, options("debug"), false)
+                .unwrap()
+                .build()
+                .unwrap();
+        let mut ids = graph
+            .statuses()
+            .keys()
+            .map(|id| id.to_string())
+            .collect::<Vec<String>>();
+        ids.sort();
+        assert_eq!(ids, vec!["app:codegen", "app:test", "lib:build"]);
+        let mut deps = graph.dependencies(&"app:test".to_string());
+        deps.sort();
+        assert_eq!(deps, vec!["app:codegen", "lib:build"]);
+
+        let mut broken = projs.clone();
+        broken
+            .get_mut("app")
+            .unwrap()
+            .targets
+            .insert("test".to_string(), target(&["lint"]));
+        let Err(err) =
+            Workspace::pipeline_builder(&["test".to_string()], &broken, options("debug"), false)
+        else {
+            panic!("an unknown target should fail the build");
+        };
+        assert_eq!(
+            err.to_string(),
+            "`app:test` depends on unknown target `lint`"
+        );
+    }
+
+    #[test]
+    fn test_build_task_graph_for() {
+        let json = |affected_by: &str| {
+            format!(
+                r#"{{
+                    "name": "ws",
+                    "app_version": "0.1.0",
+                    "projects": {{
+                        "app": {{
+                            "name": "app",
+                            "description": "",
+                            "owners": [],
+                            "affects_tags": [],
+                            "affected_by_tags": [{affected_by}],
+                            "targets": {{
+                                "test": {{ "depends_on": ["build"] }},
+                                "build": {{}}
+                            }}
+                        }},
+                        "broken": "nx-rs-missing/project.json"
+                    }},
+                    "tags": ["lib"],
+                    "maintainers": [],
+                    "repository": "",
+                    "required_targets": []
+                }}"#
+            )
+        };
+        let ws = Workspace::read_reader(json("").as_bytes()).unwrap();
+        assert!(
+            ws.build_task_graph("test", None).is_err(),
+            "every project is read"
+        );
+
+        let graph = ws
+            .build_task_graph_for(&["app:test".to_string()], None)
+            .unwrap();
+        let mut ids = graph.statuses().keys().cloned().collect::<Vec<TaskID>>();
+        ids.sort();
+        assert_eq!(ids, vec!["app:build", "app:test"]);
+
+        let Err(err) = ws.build_task_graph_for(&["app:lint".to_string()], None) else {
+            panic!("an unknown target should fail the build");
+        };
+        assert_eq!(err.to_string(), "project `app` has no target `lint`");
+        assert!(ws
+            .build_task_graph_for(&["web:test".to_string()], None)
+            .is_err());
+
+        // The affecting projects are found with the tags of every project
+        let ws = Workspace::read_reader(json(r#""lib""#).as_bytes()).unwrap();
+        assert!(ws
+            .build_task_graph_for(&["app:test".to_string()], None)
+            .is_err());
+    }
+
+    #[test]
+    fn test_profile() {
+        let mut app = project("app", &[], &[]);
+        app.targets.insert(
+            "build".to_string(),
+            Target {
+                command: vec!["cargo".to_string(), "--profile={profile}".to_string()],
+                ..Default::default()
+            },
+        );
+        let projs = projects(vec![app]);
+        let root = Path::new(".");
+
+        let mut debug =
+            Workspace::pipeline_builder(&["build".to_string()], &projs, options("debug"), false)
+                .unwrap()
+                .build()
+                .unwrap();
+        let mut release =
+            Workspace::pipeline_builder(&["build".to_string()], &projs, options("release"), false)
+                .unwrap()
+                .build()
+                .unwrap();
+        let debug = debug.next().unwrap().unwrap();
+        let release = release.next().unwrap().unwrap();
+
+        assert_eq!(
+            debug.action(),
+            &Action::shell(vec!["cargo".to_string(), "--profile=debug".to_string()])
+        );
+        assert_eq!(
+            release.action(),
+            &Action::shell(vec!["cargo".to_string(), "--profile=release".to_string()])
+        );
+        assert_ne!(
+            debug.cache_key(root).unwrap(),
+            release.cache_key(root).unwrap()
+        );
+
+        let ws = Workspace::read_reader(WORKSPACE.as_bytes()).unwrap();
+        assert_eq!(ws.default_profile, "debug");
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let targets = vec!["lint".to_string(), "test".to_string()];
+        let projs = projects(vec![
+            with_target(with_target(project("lib", &["lib"], &[]), "lint"), "test"),
+            with_target(project("app", &[], &["lib"]), "test"),
+        ]);
+
+        let graph = Workspace::pipeline_builder(&targets, &projs, options("debug"), false)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(graph.remaining(), 3);
+        assert!(graph.dependencies(&"lib:test".to_string()).is_empty());
+
+        let graph = Workspace::pipeline_builder(&targets, &projs, options("debug"), true)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(graph.dependencies(&"lib:test".to_string()), ["lib:lint"]);
+        let mut app_deps = graph.dependencies(&"app:test".to_string());
+        app_deps.sort();
+        assert_eq!(app_deps, vec!["lib:lint", "lib:test"]);
+
+        let ws = Workspace::read_reader(WORKSPACE.as_bytes()).unwrap();
+        assert!(ws.build_pipeline("ci").is_err());
+    }
+
+    #[test]
+    fn test_resolve_outputs() {
+        let target = |outputs: &[&str]| Target {
+            outputs: outputs.iter().map(|o| o.to_string()).collect(),
+            ..Default::default()
+        };
+        let root = Path::new("libs/app");
+
+        assert_eq!(
+            target(&["{projectRoot}/dist/{profile}", "libs/app/./coverage"])
+                .resolve_outputs(root, "release")
+                .unwrap(),
+            vec![
+                PathBuf::from("libs/app/dist/release"),
+                PathBuf::from("libs/app/coverage")
+            ]
+        );
+        assert!(target(&["{projectRoot}/../other/dist"])
+            .resolve_outputs(root, "debug")
+            .is_err());
+        assert!(target(&["dist"]).resolve_outputs(root, "debug").is_err());
+        assert!(target(&["/tmp/dist"])
+            .resolve_outputs(root, "debug")
+            .is_err());
+
+        let mut shared = target(&["{projectRoot}/../../dist"]);
+        shared.allow_outputs_outside_project = true;
+        assert_eq!(
+            shared.resolve_outputs(root, "debug").unwrap(),
+            vec![PathBuf::from("dist")]
+        );
+
+        let mut app = project("app", &[], &[]);
+        app.targets
+            .insert("build".to_string(), target(&["{projectRoot}/dist"]));
+        let mut graph = Workspace::pipeline_builder(
+            &["build".to_string()],
+            &projects(vec![app]),
+            options("debug"),
+            false,
+        )
+        .unwrap()
+        .build()
+        .unwrap();
+        assert_eq!(
+            graph.next().unwrap().unwrap().outputs(),
+            &[PathBuf::from("app/dist")]
+        );
+    }
+
+    #[test]
+    fn test_named_inputs() {
+        let target = |inputs: &[&str]| Target {
+            inputs: inputs.iter().map(|i| i.to_string()).collect(),
+            ..Default::default()
+        };
+        let globs = |globs: &[&str]| globs.iter().map(|g| g.to_string()).collect::<Vec<_>>();
+        let named = HashMap::from([
+            (
+                "default".to_string(),
+                globs(&["@production", "{projectRoot}/tests/**"]),
+            ),
+            ("production".to_string(), globs(&["{projectRoot}/src/**"])),
+            ("loop".to_string(), globs(&["@loop"])),
+        ]);
+        let root = Path::new("libs/app");
+
+        assert_eq!(
+            target(&["@default", "Cargo.lock", "@production", "Makefile"])
+                .resolve_inputs(root, &named)
+                .unwrap(),
+            globs(&[
+                "libs/app/src/**",
+                "libs/app/tests/**",
+                "Cargo.lock",
+                "Makefile"
+            ]),
+            "Names without a `@` are globs"
+        );
+        let err = target(&["@prod"]).resolve_inputs(root, &named).unwrap_err();
+        assert_eq!(err.to_string(), "unknown named input `prod`");
+        let err = target(&["@loop"]).resolve_inputs(root, &named).unwrap_err();
+        assert_eq!(err.to_string(), "named input cycle: loop -> loop");
+
+        let mut app = project("app", &[], &[]);
+        app.targets
+            .insert("build".to_string(), target(&["@production"]));
+        let options = TaskOptions {
+            named_inputs: Some(&named),
+            ..options("debug")
+        };
+        let mut graph = Workspace::pipeline_builder(
+            &["build".to_string()],
+            &projects(vec![app]),
+            options,
+            false,
+        )
+        .unwrap()
+        .build()
+        .unwrap();
+        assert_eq!(
+            graph.next().unwrap().unwrap().inputs(),
+            &globs(&["app/src/**"])
+        );
+    }
+
+    #[test]
+    fn test_lockfile() {
+        let versioned = |name: &str, version: &str| {
+            let mut proj = project(name, &[], &[]);
+            proj.version = Some(version.to_string());
+            proj
+        };
+        let locked = Lockfile::from_projects(&projects(vec![
+            versioned("lib", "1.0.0"),
+            versioned("app", "0.3.0"),
+            project("tools", &[], &[]),
+        ]));
+
+        assert_eq!(
+            locked.to_json().unwrap(),
+            r#"{
+  "projects": {
+    "app": "0.3.0",
+    "lib": "1.0.0",
+    "tools": null
+  }
+}
+"#
+        );
+        let read: Lockfile = serde_json::from_str(&locked.to_json().unwrap()).unwrap();
+        assert_eq!(read, locked);
+        assert!(locked.verify(&read).is_ok());
+
+        let bumped = Lockfile::from_projects(&projects(vec![
+            versioned("lib", "1.1.0"),
+            versioned("app", "0.3.0"),
+            project("tools", &[], &[]),
+        ]));
+        let err = locked.verify(&bumped).unwrap_err().to_string();
+        assert!(
+            err.contains("`lib` is locked at 1.0.0 but is 1.1.0"),
+            "{err}"
+        );
+
+        let removed = Lockfile::from_projects(&projects(vec![versioned("app", "0.3.0")]));
+        assert!(locked.verify(&removed).is_err());
+    }
+
+    #[test]
+    fn test_no_required_targets() {
+        let ws = Workspace::read_reader(WORKSPACE.as_bytes()).unwrap();
+        assert!(ws.workspace_errors().is_empty());
+
+        let ws = Workspace::read_reader(
+            r#"{
+                "name": "ws",
+                "app_version": "0.1.0",
+                "projects": {},
+                "tags": [],
+                "maintainers": [],
+                "repository": ""
+            }"#
+            .as_bytes(),
+        )
+        .unwrap();
+        let errors = ws.workspace_errors();
+        assert!(matches!(
+            errors.as_slice(),
+            [ValidateProjectsError::NoRequiredTargets]
+        ));
+        assert_eq!(errors[0].severity(), Severity::Warning);
+    }
+
+    #[test]
+    fn test_required_targets_by_type() {
+        let ws = Workspace::read_reader(
+            WORKSPACE
+                .replace(
+                    r#""required_targets": ["build"]"#,
+                    r#""required_targets": {
+                        "lib": ["build", "test"],
+                        "app": ["build", "serve"],
+                        "default": ["build"]
+                    }"#,
+                )
+                .as_bytes(),
+        )
+        .unwrap();
+
+        let missing = |project_type: Option<&str>| {
+            let mut proj = with_target(project("p", &[], &[]), "build");
+            proj.project_type = project_type.map(str::to_string);
+            let mut missing = ws
+                .project_errors("p", &proj)
+                .into_iter()
+                .filter_map(|e| match e {
+                    ValidateProjectsError::MissingTargets(_, t) => Some(t),
+                    _ => None,
+                })
+                .collect::<Vec<String>>();
+            missing.sort();
+            return missing;
+        };
+        assert_eq!(missing(Some("lib")), ["test"]);
+        assert_eq!(missing(Some("app")), ["serve"]);
+        assert!(
+            missing(Some("tool")).is_empty(),
+            "Unknown types use the default"
+        );
+        assert!(missing(None).is_empty());
+
+        let proj = Project::read_reader(
+            PROJECT
+                .replace(r#""name": "app","#, r#""name": "app", "type": "lib","#)
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(proj.project_type.as_deref(), Some("lib"));
+
+        let plain = Workspace::read_reader(WORKSPACE.as_bytes()).unwrap();
+        assert_eq!(
+            plain.diff(&ws).added_required_targets,
+            [
+                "app:build",
+                "app:serve",
+                "default:build",
+                "lib:build",
+                "lib:test"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_target_tags() {
+        let ws = Workspace::read_reader(WORKSPACE.as_bytes()).unwrap();
+        let mut app = project("app", &[], &[]);
+        app.targets.insert(
+            "build".to_string(),
+            Target {
+                tags: vec!["lib".to_string(), "slow".to_string()],
+                ..Default::default()
+            },
+        );
+
+        let errors = ws.project_errors("app", &app);
+        assert!(matches!(
+            errors.as_slice(),
+            [ValidateProjectsError::UnknownTargetTags(p, t, tags)]
+                if p == "app" && t == "build" && tags == &vec!["slow".to_string()]
+        ));
+
+        let mut graph = Workspace::pipeline_builder(
+            &["build".to_string()],
+            &projects(vec![app]),
+            options("debug"),
+            false,
+        )
+        .unwrap()
+        .build()
+        .unwrap();
+        assert_eq!(graph.tasks_with_tag("slow"), vec!["app:build"]);
+        assert_eq!(graph.next().unwrap().unwrap().tags(), &["lib", "slow"]);
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = Workspace::read_reader(WORKSPACE.as_bytes()).unwrap();
+        assert!(old.diff(&old).is_empty());
+
+        let new = Workspace::read_reader(
+            r#"{
+                "name": "ws",
+                "app_version": "0.1.0",
+                "projects": { "app": "apps/app/project.json", "lib": "lib/project.json" },
+                "tags": ["lib", "ui"],
+                "maintainers": [],
+                "repository": "",
+                "required_targets": ["build", "test"]
+            }"#
+            .as_bytes(),
+        )
+        .unwrap();
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff,
+            WorkspaceDiff {
+                added_projects: vec!["lib".to_string()],
+                removed_projects: vec![],
+                moved_projects: vec![(
+                    "app".to_string(),
+                    "app/project.json".to_string(),
+                    "apps/app/project.json".to_string()
+                )],
+                added_tags: vec!["ui".to_string()],
+                removed_tags: vec![],
+                added_required_targets: vec!["test".to_string()],
+                removed_required_targets: vec![],
+            }
+        );
+        let json = serde_json::to_value(&diff).unwrap();
+        assert_eq!(json["added_projects"][0], "lib");
+
+        let old_app = with_target(project("app", &["ui"], &["lib"]), "build");
+        let new_app = with_target(project("app", &[], &["lib", "core"]), "test");
+        assert!(old_app.diff(&old_app).is_empty());
+        assert_eq!(
+            old_app.diff(&new_app),
+            ProjectDiff {
+                name: "app".to_string(),
+                added_affects_tags: vec![],
+                removed_affects_tags: vec!["ui".to_string()],
+                added_affected_by_tags: vec!["core".to_string()],
+                removed_affected_by_tags: vec![],
+                added_targets: vec!["test".to_string()],
+                removed_targets: vec!["build".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_expand_target() {
+        let projs = projects(vec![with_target(
+            with_target(with_target(project("app", &[], &[]), "lint"), "test"),
+            "build",
+        )]);
+        let alias = |members: &[&str]| members.iter().map(|m| m.to_string()).collect();
+        let mut aliases: HashMap<String, Vec<String>> = HashMap::from([
+            ("check".to_string(), alias(&["lint", "test"])),
+            ("all".to_string(), alias(&["check", "build", "test"])),
+        ]);
+
+        let expand = |target: &str, aliases: &HashMap<String, Vec<String>>| {
+            Workspace::expand_target_util(target, aliases, &projs)
+        };
+        assert_eq!(expand("build", &aliases).unwrap(), vec!["build"]);
+        assert_eq!(expand("check", &aliases).unwrap(), vec!["lint", "test"]);
+        assert_eq!(
+            expand("all", &aliases).unwrap(),
+            vec!["lint", "test", "build"],
+            "Nested aliases are expanded, without duplicates"
+        );
+
+        aliases.insert("loop".to_string(), alias(&["again"]));
+        aliases.insert("again".to_string(), alias(&["loop"]));
+        let err = expand("loop", &aliases).unwrap_err().to_string();
+        assert_eq!(err, "alias cycle: loop -> again -> loop");
+
+        aliases.insert("typo".to_string(), alias(&["tset"]));
+        let err = expand("typo", &aliases).unwrap_err().to_string();
+        assert_eq!(err, "alias `typo` refers to unknown target `tset`");
+    }
+
+    #[test]
+    fn test_affected_since_last_success() {
+        let root = std::env::temp_dir().join(format!("nx-rs-last-success-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let state = root.join("state/last-success.json");
+
+        let mut projs = vec![
+            project("app", &[], &["lib"]),
+            project("lib", &["lib"], &[]),
+            project("docs", &[], &[]),
+            project("core", &[], &[]),
+        ];
+        for proj in &mut projs {
+            proj.root = match proj.name.as_str() {
+                // Nested in `lib`, its files are not `lib`'s
+                "core" => root.join("lib/core"),
+                name => root.join(name),
+            };
+            std::fs::create_dir_all(&proj.root).unwrap();
+            std::fs::write(proj.root.join("main.rs"), &proj.name).unwrap();
+        }
+        let projs = projects(projs);
+
+        // No successful run yet, everything is affected
+        let all = vec!["app", "core", "docs", "lib"];
+        assert_eq!(Workspace::affected_since_util(&state, &projs).unwrap(), all);
+
+        RunState::from_projects(&projs, Path::new(""))
+            .unwrap()
+            .write(&state)
+            .unwrap();
+        assert!(Workspace::affected_since_util(&state, &projs)
+            .unwrap()
+            .is_empty());
+
+        std::fs::write(root.join("lib/core/main.rs"), "changed").unwrap();
+        assert_eq!(
+            Workspace::affected_since_util(&state, &projs).unwrap(),
+            vec!["core"]
+        );
+        std::fs::write(root.join("lib/lib.rs"), "added").unwrap();
+        assert_eq!(
+            Workspace::affected_since_util(&state, &projs).unwrap(),
+            vec!["app", "core", "lib"]
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_run_state_root_projects() {
+        let root = std::env::temp_dir().join(format!("nx-rs-run-state-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("lib")).unwrap();
+        std::fs::write(root.join("main.rs"), "app").unwrap();
+        std::fs::write(root.join("lib/lib.rs"), "lib").unwrap();
+
+        // Both have an empty directory
+        let ws = format!(
+            r#"{{
+                "name": "ws",
+                "app_version": "0.1.0",
+                "projects": {{ "inline": {PROJECT} }},
+                "tags": [],
+                "maintainers": [],
+                "repository": ""
+            }}"#
+        );
+        let ws = Workspace::read_reader(ws.as_bytes()).unwrap();
+        for (i, top) in [
+            ws.projects["inline"].load(&root).unwrap(),
+            Project {
+                root: PathBuf::new(),
+                ..project("app", &[], &[])
+            },
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            assert_eq!(top.root, PathBuf::new());
+            let name = top.name.clone();
+            let projs = projects(vec![top, project("lib", &[], &[])]);
+
+            let state = RunState::from_projects(&projs, &root).unwrap();
+            assert_eq!(state.projects.len(), 2);
+            std::fs::write(root.join("lib/lib.rs"), format!("changed {i}")).unwrap();
+            let lib_changed = RunState::from_projects(&projs, &root).unwrap();
+            assert_eq!(lib_changed.changed_since(&state), vec!["lib"]);
+            std::fs::write(root.join("main.rs"), format!("changed {i}")).unwrap();
+            let app_changed = RunState::from_projects(&projs, &root).unwrap();
+            assert_eq!(app_changed.changed_since(&lib_changed), vec![name]);
+        }
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_inline_projects() {
+        let root = std::env::temp_dir().join(format!("nx-rs-inline-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("lib")).unwrap();
+
+        std::fs::write(
+            root.join("workspace.json"),
+            format!(
+                r#"{{
+                    "name": "ws",
+                    "app_version": "0.1.0",
+                    "projects": {{
+                        "app": {PROJECT},
+                        "lib": "lib/project.json"
+                    }},
+                    "tags": ["lib"],
+                    "maintainers": [],
+                    "repository": "",
+                    "required_targets": ["build"]
+                }}"#
+            ),
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("lib/project.json"),
+            PROJECT.replace(r#""name": "app""#, r#""name": "lib""#),
+        )
+        .unwrap();
+
+        let ws = Workspace::read(&root.join("workspace.json")).unwrap();
+        assert!(matches!(ws.projects["app"], ProjectEntry::Inline(_)));
+        assert!(matches!(ws.projects["lib"], ProjectEntry::Path(_)));
+
+        let app = ws.projects["app"].load(&root).unwrap();
+        let lib = ws.projects["lib"].load(&root).unwrap();
+        assert_eq!(app.name, "app");
+        assert_eq!(app.root, PathBuf::new());
+        assert_eq!(lib.root, PathBuf::from("lib"));
+
+        let report = Workspace::health_check(&root);
+        assert!(report.is_healthy(), "{:?}", report.errors);
+
+        std::fs::remove_dir_all(&root).unwrap();
     }
 }